@@ -1,22 +1,24 @@
 mod ollama;
 mod library;
 
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
-use serde::Serialize;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
 use std::{
-  collections::{HashMap, HashSet},
+  collections::{BTreeSet, HashMap, HashSet, VecDeque},
   io::{BufRead, BufReader},
-  path::PathBuf,
+  path::{Path, PathBuf},
   process::{Child, Command, Stdio},
-  sync::{Arc, Mutex},
-  time::{Duration, Instant},
+  sync::{mpsc, Arc, Mutex},
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tauri::{AppHandle, Manager, State};
+use tauri::{AppHandle, Listener, Manager, State};
 use tauri::Emitter;
 
 const DEFAULT_CHAT_MODEL: &str = "llama3.1:8b";
 const DEFAULT_FAST_CHAT_MODEL: &str = "llama3.2:3b";
 const DEFAULT_EMBED_MODEL: &str = "qwen3-embedding";
+const RECENT_ACTIVITY_CAP: usize = 300;
 
 fn split_model_tag(name: &str) -> (&str, Option<&str>) {
   match name.split_once(':') {
@@ -48,10 +50,10 @@ fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
   }
 }
 
-fn run_index_task(task: impl FnOnce() -> Result<(), String>) -> Result<(), String> {
+fn run_index_task<E: From<String>>(task: impl FnOnce() -> Result<(), E>) -> Result<(), E> {
   match std::panic::catch_unwind(std::panic::AssertUnwindSafe(task)) {
     Ok(result) => result,
-    Err(panic) => Err(format!("index task panicked: {}", panic_message(panic))),
+    Err(panic) => Err(format!("index task panicked: {}", panic_message(panic)).into()),
   }
 }
 
@@ -94,6 +96,26 @@ struct ReindexProgress {
   files: Vec<String>,
 }
 
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ActivityEntry {
+  file: String,
+  status: String,
+  timestamp: i64,
+}
+
+#[derive(Deserialize)]
+struct ProgressPayload {
+  file: String,
+  status: String,
+}
+
+#[derive(Deserialize)]
+struct ReindexPayload {
+  status: String,
+  files: Vec<String>,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct OllamaStartResult {
@@ -114,11 +136,14 @@ struct AppState {
 struct AppStateInner {
   watcher: Mutex<Option<RecommendedWatcher>>,
   watched: Mutex<HashSet<PathBuf>>,
-  last_event: Mutex<HashMap<PathBuf, Instant>>,
+  last_event: Mutex<HashMap<(PathBuf, WatchChange), Instant>>,
   last_embed_model: Mutex<String>,
   last_index_settings: Mutex<library::IndexSettings>,
   target_files: Mutex<HashSet<PathBuf>>,
-  folder_roots: Mutex<Vec<(PathBuf, bool)>>,
+  folder_roots: Mutex<Vec<(PathBuf, bool, library::TargetExclusions)>>,
+  reindex_pending: Mutex<BTreeSet<String>>,
+  reindex_wake: Mutex<Option<mpsc::SyncSender<()>>>,
+  recent_activity: Mutex<VecDeque<ActivityEntry>>,
   ollama_child: Mutex<Option<Child>>,
 }
 
@@ -127,10 +152,28 @@ impl Default for AppState {
     let settings = library::IndexSettings {
       chunk_size: 1400,
       chunk_overlap: 250,
+      chunk_overlap_ratio: None,
       ocr_enabled: true,
       ocr_lang: "pol+eng".into(),
       ocr_min_chars: 120,
       ocr_dpi: 300,
+      ocr_auto_lang: false,
+      ocr_reflow: true,
+      ocr_max_concurrency: 0,
+      embed_truncate: true,
+      verify_text_hash: true,
+      min_chunk_chars: 30,
+      context_model: None,
+      embed_doc_prefix: None,
+      fts_tokenizer: library::FtsTokenizer::Unicode61,
+      chunk_strategy: library::ChunkStrategy::Fixed,
+      max_concurrency: 0,
+      max_file_bytes: None,
+      pptx_notes: false,
+      code_extensions: library::default_code_extensions(),
+      enabled_kinds: None,
+      embed_options: None,
+      ollama_url: None,
     };
     Self {
       inner: Arc::new(AppStateInner {
@@ -141,6 +184,9 @@ impl Default for AppState {
         last_index_settings: Mutex::new(settings),
         target_files: Mutex::new(HashSet::new()),
         folder_roots: Mutex::new(Vec::new()),
+        reindex_pending: Mutex::new(BTreeSet::new()),
+        reindex_wake: Mutex::new(None),
+        recent_activity: Mutex::new(VecDeque::new()),
         ollama_child: Mutex::new(None),
       }),
     }
@@ -156,6 +202,44 @@ fn update_last_settings(state: &State<AppState>, embed_model: &str, settings: &l
   }
 }
 
+fn push_activity(inner: &AppStateInner, file: String, status: String) {
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or(0);
+  let mut log = inner.recent_activity.lock().unwrap();
+  log.push_back(ActivityEntry { file, status, timestamp });
+  while log.len() > RECENT_ACTIVITY_CAP {
+    log.pop_front();
+  }
+}
+
+fn recent_entries(inner: &AppStateInner, limit: usize) -> Vec<ActivityEntry> {
+  let log = inner.recent_activity.lock().unwrap();
+  log.iter().rev().take(limit).cloned().collect()
+}
+
+/// Mirrors indexing events into `recent_activity` so views mounted later can still show them.
+fn record_activity(app: &AppHandle, inner: Arc<AppStateInner>) {
+  let progress_inner = inner.clone();
+  app.listen("index_progress", move |event| {
+    if let Ok(p) = serde_json::from_str::<ProgressPayload>(event.payload()) {
+      if !p.file.is_empty() && p.status != "loading_model" && p.status != "ready" {
+        push_activity(&progress_inner, p.file, p.status);
+      }
+    }
+  });
+  app.listen("reindex_progress", move |event| {
+    if let Ok(p) = serde_json::from_str::<ReindexPayload>(event.payload()) {
+      if p.status == "queued" {
+        for file in p.files {
+          push_activity(&inner, file, "queued".into());
+        }
+      }
+    }
+  });
+}
+
 fn emit_setup_progress(app: &AppHandle, stage: &str, message: impl Into<String>) {
   let _ = app.emit(
     "setup_progress",
@@ -170,6 +254,8 @@ fn emit_setup_error(app: &AppHandle, message: impl Into<String>) {
   let _ = app.emit("setup_error", message.into());
 }
 
+/// Every `Ollama::new()` reads OLLAMA_BASE_URL, so setting it reroutes indexing and chat too.
+/// Commands taking `ollama_url` only override the host for that call.
 #[tauri::command]
 fn set_ollama_host(host: String) -> Result<(), String> {
   let trimmed = host.trim();
@@ -196,7 +282,7 @@ fn run_ollama_pull(app: &AppHandle, model: &str) -> Result<(), String> {
   let app_out = app.clone();
   let model_out = model.to_string();
   let out_handle = std::thread::spawn(move || {
-    for line in BufReader::new(stdout).lines().flatten() {
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
       let _ = app_out.emit(
         "model_pull_progress",
         ModelPullProgress {
@@ -210,7 +296,7 @@ fn run_ollama_pull(app: &AppHandle, model: &str) -> Result<(), String> {
   let app_err = app.clone();
   let model_err = model.to_string();
   let err_handle = std::thread::spawn(move || {
-    for line in BufReader::new(stderr).lines().flatten() {
+    for line in BufReader::new(stderr).lines().map_while(Result::ok) {
       let _ = app_err.emit(
         "model_pull_progress",
         ModelPullProgress {
@@ -265,25 +351,80 @@ fn is_ollama_managed(state: &State<AppState>) -> bool {
   }
 }
 
-fn should_process(inner: &AppStateInner, path: &PathBuf) -> bool {
+/// What a watcher event means for one path. Debouncing is keyed per change so a
+/// delete (or the old half of a rename) right after a save isn't swallowed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum WatchChange {
+  Reindex,
+  Remove,
+}
+
+fn should_process(inner: &AppStateInner, path: &Path, change: WatchChange) -> bool {
   let mut map = inner.last_event.lock().unwrap();
   let now = Instant::now();
-  if let Some(prev) = map.get(path) {
+  let key = (path.to_path_buf(), change);
+  if let Some(prev) = map.get(&key) {
     if now.duration_since(*prev) < Duration::from_secs(2) {
       return false;
     }
   }
-  map.insert(path.clone(), now);
+  map.insert(key, now);
   true
 }
 
+/// Pairs each event path with what should happen to it. Renames reported as one event
+/// carry `[from, to]`; paths that no longer exist are treated as removed.
+fn classify_event(kind: &EventKind, paths: &[PathBuf]) -> Vec<(PathBuf, WatchChange)> {
+  match kind {
+    EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+      paths.iter().map(|p| (p.clone(), WatchChange::Remove)).collect()
+    }
+    EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => vec![
+      (paths[0].clone(), WatchChange::Remove),
+      (paths[1].clone(), WatchChange::Reindex),
+    ],
+    _ => paths
+      .iter()
+      .map(|p| {
+        let change = if p.exists() { WatchChange::Reindex } else { WatchChange::Remove };
+        (p.clone(), change)
+      })
+      .collect(),
+  }
+}
+
+fn forget_removed(app: &AppHandle, inner: &AppStateInner, paths: Vec<String>) {
+  {
+    let mut pending = inner.reindex_pending.lock().unwrap();
+    for path in &paths {
+      pending.remove(path);
+    }
+  }
+  for path in paths {
+    // The file is already gone from disk, so there is nothing left to confirm.
+    match library::forget_files(app, vec![path.clone()], true) {
+      Ok(0) => {}
+      Ok(_) => {
+        push_activity(inner, path.clone(), "removed".into());
+        let _ = app.emit("index_removed", path);
+      }
+      Err(e) => {
+        let _ = app.emit("index_error", library::IndexError::from_anyhow(&e, Some(&path)));
+      }
+    }
+  }
+}
+
 fn is_in_targets(inner: &AppStateInner, path: &PathBuf) -> bool {
   let target_files = inner.target_files.lock().unwrap();
   if target_files.contains(path) {
     return true;
   }
   let folder_roots = inner.folder_roots.lock().unwrap();
-  for (root, recursive) in folder_roots.iter() {
+  for (root, recursive, excluded) in folder_roots.iter() {
+    if excluded.is_excluded(path) {
+      continue;
+    }
     if *recursive {
       if path.starts_with(root) {
         return true;
@@ -295,6 +436,72 @@ fn is_in_targets(inner: &AppStateInner, path: &PathBuf) -> bool {
   false
 }
 
+/// Adds files to the pending reindex set; returns the ones that were not already queued.
+fn enqueue_pending(inner: &AppStateInner, files: Vec<String>) -> Vec<String> {
+  let mut pending = inner.reindex_pending.lock().unwrap();
+  files.into_iter().filter(|f| pending.insert(f.clone())).collect()
+}
+
+fn take_pending(inner: &AppStateInner) -> Vec<String> {
+  std::mem::take(&mut *inner.reindex_pending.lock().unwrap()).into_iter().collect()
+}
+
+/// Watcher events are funnelled into a single worker so bulk changes (e.g. unzipping into a watched
+/// folder) coalesce into one index run at a time instead of many tasks racing for Ollama and the DB.
+fn queue_reindex(app: &AppHandle, inner: &Arc<AppStateInner>, files: Vec<String>) {
+  let added = enqueue_pending(inner, files);
+  if added.is_empty() {
+    return;
+  }
+  let _ = app.emit(
+    "reindex_progress",
+    ReindexProgress {
+      status: "queued".into(),
+      files: added,
+    },
+  );
+
+  let mut wake = inner.reindex_wake.lock().unwrap();
+  let tx = wake.get_or_insert_with(|| {
+    // Capacity 1: a pending wake-up already covers everything added to the set since.
+    let (tx, rx) = mpsc::sync_channel(1);
+    let app = app.clone();
+    let inner = inner.clone();
+    std::thread::spawn(move || run_reindex_worker(app, inner, rx));
+    tx
+  });
+  let _ = tx.try_send(());
+}
+
+fn run_reindex_worker(app: AppHandle, inner: Arc<AppStateInner>, rx: mpsc::Receiver<()>) {
+  while rx.recv().is_ok() {
+    let files = take_pending(&inner);
+    if files.is_empty() { continue; }
+    let embed_model = inner.last_embed_model.lock().unwrap().clone();
+    if embed_model.is_empty() { continue; }
+    let settings = inner.last_index_settings.lock().unwrap().clone();
+
+    app.state::<library::IndexCancel>().reset();
+    let res = run_index_task(|| {
+      library::index_files(&app, files.clone(), embed_model, settings).map_err(library::IndexError::from)
+    });
+    let status = match res {
+      Ok(()) => "done",
+      Err(e) => {
+        let _ = app.emit("index_error", e);
+        "error"
+      }
+    };
+    let _ = app.emit(
+      "reindex_progress",
+      ReindexProgress {
+        status: status.into(),
+        files,
+      },
+    );
+  }
+}
+
 fn update_watcher(app: &AppHandle, state: &State<AppState>, targets: &[library::IndexTarget]) -> Result<(), String> {
   let inner = state.inner.clone();
   let app_handle = app.clone();
@@ -304,73 +511,35 @@ fn update_watcher(app: &AppHandle, state: &State<AppState>, targets: &[library::
     match res {
       Ok(event) => {
         let mut files = Vec::new();
-        for path in event.paths {
-          if !path.is_file() { continue; }
-          if !library::is_supported_document(&path) { continue; }
+        let mut removed = Vec::new();
+        let kinds = inner_for_watcher
+          .last_index_settings
+          .lock()
+          .map(|s| s.kind_filter())
+          .unwrap_or_default();
+        // Removals ignore enabled_kinds so files indexed before a kind was disabled are still forgotten.
+        let any_kind = kinds.all_kinds();
+        for (path, change) in classify_event(&event.kind, &event.paths) {
+          if change == WatchChange::Reindex && !path.is_file() { continue; }
+          let filter = if change == WatchChange::Remove { &any_kind } else { &kinds };
+          if !library::is_supported_document(&path, filter) { continue; }
           if !is_in_targets(&inner_for_watcher, &path) { continue; }
-          if !should_process(&inner_for_watcher, &path) { continue; }
-          files.push(path.to_string_lossy().to_string());
+          if !should_process(&inner_for_watcher, &path, change) { continue; }
+          let path = path.to_string_lossy().to_string();
+          match change {
+            WatchChange::Reindex => files.push(path),
+            WatchChange::Remove => removed.push(path),
+          }
         }
 
+        if !removed.is_empty() {
+          forget_removed(&app_handle, &inner_for_watcher, removed);
+        }
         if files.is_empty() { return; }
-        let embed_model = inner_for_watcher.last_embed_model.lock().unwrap().clone();
-        if embed_model.is_empty() { return; }
-        let settings = inner_for_watcher.last_index_settings.lock().unwrap().clone();
-        let app_for_index = app_handle.clone();
-        let app_for_error = app_handle.clone();
-        let files_for_done = files.clone();
-
-        let _ = app_handle.emit(
-          "reindex_progress",
-          ReindexProgress {
-            status: "queued".into(),
-            files: files.clone(),
-          },
-        );
-
-        tauri::async_runtime::spawn(async move {
-          let app_for_error_clone = app_for_error.clone();
-          let res = tauri::async_runtime::spawn_blocking(move || {
-            run_index_task(|| {
-            library::index_files(&app_for_index, files, embed_model, settings)
-                .map_err(|e| format!("{:#}", e))
-            })
-          }).await;
-          match res {
-            Ok(Ok(())) => {
-              let _ = app_for_error_clone.emit(
-                "reindex_progress",
-                ReindexProgress {
-                  status: "done".into(),
-                  files: files_for_done,
-                },
-              );
-            }
-            Ok(Err(e)) => {
-              let _ = app_for_error_clone.emit("index_error", e.to_string());
-              let _ = app_for_error_clone.emit(
-                "reindex_progress",
-                ReindexProgress {
-                  status: "error".into(),
-                  files: files_for_done,
-                },
-              );
-            }
-            Err(e) => {
-              let _ = app_for_error_clone.emit("index_error", format!("reindex task join error: {e}"));
-              let _ = app_for_error_clone.emit(
-                "reindex_progress",
-                ReindexProgress {
-                  status: "error".into(),
-                  files: files_for_done,
-                },
-              );
-            }
-          }
-        });
+        queue_reindex(&app_handle, &inner_for_watcher, files);
       }
       Err(e) => {
-        let _ = app_handle.emit("index_error", format!("watcher error: {e}"));
+        let _ = app_handle.emit("index_error", library::IndexError::from(format!("watcher error: {e}")));
         let _ = app_handle.emit(
           "watcher_status",
           WatcherStatus {
@@ -405,7 +574,7 @@ fn update_watcher(app: &AppHandle, state: &State<AppState>, targets: &[library::
         if watcher.watch(&path, if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive }).is_ok() {
           watched.insert(path.clone());
         }
-        folder_roots.push((path, recursive));
+        folder_roots.push((path, recursive, target.exclusions()));
       }
     }
   }
@@ -448,6 +617,14 @@ async fn setup_status(state: State<'_, AppState>) -> Result<SetupStatus, String>
   }
 }
 
+#[tauri::command]
+async fn check_environment(app: AppHandle) -> Result<library::EnvironmentCheck, String> {
+  let version = ollama::version_with_timeout(Duration::from_secs(2)).await;
+  tauri::async_runtime::spawn_blocking(move || library::check_environment(&app, version))
+    .await
+    .map_err(|e| format!("environment check task join error: {e}"))
+}
+
 #[tauri::command]
 fn run_setup(app: AppHandle) -> Result<(), String> {
   let app_handle = app.clone();
@@ -510,15 +687,18 @@ fn start_index(
   state: State<AppState>,
   targets: Vec<library::IndexTarget>,
   embed_model: String,
-  settings: library::IndexSettings,
+  mut settings: library::IndexSettings,
+  ollama_url: Option<String>,
 ) -> Result<(), String> {
   update_last_settings(&state, &embed_model, &settings);
+  // After saving, so the watcher keeps using the global host.
+  settings.ollama_url = ollama_url;
+  app.state::<library::IndexCancel>().reset();
   let app_for_error = app.clone();
   tauri::async_runtime::spawn(async move {
     let res = tauri::async_runtime::spawn_blocking(move || {
       run_index_task(|| {
-        library::index_library(app, targets, embed_model, settings)
-          .map_err(|e| format!("{:#}", e))
+        library::index_library(app, targets, embed_model, settings).map_err(library::IndexError::from)
       })
     }).await;
 
@@ -528,7 +708,7 @@ fn start_index(
         let _ = app_for_error.emit("index_error", e);
       }
       Err(e) => {
-        let _ = app_for_error.emit("index_error", format!("index task join error: {e}"));
+        let _ = app_for_error.emit("index_error", library::IndexError::from(format!("index task join error: {e}")));
       }
     }
   });
@@ -536,16 +716,52 @@ fn start_index(
 }
 
 #[tauri::command]
+fn cancel_index(cancel: State<library::IndexCancel>) {
+  cancel.request();
+}
+
+#[tauri::command]
+async fn search(
+  app: AppHandle,
+  question: String,
+  embed_model: String,
+  mut settings: library::RetrievalSettings,
+  ollama_url: Option<String>,
+) -> Result<Vec<library::Source>, String> {
+  settings.ollama_url = ollama_url;
+  tauri::async_runtime::spawn_blocking(move || {
+    library::retrieve(&app, &question, &embed_model, &settings).map_err(|e| format!("{:#}", e))
+  })
+  .await
+  .map_err(|e| format!("search task join error: {e}"))?
+}
+
+#[tauri::command]
+async fn search_fts(app: AppHandle, query: String, limit: Option<usize>) -> Result<Vec<library::Source>, String> {
+  tauri::async_runtime::spawn_blocking(move || {
+    library::search_fts(&app, &query, limit.unwrap_or(20)).map_err(|e| format!("{:#}", e))
+  })
+  .await
+  .map_err(|e| format!("search task join error: {e}"))?
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn chat(
   app: AppHandle,
   question: String,
+  history: Option<Vec<ollama::ChatMessage>>,
   llm_model: String,
   embed_model: String,
-  settings: library::RetrievalSettings,
+  mut settings: library::RetrievalSettings,
+  model_options: Option<serde_json::Value>,
+  bypass_cache: Option<bool>,
+  ollama_url: Option<String>,
 ) -> Result<library::ChatResult, String> {
+  settings.ollama_url = ollama_url;
   let app = app.clone();
   tauri::async_runtime::spawn_blocking(move || {
-    library::chat(&app, question, llm_model, embed_model, settings)
+    library::chat(&app, question, history.unwrap_or_default(), llm_model, embed_model, settings, model_options, bypass_cache.unwrap_or(false))
       .map_err(|e| format!("{:#}", e))
   })
   .await
@@ -553,22 +769,51 @@ async fn chat(
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn chat_stream(
   app: AppHandle,
   question: String,
+  history: Option<Vec<ollama::ChatMessage>>,
   llm_model: String,
   embed_model: String,
-  settings: library::RetrievalSettings,
+  mut settings: library::RetrievalSettings,
+  model_options: Option<serde_json::Value>,
+  bypass_cache: Option<bool>,
+  ollama_url: Option<String>,
 ) -> Result<library::ChatResult, String> {
+  settings.ollama_url = ollama_url;
   let app = app.clone();
   tauri::async_runtime::spawn_blocking(move || {
-    library::chat_stream(&app, question, llm_model, embed_model, settings)
+    library::chat_stream(&app, question, history.unwrap_or_default(), llm_model, embed_model, settings, model_options, bypass_cache.unwrap_or(false))
       .map_err(|e| format!("{:#}", e))
   })
   .await
   .map_err(|e| format!("chat task join error: {e}"))?
 }
 
+#[tauri::command]
+async fn test_ocr(app: AppHandle, path: String, settings: library::IndexSettings) -> Result<library::OcrTestResult, String> {
+  tauri::async_runtime::spawn_blocking(move || library::test_ocr(&app, path, settings).map_err(|e| format!("{:#}", e)))
+    .await
+    .map_err(|e| format!("ocr test task join error: {e}"))?
+}
+
+#[tauri::command]
+async fn build_chat_prompt(
+  app: AppHandle,
+  question: String,
+  embed_model: String,
+  settings: library::RetrievalSettings,
+) -> Result<library::PromptPreview, String> {
+  let app = app.clone();
+  tauri::async_runtime::spawn_blocking(move || {
+    library::build_chat_prompt(&app, question, embed_model, settings)
+      .map_err(|e| format!("{:#}", e))
+  })
+  .await
+  .map_err(|e| format!("prompt task join error: {e}"))?
+}
+
 #[tauri::command]
 fn reindex_files(
   app: AppHandle,
@@ -578,12 +823,12 @@ fn reindex_files(
   settings: library::IndexSettings,
 ) -> Result<(), String> {
   update_last_settings(&state, &embed_model, &settings);
+  app.state::<library::IndexCancel>().reset();
   let app_for_error = app.clone();
   tauri::async_runtime::spawn(async move {
     let res = tauri::async_runtime::spawn_blocking(move || {
       run_index_task(|| {
-        library::index_files(&app, files, embed_model, settings)
-          .map_err(|e| format!("{:#}", e))
+        library::index_files(&app, files, embed_model, settings).map_err(library::IndexError::from)
       })
     }).await;
     match res {
@@ -592,24 +837,174 @@ fn reindex_files(
         let _ = app_for_error.emit("index_error", e);
       }
       Err(e) => {
-        let _ = app_for_error.emit("index_error", format!("index task join error: {e}"));
+        let _ = app_for_error.emit("index_error", library::IndexError::from(format!("index task join error: {e}")));
       }
     }
   });
   Ok(())
 }
 
+/// Indexes only the files the preview reports as new or changed and returns them; indexing
+/// runs in the background like `reindex_files`.
 #[tauri::command]
-fn preview_index(app: AppHandle, targets: Vec<library::IndexTarget>) -> Result<Vec<library::IndexFilePreview>, String> {
-  library::preview_index(&app, targets).map_err(|e| format!("{:#}", e))
+fn reindex_changed(
+  app: AppHandle,
+  state: State<AppState>,
+  targets: Vec<library::IndexTarget>,
+  embed_model: String,
+  settings: library::IndexSettings,
+) -> Result<Vec<String>, String> {
+  let files = library::changed_files(&app, targets, &settings).map_err(|e| format!("{:#}", e))?;
+  if files.is_empty() {
+    return Ok(files);
+  }
+  reindex_files(app, state, files.clone(), embed_model, settings)?;
+  Ok(files)
 }
 
 #[tauri::command]
-fn list_models() -> Result<Vec<String>, String> {
-  let ollama = ollama::Ollama::new();
+fn scan_targets(
+  app: AppHandle,
+  state: State<AppState>,
+  embed_model: String,
+  settings: library::IndexSettings,
+) -> Result<(), String> {
+  update_last_settings(&state, &embed_model, &settings);
+  app.state::<library::IndexCancel>().reset();
+  let app_for_error = app.clone();
+  tauri::async_runtime::spawn(async move {
+    let res = tauri::async_runtime::spawn_blocking(move || {
+      run_index_task(|| {
+        library::scan_targets(&app, embed_model, settings).map_err(library::IndexError::from)
+      })
+    }).await;
+    match res {
+      Ok(Ok(())) => {}
+      Ok(Err(e)) => {
+        let _ = app_for_error.emit("index_error", e);
+      }
+      Err(e) => {
+        let _ = app_for_error.emit("index_error", library::IndexError::from(format!("index task join error: {e}")));
+      }
+    }
+  });
+  Ok(())
+}
+
+#[tauri::command]
+fn switch_embed_model(
+  app: AppHandle,
+  state: State<AppState>,
+  new_model: String,
+  settings: library::IndexSettings,
+) -> Result<(), String> {
+  update_last_settings(&state, &new_model, &settings);
+  app.state::<library::IndexCancel>().reset();
+  let app_for_error = app.clone();
+  tauri::async_runtime::spawn(async move {
+    let res = tauri::async_runtime::spawn_blocking(move || {
+      run_index_task(|| {
+        library::switch_embed_model(&app, new_model, settings).map_err(library::IndexError::from)
+      })
+    }).await;
+    match res {
+      Ok(Ok(())) => {}
+      Ok(Err(e)) => {
+        let _ = app_for_error.emit("index_error", e);
+      }
+      Err(e) => {
+        let _ = app_for_error.emit("index_error", library::IndexError::from(format!("index task join error: {e}")));
+      }
+    }
+  });
+  Ok(())
+}
+
+#[tauri::command]
+async fn estimate_index(
+  app: AppHandle,
+  targets: Vec<library::IndexTarget>,
+  embed_model: String,
+  settings: library::IndexSettings,
+) -> Result<library::IndexEstimate, String> {
+  let app = app.clone();
+  tauri::async_runtime::spawn_blocking(move || {
+    library::estimate_index(&app, targets, embed_model, settings)
+      .map_err(|e| format!("{:#}", e))
+  })
+  .await
+  .map_err(|e| format!("estimate task join error: {e}"))?
+}
+
+#[tauri::command]
+fn preview_index(
+  app: AppHandle,
+  state: State<AppState>,
+  targets: Vec<library::IndexTarget>,
+  max_file_bytes: Option<u64>,
+) -> Result<Vec<library::IndexFilePreview>, String> {
+  let kinds = state
+    .inner
+    .last_index_settings
+    .lock()
+    .map(|s| s.kind_filter())
+    .unwrap_or_default();
+  library::preview_index(&app, targets, max_file_bytes, &kinds).map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn list_indexed_files(app: AppHandle) -> Result<Vec<library::IndexedFile>, String> {
+  library::list_indexed_files(&app).map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn get_file_chunks(app: AppHandle, path: String) -> Result<Vec<library::ChunkInfo>, String> {
+  library::get_file_chunks(&app, path).map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn get_chunk(app: AppHandle, id: i64) -> Result<library::ChunkFull, String> {
+  library::get_chunk(&app, id).map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn list_models(ollama_url: Option<String>) -> Result<Vec<String>, String> {
+  let ollama = match ollama_url {
+    Some(url) => ollama::Ollama::with_base_url(url),
+    None => ollama::Ollama::new(),
+  };
   ollama.list_models().map_err(|e| format!("{:#}", e))
 }
 
+#[tauri::command]
+async fn list_model_details(
+  ollama_url: Option<String>,
+  models: Option<Vec<String>>,
+) -> Result<Vec<ollama::ModelDetails>, String> {
+  tauri::async_runtime::spawn_blocking(move || {
+    let ollama = match ollama_url {
+      Some(url) => ollama::Ollama::with_base_url(url),
+      None => ollama::Ollama::new(),
+    };
+    ollama.list_model_details(models.as_deref()).map_err(|e| format!("{:#}", e))
+  })
+  .await
+  .map_err(|e| format!("model details task join error: {e}"))?
+}
+
+#[tauri::command]
+async fn probe_embedding(
+  model: String,
+  text: String,
+  mut settings: library::IndexSettings,
+  ollama_url: Option<String>,
+) -> Result<library::EmbeddingProbe, String> {
+  settings.ollama_url = ollama_url;
+  tauri::async_runtime::spawn_blocking(move || library::probe_embedding(&model, &text, &settings).map_err(|e| format!("{:#}", e)))
+    .await
+    .map_err(|e| format!("embedding probe task join error: {e}"))?
+}
+
 #[tauri::command]
 fn list_cloud_models() -> Result<Vec<String>, String> {
   ollama::list_cloud_models().map_err(|e| format!("{:#}", e))
@@ -687,6 +1082,11 @@ fn stop_ollama(state: State<'_, AppState>) -> Result<OllamaStopResult, String> {
   })
 }
 
+#[tauri::command]
+fn recent_activity(state: State<AppState>, limit: Option<usize>) -> Vec<ActivityEntry> {
+  recent_entries(&state.inner, limit.unwrap_or(RECENT_ACTIVITY_CAP))
+}
+
 #[tauri::command]
 fn list_targets(app: AppHandle) -> Result<Vec<library::IndexTarget>, String> {
   library::list_targets(&app).map_err(|e| format!("{:#}", e))
@@ -700,20 +1100,105 @@ fn save_targets(app: AppHandle, state: State<AppState>, targets: Vec<library::In
 }
 
 #[tauri::command]
-fn prune_index(app: AppHandle, targets: Vec<library::IndexTarget>) -> Result<usize, String> {
-  library::prune_index(&app, targets).map_err(|e| format!("{:#}", e))
+fn remove_target(
+  app: AppHandle,
+  state: State<AppState>,
+  path: String,
+  kind: library::IndexTargetKind,
+  confirm: bool,
+) -> Result<usize, String> {
+  let removed = library::remove_target(&app, path, kind, confirm).map_err(|e| format!("{:#}", e))?;
+  let targets = library::list_targets(&app).map_err(|e| format!("{:#}", e))?;
+  update_watcher(&app, &state, &targets)?;
+  Ok(removed)
+}
+
+#[tauri::command]
+fn remap_paths(app: AppHandle, state: State<AppState>, old_prefix: String, new_prefix: String) -> Result<usize, String> {
+  let moved = library::remap_paths(&app, old_prefix, new_prefix).map_err(|e| format!("{:#}", e))?;
+  let targets = library::list_targets(&app).map_err(|e| format!("{:#}", e))?;
+  update_watcher(&app, &state, &targets)?;
+  Ok(moved)
+}
+
+#[tauri::command]
+async fn prune_index(
+  app: AppHandle,
+  targets: Vec<library::IndexTarget>,
+  missing: Option<bool>,
+  confirm: bool,
+) -> Result<library::PruneSummary, String> {
+  tauri::async_runtime::spawn_blocking(move || {
+    library::prune_index(&app, targets, missing.unwrap_or(false), confirm).map_err(|e| format!("{:#}", e))
+  })
+  .await
+  .map_err(|e| format!("prune task join error: {e}"))?
+}
+
+#[tauri::command]
+fn index_stats(app: AppHandle) -> Result<library::IndexStats, String> {
+  library::index_stats(&app).map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn clear_embed_cache(app: AppHandle, confirm: bool) -> Result<usize, String> {
+  library::clear_embed_cache(&app, confirm).map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn forget_files(app: AppHandle, files: Vec<String>, confirm: bool) -> Result<usize, String> {
+  library::forget_files(&app, files, confirm).map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+async fn compact_index(app: AppHandle) -> Result<library::CompactStats, String> {
+  tauri::async_runtime::spawn_blocking(move || {
+    library::compact_index(&app).map_err(|e| format!("{:#}", e))
+  })
+  .await
+  .map_err(|e| format!("compact task join error: {e}"))?
+}
+
+#[tauri::command]
+async fn redetect_languages(app: AppHandle, all: Option<bool>) -> Result<usize, String> {
+  tauri::async_runtime::spawn_blocking(move || {
+    library::redetect_languages(&app, all.unwrap_or(false)).map_err(|e| format!("{:#}", e))
+  })
+  .await
+  .map_err(|e| format!("language detection task join error: {e}"))?
+}
+
+#[tauri::command]
+async fn set_db_location(app: AppHandle, new_dir: String) -> Result<String, String> {
+  tauri::async_runtime::spawn_blocking(move || {
+    library::set_db_location(&app, new_dir).map_err(|e| format!("{:#}", e))
+  })
+  .await
+  .map_err(|e| format!("db move task join error: {e}"))?
+}
+
+#[tauri::command]
+fn clear_index(app: AppHandle, confirm: bool) -> Result<usize, String> {
+  library::clear_index(&app, confirm).map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn preview_destructive(app: AppHandle, action: library::DestructiveAction) -> Result<library::DestructivePreview, String> {
+  library::preview_destructive(&app, action).map_err(|e| format!("{:#}", e))
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
   tauri::Builder::default()
     .manage(AppState::default())
+    .manage(library::IndexCancel::default())
     .plugin(tauri_plugin_dialog::init())
     .plugin(tauri_plugin_opener::init())
     .setup(|app| {
       let state = app.state::<AppState>();
-      if let Ok(targets) = library::list_targets(&app.handle()) {
-        let _ = update_watcher(&app.handle(), &state, &targets);
+      record_activity(app.handle(), state.inner.clone());
+      if let Ok(targets) = library::list_targets(app.handle()) {
+        let _ = update_watcher(app.handle(), &state, &targets);
       }
       Ok(())
     })
@@ -722,18 +1207,44 @@ pub fn run() {
       setup_status,
       run_setup,
       start_index,
+      cancel_index,
       chat,
       chat_stream,
+      search,
+      search_fts,
+      build_chat_prompt,
       reindex_files,
+      reindex_changed,
+      scan_targets,
+      switch_embed_model,
       preview_index,
+      list_indexed_files,
+      estimate_index,
+      get_file_chunks,
+      get_chunk,
       list_models,
+      list_model_details,
+      probe_embedding,
       list_cloud_models,
       ollama_runtime_status,
       start_ollama,
       stop_ollama,
       list_targets,
       save_targets,
-      prune_index
+      prune_index,
+      clear_index,
+      set_db_location,
+      compact_index,
+      redetect_languages,
+      remap_paths,
+      remove_target,
+      test_ocr,
+      check_environment,
+      forget_files,
+      clear_embed_cache,
+      index_stats,
+      preview_destructive,
+      recent_activity
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
@@ -768,7 +1279,7 @@ mod tests {
 
   #[test]
   fn run_index_task_catches_panics() {
-    let ok = run_index_task(|| Ok(()));
+    let ok = run_index_task(|| Ok::<(), String>(()));
     assert!(ok.is_ok());
 
     let err = run_index_task(|| -> Result<(), String> {
@@ -778,15 +1289,91 @@ mod tests {
     assert!(err.contains("index task panicked"));
   }
 
+  #[test]
+  fn classify_event_splits_renames() {
+    let from = PathBuf::from("/nonexistent/old.pdf");
+    let to = PathBuf::from("/nonexistent/new.pdf");
+    let rename = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+    assert_eq!(
+      classify_event(&rename, &[from.clone(), to.clone()]),
+      vec![(from.clone(), WatchChange::Remove), (to, WatchChange::Reindex)]
+    );
+    let remove = EventKind::Remove(notify::event::RemoveKind::File);
+    assert_eq!(classify_event(&remove, std::slice::from_ref(&from)), vec![(from.clone(), WatchChange::Remove)]);
+    let modify = EventKind::Modify(ModifyKind::Any);
+    assert_eq!(classify_event(&modify, std::slice::from_ref(&from)), vec![(from, WatchChange::Remove)]);
+  }
+
+  fn test_state() -> AppStateInner {
+    let settings = library::IndexSettings {
+      chunk_size: 1400,
+      chunk_overlap: 250,
+      chunk_overlap_ratio: None,
+      ocr_enabled: true,
+      ocr_lang: "pol+eng".into(),
+      ocr_min_chars: 120,
+      ocr_dpi: 300,
+      ocr_auto_lang: false,
+      ocr_reflow: true,
+      ocr_max_concurrency: 0,
+      embed_truncate: true,
+      verify_text_hash: true,
+      min_chunk_chars: 30,
+      context_model: None,
+      embed_doc_prefix: None,
+      fts_tokenizer: library::FtsTokenizer::Unicode61,
+      chunk_strategy: library::ChunkStrategy::Fixed,
+      max_concurrency: 0,
+      max_file_bytes: None,
+      pptx_notes: false,
+      code_extensions: library::default_code_extensions(),
+      enabled_kinds: None,
+      embed_options: None,
+      ollama_url: None,
+    };
+
+    AppStateInner {
+      watcher: Mutex::new(None),
+      watched: Mutex::new(HashSet::new()),
+      last_event: Mutex::new(HashMap::new()),
+      last_embed_model: Mutex::new(String::new()),
+      last_index_settings: Mutex::new(settings),
+      target_files: Mutex::new(HashSet::new()),
+      folder_roots: Mutex::new(Vec::new()),
+      reindex_pending: Mutex::new(BTreeSet::new()),
+      reindex_wake: Mutex::new(None),
+      recent_activity: Mutex::new(VecDeque::new()),
+      ollama_child: Mutex::new(None),
+    }
+  }
+
   #[test]
   fn should_process_dedupes_events() {
     let settings = library::IndexSettings {
       chunk_size: 1400,
       chunk_overlap: 250,
+      chunk_overlap_ratio: None,
       ocr_enabled: true,
       ocr_lang: "pol+eng".into(),
       ocr_min_chars: 120,
       ocr_dpi: 300,
+      ocr_auto_lang: false,
+      ocr_reflow: true,
+      ocr_max_concurrency: 0,
+      embed_truncate: true,
+      verify_text_hash: true,
+      min_chunk_chars: 30,
+      context_model: None,
+      embed_doc_prefix: None,
+      fts_tokenizer: library::FtsTokenizer::Unicode61,
+      chunk_strategy: library::ChunkStrategy::Fixed,
+      max_concurrency: 0,
+      max_file_bytes: None,
+      pptx_notes: false,
+      code_extensions: library::default_code_extensions(),
+      enabled_kinds: None,
+      embed_options: None,
+      ollama_url: None,
     };
 
     let inner = AppStateInner {
@@ -797,11 +1384,38 @@ mod tests {
       last_index_settings: Mutex::new(settings),
       target_files: Mutex::new(HashSet::new()),
       folder_roots: Mutex::new(Vec::new()),
+      reindex_pending: Mutex::new(BTreeSet::new()),
+      reindex_wake: Mutex::new(None),
+      recent_activity: Mutex::new(VecDeque::new()),
       ollama_child: Mutex::new(None),
     };
 
     let path = PathBuf::from("C:\\temp\\file.txt");
-    assert!(should_process(&inner, &path));
-    assert!(!should_process(&inner, &path));
+    assert!(should_process(&inner, &path, WatchChange::Reindex));
+    assert!(!should_process(&inner, &path, WatchChange::Reindex));
+    assert!(should_process(&inner, &path, WatchChange::Remove));
+  }
+
+  #[test]
+  fn reindex_worker_drains_pending_files_once_in_order() {
+    let inner = test_state();
+    let added = enqueue_pending(&inner, vec!["b.txt".into(), "a.txt".into()]);
+    assert_eq!(added.len(), 2);
+    let added = enqueue_pending(&inner, vec!["a.txt".into(), "c.txt".into()]);
+    assert_eq!(added, vec!["c.txt".to_string()]);
+    assert_eq!(take_pending(&inner), vec!["a.txt", "b.txt", "c.txt"]);
+    assert!(take_pending(&inner).is_empty());
+  }
+
+  #[test]
+  fn recent_activity_is_bounded() {
+    let inner = test_state();
+    for i in 0..RECENT_ACTIVITY_CAP + 5 {
+      push_activity(&inner, format!("{i}.txt"), "done".into());
+    }
+    assert_eq!(inner.recent_activity.lock().unwrap().len(), RECENT_ACTIVITY_CAP);
+    let recent = recent_entries(&inner, 2);
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[0].file, format!("{}.txt", RECENT_ACTIVITY_CAP + 4));
   }
 }
@@ -4,30 +4,47 @@ use sha2::{Digest, Sha256};
 use std::{
   cmp::Ordering,
   collections::{HashMap, HashSet},
-  fs,
-  io::Read,
+  fmt, fs,
+  io::{BufRead, BufReader, Read},
   path::{Path, PathBuf},
   process::Command,
-  sync::{Mutex, OnceLock},
+  sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering},
+  ops::{Deref, DerefMut},
+  sync::{mpsc, Condvar, Mutex, OnceLock, RwLock, RwLockReadGuard},
   time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tauri::{AppHandle, Emitter};
 use tauri::path::BaseDirectory;
 use walkdir::WalkDir;
 use whatlang::detect;
-use rusqlite::{params, Connection, LoadExtensionGuard};
+use rusqlite::{params, Connection, LoadExtensionGuard, OpenFlags};
 use quick_xml::Reader;
 use quick_xml::events::Event;
 use zip::ZipArchive;
+use encoding_rs::Encoding;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use tauri::Manager;
+use pdfium_render::prelude::{PdfDocumentMetadataTagType, PdfFormField, PdfFormFieldCommon, PdfPage, PdfPageAnnotationCommon, PdfPageAnnotationType, Pdfium};
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
-use crate::ollama::{ChatMessage, Ollama, OllamaHttpError};
+use crate::ollama::{classify_ollama_error, ChatMessage, Ollama, OllamaErrorKind, OllamaHttpError};
 use reqwest::StatusCode;
 
 const DB_NAME: &str = "library.sqlite3";
+/// Lives in app_local_data_dir and holds the directory of a relocated database, if any.
+const DB_LOCATION_FILE: &str = "db_location.txt";
+/// Extra free space required on the target volume beyond the database size.
+const DB_MOVE_SPACE_MARGIN: u64 = 64 * 1024 * 1024;
+/// Lock wait for chat cache writes, which are skipped rather than delaying the answer.
+const CHAT_CACHE_BUSY_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long `set_db_location` waits for open connections (index runs, the watcher, chats) to close.
+const DB_MOVE_WAIT: Duration = Duration::from_secs(5);
+// sqlite-vec rejects KNN queries with k above this.
+const MAX_KNN_K: i64 = 4096;
+/// Hard cap on a single archive entry read into memory, whatever `max_file_bytes` says.
+const MAX_ZIP_ENTRY_BYTES: u64 = 512 * 1024 * 1024;
 
 #[derive(Serialize, Clone)]
 pub struct IndexProgress {
@@ -35,23 +52,216 @@ pub struct IndexProgress {
   pub total: usize,
   pub file: String,
   pub status: String,
+  /// Chunks of `file` embedded so far, only set on `"embed"` events.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub chunk_current: Option<usize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub chunk_total: Option<usize>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
+pub struct IndexCancelled {
+  /// Files written before the run stopped; they stay indexed.
+  pub processed: usize,
+  pub total: usize,
+}
+
+/// Payload of `index_error` (fatal, the run stopped) and `index_file_error` (one file was
+/// skipped), serialized as `{ code, message, file? }` so the UI can offer a matching fix.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum IndexError {
+  OllamaUnreachable { message: String },
+  ModelMissing { message: String },
+  VecExtensionMissing { message: String },
+  OcrFailed { message: String, file: Option<String> },
+  ExtractFailed { message: String, file: Option<String> },
+  Other {
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+  },
+}
+
+impl IndexError {
+  /// Classifies by downcasting the anyhow chain; `file` is attached to the variants that carry one.
+  pub fn from_anyhow(err: &anyhow::Error, file: Option<&str>) -> Self {
+    let message = format!("{:#}", err);
+    let file = file.map(str::to_string);
+    if err.downcast_ref::<VecExtensionNotFound>().is_some() {
+      return Self::VecExtensionMissing { message };
+    }
+    match classify_ollama_error(err) {
+      OllamaErrorKind::ModelNotFound => return Self::ModelMissing { message },
+      OllamaErrorKind::Unreachable => return Self::OllamaUnreachable { message },
+      OllamaErrorKind::Other => {}
+    }
+    if err.downcast_ref::<OcrFailure>().is_some() {
+      return Self::OcrFailed { message, file };
+    }
+    if let Some(failure) = err.downcast_ref::<ExtractFailure>() {
+      let file = file.or_else(|| Some(failure.0.clone()));
+      return Self::ExtractFailed { message, file };
+    }
+    Self::Other { message, file }
+  }
+
+  pub fn message(&self) -> &str {
+    match self {
+      Self::OllamaUnreachable { message }
+      | Self::ModelMissing { message }
+      | Self::VecExtensionMissing { message }
+      | Self::OcrFailed { message, .. }
+      | Self::ExtractFailed { message, .. }
+      | Self::Other { message, .. } => message,
+    }
+  }
+}
+
+impl From<anyhow::Error> for IndexError {
+  fn from(err: anyhow::Error) -> Self {
+    Self::from_anyhow(&err, None)
+  }
+}
+
+impl From<String> for IndexError {
+  fn from(message: String) -> Self {
+    Self::Other { message, file: None }
+  }
+}
+
+impl fmt::Display for IndexError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.message())
+  }
+}
+
+#[derive(Debug)]
+struct VecExtensionNotFound(String);
+
+impl fmt::Display for VecExtensionNotFound {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::error::Error for VecExtensionNotFound {}
+
+#[derive(Debug)]
+struct OcrFailure(String);
+
+impl fmt::Display for OcrFailure {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::error::Error for OcrFailure {}
+
+/// Context marking text extraction of `.0` (a file key) as the failing step.
+#[derive(Debug)]
+struct ExtractFailure(String);
+
+impl fmt::Display for ExtractFailure {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "extract failed: {}", self.0)
+  }
+}
+
+/// Managed state set by the `cancel_index` command. Commands clear it when they register a
+/// run, before spawning it, so a cancel sent while the model loads is not lost; the run
+/// stops taking new files once it is set.
+#[derive(Default)]
+pub struct IndexCancel(AtomicBool);
+
+impl IndexCancel {
+  pub fn request(&self) {
+    self.0.store(true, AtomicOrdering::Relaxed);
+  }
+
+  pub fn reset(&self) {
+    self.0.store(false, AtomicOrdering::Relaxed);
+  }
+}
+
+/// Probes the embedding dimension unless the run was cancelled before or while Ollama loaded
+/// the model; `None` means stop.
+fn probe_dim_unless_cancelled(cancel: Option<&AtomicBool>, probe: impl FnOnce() -> Result<usize>) -> Result<Option<usize>> {
+  let cancelled = || cancel.is_some_and(|c| c.load(AtomicOrdering::Relaxed));
+  if cancelled() {
+    return Ok(None);
+  }
+  let dim = probe()?;
+  Ok((!cancelled()).then_some(dim))
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Source {
+  /// Chunk id for `get_chunk`; for merged neighbours, the first chunk of the run.
+  pub id: i64,
   pub file_path: String,
   pub page: i32,
+  pub chunk_index: i32,
   pub snippet: String,
   pub distance: f64,
+  /// `native` or `ocr` for chunks indexed since the origin was recorded.
+  #[serde(default)]
+  pub origin: Option<String>,
+  /// Window of `snippet` around the keyword matches, set when the FTS query matched this text.
+  #[serde(default)]
+  pub highlight: Option<SnippetHighlight>,
+  /// Document title from its metadata, otherwise the file name.
+  #[serde(default)]
+  pub title: Option<String>,
+  #[serde(default)]
+  pub author: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SnippetHighlight {
+  pub text: String,
+  /// `[start, end)` character (code point) offsets of matched words in `text`.
+  pub ranges: Vec<[usize; 2]>,
 }
 
 #[derive(Serialize)]
+pub struct ChunkInfo {
+  pub id: i64,
+  pub page: i32,
+  pub chunk_index: i32,
+  pub lang: Option<String>,
+  pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct ChunkFull {
+  pub id: i64,
+  pub file_path: String,
+  pub page: i32,
+  pub chunk_index: i32,
+  pub text: String,
+  pub prev_id: Option<i64>,
+  pub next_id: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct ChatResult {
   pub answer: String,
   pub sources: Vec<Source>,
+  pub confidence: f32,
+  /// Language the retrieval filter assumed for the question, if it filtered at all.
+  #[serde(default)]
+  pub language: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PromptPreview {
+  pub system: String,
+  pub user: String,
+  pub sources: Vec<Source>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum IndexTargetKind {
   File,
@@ -65,6 +275,166 @@ pub struct IndexTarget {
   pub kind: IndexTargetKind,
   #[serde(default)]
   pub include_subfolders: bool,
+  /// Absolute subpaths of a folder target that are skipped entirely.
+  #[serde(default)]
+  pub exclude_paths: Vec<String>,
+  /// Globs matched against the path relative to the target root; a pattern without `/`
+  /// matches that name at any depth (e.g. `node_modules`, `*.tmp`).
+  #[serde(default)]
+  pub exclude_globs: Vec<String>,
+  /// Also skip `DEFAULT_IGNORE_GLOBS`.
+  #[serde(default = "default_true")]
+  pub default_ignores: bool,
+}
+
+const DEFAULT_IGNORE_GLOBS: &[&str] = &[".git", ".DS_Store", "$RECYCLE.BIN"];
+
+/// Per-folder ignore file read from the root of a folder target; its lines use the
+/// `exclude_globs` semantics.
+const IGNORE_FILE_NAME: &str = ".lfcignore";
+
+/// Patterns from an ignore file: one per line, blank lines and `#` comments skipped.
+/// Negated (`!`) patterns are not supported and are skipped as well.
+fn ignore_file_patterns(text: &str) -> Vec<String> {
+  text
+    .lines()
+    .map(str::trim)
+    .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with('!'))
+    .map(str::to_string)
+    .collect()
+}
+
+impl IndexTarget {
+  pub fn is_excluded(&self, path: &Path) -> bool {
+    self.exclusions().is_excluded(path)
+  }
+
+  /// Compiles the exclusion rules once, for walking or watching many paths. Folder targets
+  /// also pick up the patterns in their root `.lfcignore`.
+  pub fn exclusions(&self) -> TargetExclusions {
+    let defaults = if self.default_ignores { DEFAULT_IGNORE_GLOBS } else { &[] };
+    let ignore_file = match self.kind {
+      IndexTargetKind::Folder => fs::read_to_string(Path::new(&self.path).join(IGNORE_FILE_NAME))
+        .map(|text| ignore_file_patterns(&text))
+        .unwrap_or_default(),
+      IndexTargetKind::File => vec![],
+    };
+    TargetExclusions {
+      root: PathBuf::from(&self.path),
+      paths: self
+        .exclude_paths
+        .iter()
+        .filter(|p| !p.trim().is_empty())
+        .map(PathBuf::from)
+        .collect(),
+      globs: compile_exclude_globs(
+        defaults
+          .iter()
+          .copied()
+          .chain(self.exclude_globs.iter().map(String::as_str))
+          .chain(ignore_file.iter().map(String::as_str)),
+      ),
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct TargetExclusions {
+  root: PathBuf,
+  paths: Vec<PathBuf>,
+  globs: GlobSet,
+}
+
+impl TargetExclusions {
+  pub fn is_excluded(&self, path: &Path) -> bool {
+    if self.paths.iter().any(|p| path.starts_with(p)) {
+      return true;
+    }
+    let Ok(rel) = path.strip_prefix(&self.root) else {
+      return false;
+    };
+    // Check every ancestor too, so a file under an excluded folder is excluded as well.
+    let mut prefix = String::new();
+    for part in rel.components() {
+      if !prefix.is_empty() {
+        prefix.push('/');
+      }
+      prefix.push_str(&part.as_os_str().to_string_lossy());
+      if self.globs.is_match(&prefix) {
+        return true;
+      }
+    }
+    false
+  }
+}
+
+fn compile_exclude_globs<'a>(patterns: impl Iterator<Item = &'a str>) -> GlobSet {
+  let mut builder = GlobSetBuilder::new();
+  for pattern in patterns {
+    let pattern = pattern.trim().replace('\\', "/");
+    let pattern = pattern.trim_end_matches('/');
+    if pattern.is_empty() {
+      continue;
+    }
+    let pattern = if pattern.contains('/') { pattern.to_string() } else { format!("**/{pattern}") };
+    match GlobBuilder::new(&pattern).literal_separator(true).build() {
+      Ok(glob) => {
+        builder.add(glob);
+      }
+      Err(e) => eprintln!("invalid exclude glob {pattern}: {e}"),
+    }
+  }
+  builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+/// Where a PDF page's indexed text came from.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PageOrigin {
+  Text,
+  Ocr,
+}
+
+impl PageOrigin {
+  /// Value of `chunks.origin` and `Source::origin`.
+  fn as_str(self) -> &'static str {
+    match self {
+      PageOrigin::Text => "native",
+      PageOrigin::Ocr => "ocr",
+    }
+  }
+}
+
+/// Text-layer density of one PDF page (1-based), emitted as `page_density` during indexing.
+#[derive(Serialize, Clone, Debug)]
+pub struct PageDensity {
+  pub page: usize,
+  pub chars: usize,
+  pub origin: PageOrigin,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PageDensityReport {
+  pub file: String,
+  pub pages: Vec<PageDensity>,
+}
+
+struct ExtractedText {
+  /// One entry per page, blank pages included so the index is the page number.
+  pages: Vec<String>,
+  /// Per-page densities; only filled for PDFs.
+  page_report: Vec<PageDensity>,
+  /// Origin of each entry in `pages`; empty when every page is native text.
+  origins: Vec<PageOrigin>,
+}
+
+#[derive(Serialize)]
+pub struct IndexEstimate {
+  pub files: usize,
+  pub bytes: u64,
+  pub estimated_chunks: usize,
+  /// `None` when the embedding benchmark could not run (e.g. Ollama is down).
+  pub estimated_seconds: Option<f64>,
 }
 
 #[derive(Serialize)]
@@ -74,6 +444,18 @@ pub struct IndexFilePreview {
   pub status: String,
   pub size: i64,
   pub mtime: i64,
+  /// Unix seconds of the last successful index; `None` for files never indexed.
+  pub indexed_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct IndexedFile {
+  pub path: String,
+  pub kind: Option<String>,
+  pub size: Option<i64>,
+  pub mtime: Option<i64>,
+  pub indexed_at: Option<i64>,
+  pub chunks: i64,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -81,10 +463,97 @@ pub struct IndexFilePreview {
 pub struct IndexSettings {
   pub chunk_size: usize,
   pub chunk_overlap: usize,
+  /// Overlap as a fraction of `chunk_size` (clamped to 0.0..=0.9); overrides `chunk_overlap` when set.
+  pub chunk_overlap_ratio: Option<f64>,
   pub ocr_enabled: bool,
   pub ocr_lang: String,
   pub ocr_min_chars: usize,
   pub ocr_dpi: u16,
+  #[serde(default)]
+  pub ocr_auto_lang: bool,
+  /// Rejoin OCR line breaks into paragraphs and undo end-of-line hyphenation.
+  #[serde(default = "default_true")]
+  pub ocr_reflow: bool,
+  /// Upper bound on simultaneous tesseract processes; 0 picks one less than the CPU count.
+  #[serde(default)]
+  pub ocr_max_concurrency: usize,
+  #[serde(default = "default_true")]
+  pub embed_truncate: bool,
+  /// Re-hash extracted text when a file's fingerprint changes and skip re-embedding if it matches.
+  #[serde(default = "default_true")]
+  pub verify_text_hash: bool,
+  /// Chunks shorter than this (page numbers, stray headers) are dropped unless they are a page's only chunk.
+  #[serde(default = "default_min_chunk_chars")]
+  pub min_chunk_chars: usize,
+  /// When set, this LLM writes a short summary of each document that is prepended to every chunk
+  /// before embedding (the stored chunk text stays unchanged).
+  pub context_model: Option<String>,
+  /// Prepended to chunk text before embedding, for models trained with task prefixes
+  /// (e.g. `search_document: ` for nomic-embed-text).
+  pub embed_doc_prefix: Option<String>,
+  #[serde(default)]
+  pub fts_tokenizer: FtsTokenizer,
+  #[serde(default)]
+  pub chunk_strategy: ChunkStrategy,
+  /// Documents extracted and embedded in parallel while indexing; 0 uses the CPU count.
+  #[serde(default)]
+  pub max_concurrency: usize,
+  /// Files (or archive entries) larger than this are skipped with status `too_large`.
+  pub max_file_bytes: Option<u64>,
+  /// Append each PowerPoint slide's speaker notes to its page.
+  #[serde(default)]
+  pub pptx_notes: bool,
+  /// Extensions (without the dot) indexed as source code; built-in document types take precedence.
+  #[serde(default = "default_code_extensions")]
+  pub code_extensions: Vec<String>,
+  /// Kinds to index by name (`pdf`, `docx`, `code`, ...); unset or empty indexes every kind.
+  pub enabled_kinds: Option<Vec<String>>,
+  /// Ollama `options` for embed requests while indexing, e.g. `{"num_ctx": 8192}`.
+  pub embed_options: Option<serde_json::Value>,
+  /// Host for this run only, set by the command; `None` uses the global host.
+  #[serde(skip)]
+  pub ollama_url: Option<String>,
+}
+
+impl IndexSettings {
+  pub fn kind_filter(&self) -> KindFilter {
+    KindFilter { code_extensions: self.code_extensions.clone(), enabled_kinds: self.enabled_kinds.clone() }
+  }
+
+  /// Embedding client configured from these settings.
+  fn embed_client(&self) -> Result<Ollama> {
+    Ok(ollama_client(self.ollama_url.as_deref()).with_truncate(self.embed_truncate).with_embed_options(validate_model_options(self.embed_options.clone())?))
+  }
+
+  /// Overlap in characters actually used for chunking and recorded in `meta`.
+  pub fn effective_chunk_overlap(&self) -> usize {
+    match self.chunk_overlap_ratio {
+      Some(ratio) => (self.chunk_size as f64 * ratio.clamp(0.0, 0.9)) as usize,
+      None => self.chunk_overlap,
+    }
+  }
+}
+
+pub fn default_code_extensions() -> Vec<String> {
+  [
+    "rs", "py", "js", "jsx", "ts", "tsx", "go", "java", "kt", "c", "h", "cpp", "hpp", "cs", "rb", "php", "swift", "sh",
+    "sql",
+  ]
+  .into_iter()
+  .map(String::from)
+  .collect()
+}
+
+fn exceeds_max_file_bytes(size: i64, max_file_bytes: Option<u64>) -> bool {
+  max_file_bytes.is_some_and(|max| size.max(0) as u64 > max)
+}
+
+fn default_min_chunk_chars() -> usize {
+  30
+}
+
+fn default_true() -> bool {
+  true
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -95,6 +564,184 @@ pub struct RetrievalSettings {
   pub use_mmr: bool,
   pub mmr_lambda: f64,
   pub mmr_candidates: i64,
+  pub file_scope: Option<Vec<String>>,
+  #[serde(default)]
+  pub context_order: ContextOrder,
+  #[serde(default)]
+  pub fts_stopwords: bool,
+  #[serde(default)]
+  pub fts_stemming: bool,
+  /// Refuse to answer when fewer passages than this survive retrieval.
+  #[serde(default)]
+  pub min_sources: usize,
+  /// Answer in this language (e.g. "English") instead of the question's language.
+  pub answer_language: Option<String>,
+  /// Let the LLM rewrite the question once when the best source is weaker than `reformulate_distance`.
+  #[serde(default)]
+  pub auto_reformulate: bool,
+  #[serde(default = "default_reformulate_distance")]
+  pub reformulate_distance: f64,
+  /// KNN fetches at least `max(top_k * knn_multiplier, knn_min_k)` vectors so filters have headroom.
+  #[serde(default = "default_knn_multiplier")]
+  pub knn_multiplier: i64,
+  #[serde(default = "default_knn_min_k")]
+  pub knn_min_k: i64,
+  /// Exact KNN (and FTS) candidate count, floored at `top_k`, replacing the multiplier and MMR
+  /// derivation. A deeper pool gives keyword fusion and MMR more to work with at the cost of
+  /// slower queries.
+  pub candidate_pool: Option<i64>,
+  /// When chat is scoped to a single file, keep at most this many chunks per page (0 = no cap).
+  #[serde(default)]
+  pub max_chunks_per_page: usize,
+  /// Counterpart of `IndexSettings::embed_doc_prefix` for the question (e.g. `search_query: `).
+  pub embed_query_prefix: Option<String>,
+  /// Cap on each returned snippet (per merged chunk); `null` returns whole chunks.
+  #[serde(default = "default_snippet_max_chars")]
+  pub snippet_max_chars: Option<usize>,
+  /// Separate cap on each passage in the LLM context block; unset sends the snippet as returned.
+  pub context_max_chars: Option<usize>,
+  #[serde(default)]
+  pub language_filter: LanguageFilter,
+  /// Prior chat turns kept in the prompt, newest first; older ones are dropped.
+  #[serde(default = "default_history_max_messages")]
+  pub history_max_messages: usize,
+  /// Let the LLM turn a follow-up into a standalone question before retrieval when history is present.
+  #[serde(default)]
+  pub history_rewrite: bool,
+  /// After MMR, have an LLM score the best `rerank_candidates` passages against the question and
+  /// keep the `top_k` highest. Costs one chat call per candidate, typically a few seconds in total.
+  #[serde(default)]
+  pub rerank: bool,
+  /// Model used for scoring; the chat model when unset.
+  pub rerank_model: Option<String>,
+  #[serde(default = "default_rerank_candidates")]
+  pub rerank_candidates: usize,
+  /// Replaces the default RAG instructions; the citation and answer language lines are still appended.
+  pub system_prompt: Option<String>,
+  /// Append the `[1]`-style citation instruction the UI relies on to link sources.
+  #[serde(default = "default_true")]
+  pub cite_sources: bool,
+  /// User message template with `{question}` and `{context}` placeholders; sources are appended
+  /// when `{context}` is missing.
+  pub user_prompt_template: Option<String>,
+  /// Host for this request only, set by the command; `None` uses the global host.
+  #[serde(skip)]
+  pub ollama_url: Option<String>,
+}
+
+/// Client for a per-call host override, falling back to `OLLAMA_BASE_URL` / the default.
+fn ollama_client(base_url: Option<&str>) -> Ollama {
+  match base_url.map(str::trim).filter(|u| !u.is_empty()) {
+    Some(url) => Ollama::with_base_url(url.to_string()),
+    None => Ollama::new(),
+  }
+}
+
+fn default_rerank_candidates() -> usize {
+  12
+}
+
+fn default_history_max_messages() -> usize {
+  8
+}
+
+fn default_snippet_max_chars() -> Option<usize> {
+  Some(600)
+}
+
+fn default_knn_multiplier() -> i64 {
+  3
+}
+
+fn default_knn_min_k() -> i64 {
+  20
+}
+
+fn default_reformulate_distance() -> f64 {
+  0.5
+}
+
+/// How chunks in a language other than the question's are treated during retrieval.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LanguageFilter {
+  /// Ignore chunk languages.
+  Off,
+  /// Prefer same-language chunks, but keep the rest when none match.
+  #[default]
+  Soft,
+  /// Only same-language chunks (code chunks always pass).
+  Strict,
+}
+
+/// Questions shorter than this are not filtered by language: whatlang guesses poorly on a few words.
+const LANG_FILTER_MIN_CHARS: usize = 15;
+
+/// The language retrieval filters by, or `None` when the filter is off, the question is too
+/// short to trust detection, or no language was detected.
+fn filter_language(question: &str, mode: LanguageFilter) -> Option<String> {
+  if mode == LanguageFilter::Off || question.trim().chars().count() < LANG_FILTER_MIN_CHARS {
+    return None;
+  }
+  detect_lang_code(question)
+}
+
+/// Order in which retrieved passages are laid out in the LLM context block.
+/// `sources` returned to the UI always stay ranked by relevance.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextOrder {
+  #[default]
+  Relevance,
+  Document,
+}
+
+/// Tokenizer of the `chunks_fts` table. Changing it rebuilds the keyword index from `chunks`.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FtsTokenizer {
+  /// Word tokens with diacritics folded, so "zazolc" matches "zażółć".
+  #[default]
+  Unicode61,
+  /// Substring matching on character trigrams (terms need at least 3 characters).
+  Trigram,
+}
+
+impl FtsTokenizer {
+  fn as_sql(self) -> &'static str {
+    match self {
+      FtsTokenizer::Unicode61 => "unicode61 remove_diacritics 2",
+      FtsTokenizer::Trigram => "trigram remove_diacritics 1",
+    }
+  }
+}
+
+/// How page text is cut into chunks. Changing it rebuilds the index.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkStrategy {
+  /// Windows of `chunk_size` characters, cut at the nearest word or punctuation boundary.
+  #[default]
+  Fixed,
+  /// Whole sentences packed up to `chunk_size`; the overlap repeats trailing sentences.
+  Sentence,
+}
+
+impl ChunkStrategy {
+  fn chunk(self, text: &str, max_chars: usize, overlap: usize) -> Vec<String> {
+    match self {
+      ChunkStrategy::Fixed => chunk_text(text, max_chars, overlap),
+      ChunkStrategy::Sentence => chunk_sentences(text, max_chars, overlap),
+    }
+  }
+
+  /// Stored in `meta` only when not the default, so older databases count as `Fixed`.
+  fn meta_value(self) -> Option<String> {
+    match self {
+      ChunkStrategy::Fixed => None,
+      ChunkStrategy::Sentence => Some("sentence".into()),
+    }
+  }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -103,6 +750,13 @@ enum DocumentKind {
   Txt,
   Md,
   Docx,
+  Html,
+  Epub,
+  Rtf,
+  Pptx,
+  Csv,
+  Xlsx,
+  Code,
 }
 
 impl DocumentKind {
@@ -112,66 +766,398 @@ impl DocumentKind {
       DocumentKind::Txt => "txt",
       DocumentKind::Md => "md",
       DocumentKind::Docx => "docx",
+      DocumentKind::Html => "html",
+      DocumentKind::Epub => "epub",
+      DocumentKind::Rtf => "rtf",
+      DocumentKind::Pptx => "pptx",
+      DocumentKind::Csv => "csv",
+      DocumentKind::Xlsx => "xlsx",
+      DocumentKind::Code => "code",
     }
   }
+
+  /// Extracted as one line per row, so chunks are packed from whole rows.
+  fn is_tabular(self) -> bool {
+    matches!(self, DocumentKind::Csv | DocumentKind::Xlsx)
+  }
 }
 
 #[derive(Clone)]
 struct DocumentCandidate {
   path: PathBuf,
   kind: DocumentKind,
+  /// Inner path when the document lives inside a `.zip` at `path`.
+  entry: Option<String>,
+}
+
+impl DocumentCandidate {
+  /// Path stored in the index and shown in citations, e.g. `archive.zip!inner/file.pdf`.
+  fn key(&self) -> String {
+    document_key(&self.path, self.entry.as_deref())
+  }
+}
+
+fn document_key(path: &Path, entry: Option<&str>) -> String {
+  match entry {
+    Some(entry) => format!("{}!{}", path.to_string_lossy(), entry),
+    None => path.to_string_lossy().to_string(),
+  }
+}
+
+/// Splits `archive.zip!inner/file.pdf` into the archive path and the inner entry.
+fn split_archive_path(path: &str) -> (&str, Option<&str>) {
+  let lower = path.to_ascii_lowercase();
+  match lower.find(".zip!") {
+    Some(idx) => (&path[..idx + 4], Some(&path[idx + 5..])),
+    None => (path, None),
+  }
 }
 
 fn app_db_path(app: &AppHandle) -> Result<PathBuf> {
   // PathResolver ma app_local_data_dir, app_data_dir itd. 
   let dir = app.path().app_local_data_dir()?;
   fs::create_dir_all(&dir)?;
-  Ok(dir.join(DB_NAME))
-}
-
-fn vec0_extension_path(app: &AppHandle) -> Result<PathBuf> {
-  let candidates = [
-    "vec0.dll",
-    "vec0.dylib",
-    "libvec0.dylib",
-    "vec0.so",
-    "libvec0.so",
-    "resources/vec0.dll",
-    "resources/vec0.dylib",
-    "resources/libvec0.dylib",
-    "resources/vec0.so",
-    "resources/libvec0.so",
-  ];
-
-  for rel in candidates {
-    if let Ok(p) = app.path().resolve(rel, BaseDirectory::Resource) {
-      if p.exists() {
-        return Ok(p);
-      }
+  let configured = fs::read_to_string(dir.join(DB_LOCATION_FILE))
+    .ok()
+    .map(|raw| raw.trim().to_string())
+    .filter(|raw| !raw.is_empty())
+    .map(PathBuf::from);
+  match configured {
+    // Never recreate a relocated directory: on an unmounted drive that would silently start an empty index.
+    Some(dir) => {
+      anyhow::ensure!(dir.is_dir(), "database location {} is not available (is the drive connected?)", dir.display());
+      Ok(dir.join(DB_NAME))
     }
+    None => Ok(dir.join(DB_NAME)),
   }
-
-  anyhow::bail!("sqlite-vec extension not found in bundled resources")
 }
 
-fn open_db(app: &AppHandle) -> Result<Connection> {
-  let db_path = app_db_path(app)?;
-  let conn = Connection::open(db_path)?;
-  conn.busy_timeout(Duration::from_secs(10))?;
+/// Held shared by every open `Db`; `set_db_location` takes it exclusively so nothing reads
+/// or writes the files while they move.
+static DB_USERS: RwLock<()> = RwLock::new(());
 
-  conn.execute_batch(
-    "PRAGMA journal_mode=WAL;
-     PRAGMA synchronous=NORMAL;"
-  )?;
+/// A connection to the library database that keeps `set_db_location` from moving it.
+pub(crate) struct Db {
+  conn: Connection,
+  _in_use: RwLockReadGuard<'static, ()>,
+}
 
-  let vec_path = vec0_extension_path(app)?;
+impl Db {
+  fn in_use() -> RwLockReadGuard<'static, ()> {
+    DB_USERS.read().unwrap_or_else(|e| e.into_inner())
+  }
+}
 
-  unsafe {
-    let _guard = LoadExtensionGuard::new(&conn)?;
-    conn.load_extension(vec_path, None)?;
+impl Deref for Db {
+  type Target = Connection;
+  fn deref(&self) -> &Connection {
+    &self.conn
   }
+}
 
-  Ok(conn)
+impl DerefMut for Db {
+  fn deref_mut(&mut self) -> &mut Connection {
+    &mut self.conn
+  }
+}
+
+/// The database file followed by its WAL sidecars, which must travel with it.
+fn db_files(db_path: &Path) -> Vec<PathBuf> {
+  let mut files = vec![db_path.to_path_buf()];
+  for suffix in ["-wal", "-shm"] {
+    let mut name = db_path.as_os_str().to_os_string();
+    name.push(suffix);
+    files.push(PathBuf::from(name));
+  }
+  files
+}
+
+fn ensure_writable_dir(dir: &Path) -> Result<()> {
+  fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+  let probe = dir.join(format!(".write-probe-{}", std::process::id()));
+  fs::write(&probe, b"ok").with_context(|| format!("{} is not writable", dir.display()))?;
+  let _ = fs::remove_file(&probe);
+  Ok(())
+}
+
+/// Renames when possible and falls back to copy + delete across filesystems.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+  if fs::rename(from, to).is_ok() {
+    return Ok(());
+  }
+  fs::copy(from, to).with_context(|| format!("copy {} -> {}", from.display(), to.display()))?;
+  fs::remove_file(from).with_context(|| format!("remove {}", from.display()))?;
+  Ok(())
+}
+
+/// Moves each file in order, undoing the earlier moves if one fails.
+fn move_db_files(old_path: &Path, new_path: &Path) -> Result<()> {
+  let mut moved: Vec<(PathBuf, PathBuf)> = Vec::new();
+  for (from, to) in db_files(old_path).into_iter().zip(db_files(new_path)) {
+    if !from.exists() {
+      continue;
+    }
+    if let Err(e) = move_file(&from, &to) {
+      for (from, to) in moved.iter().rev() {
+        if let Err(undo) = move_file(to, from) {
+          eprintln!("db move rollback failed for {}: {undo:#}", to.display());
+        }
+      }
+      return Err(e);
+    }
+    moved.push((from, to));
+  }
+  Ok(())
+}
+
+fn write_db_location(config_dir: &Path, dir: Option<&Path>) -> Result<()> {
+  let marker = config_dir.join(DB_LOCATION_FILE);
+  match dir {
+    Some(dir) => fs::write(&marker, dir.to_string_lossy().as_bytes())
+      .with_context(|| format!("cannot write {}", marker.display()))?,
+    None => {
+      if marker.exists() {
+        fs::remove_file(&marker).with_context(|| format!("cannot remove {}", marker.display()))?;
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Moves the database (with its `-wal`/`-shm` files) into `new_dir` and remembers the location.
+/// Waits briefly for open connections to close and refuses while indexing or a chat still
+/// holds one. Returns the new database path.
+pub fn set_db_location(app: &AppHandle, new_dir: String) -> Result<String> {
+  let new_dir = PathBuf::from(new_dir.trim());
+  anyhow::ensure!(new_dir.is_absolute(), "database location must be an absolute path");
+  let started = std::time::Instant::now();
+  let exclusive = loop {
+    match DB_USERS.try_write() {
+      Ok(guard) => break guard,
+      Err(std::sync::TryLockError::Poisoned(e)) => break e.into_inner(),
+      Err(std::sync::TryLockError::WouldBlock) if started.elapsed() < DB_MOVE_WAIT => {
+        std::thread::sleep(Duration::from_millis(50));
+      }
+      Err(std::sync::TryLockError::WouldBlock) => {
+        anyhow::bail!("the database is in use (indexing, the folder watcher or a chat); try again when it finishes")
+      }
+    }
+  };
+  let old_path = app_db_path(app)?;
+  let new_path = new_dir.join(DB_NAME);
+  if old_path == new_path {
+    return Ok(new_path.to_string_lossy().to_string());
+  }
+  anyhow::ensure!(!new_path.exists(), "{} already contains a database", new_dir.display());
+  ensure_writable_dir(&new_dir)?;
+
+  if old_path.exists() {
+    // Fold the WAL back into the main file so the copy is self-contained.
+    let conn = Connection::open(&old_path)?;
+    conn.busy_timeout(Duration::from_secs(10))?;
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    drop(conn);
+
+    let needed: u64 = db_files(&old_path)
+      .iter()
+      .filter_map(|p| fs::metadata(p).ok())
+      .map(|m| m.len())
+      .sum();
+    let available = fs2::available_space(&new_dir)
+      .with_context(|| format!("cannot read free space on {}", new_dir.display()))?;
+    anyhow::ensure!(
+      available >= needed + DB_MOVE_SPACE_MARGIN,
+      "not enough free space in {} ({} MB needed, {} MB available)",
+      new_dir.display(),
+      (needed + DB_MOVE_SPACE_MARGIN) / (1024 * 1024),
+      available / (1024 * 1024)
+    );
+  }
+
+  // Record the new location before moving, so a failure never leaves the files somewhere
+  // the app does not look; both steps are undone together.
+  let config_dir = app.path().app_local_data_dir()?;
+  let previous = fs::read_to_string(config_dir.join(DB_LOCATION_FILE)).ok();
+  write_db_location(&config_dir, (new_dir != config_dir).then_some(new_dir.as_path()))?;
+  if old_path.exists() {
+    if let Err(e) = move_db_files(&old_path, &new_path) {
+      let restored = match &previous {
+        Some(raw) => fs::write(config_dir.join(DB_LOCATION_FILE), raw).map_err(anyhow::Error::from),
+        None => write_db_location(&config_dir, None),
+      };
+      if let Err(undo) = restored {
+        eprintln!("db location rollback failed: {undo:#}");
+      }
+      return Err(e);
+    }
+  }
+  drop(exclusive);
+
+  // Reopen from the new location to fail loudly now rather than on the next query.
+  open_db(app)?;
+  Ok(new_path.to_string_lossy().to_string())
+}
+
+const VEC0_SYSTEM_DIRS: &[&str] = &["/usr/local/lib", "/usr/lib", "/usr/lib64", "/usr/lib/x86_64-linux-gnu", "/usr/lib/aarch64-linux-gnu"];
+
+/// System-wide install locations for distro packages, checked after the bundled resources.
+fn vec0_system_candidates() -> Vec<PathBuf> {
+  let mut out = Vec::new();
+  for dir in VEC0_SYSTEM_DIRS {
+    for name in ["vec0.so", "libvec0.so", "sqlite-vec/vec0.so"] {
+      out.push(Path::new(dir).join(name));
+    }
+  }
+  out
+}
+
+fn vec0_extension_path(app: &AppHandle) -> Result<PathBuf> {
+  let candidates = [
+    "vec0.dll",
+    "vec0.dylib",
+    "libvec0.dylib",
+    "vec0.so",
+    "libvec0.so",
+    "resources/vec0.dll",
+    "resources/vec0.dylib",
+    "resources/libvec0.dylib",
+    "resources/vec0.so",
+    "resources/libvec0.so",
+  ];
+
+  let mut tried: Vec<PathBuf> = Vec::new();
+  // An explicit override wins over anything bundled.
+  if let Some(path) = std::env::var_os("SQLITE_VEC_PATH").filter(|v| !v.is_empty()) {
+    let path = PathBuf::from(path);
+    if path.is_file() {
+      return Ok(path);
+    }
+    tried.push(path);
+  }
+
+  for rel in candidates {
+    if let Ok(p) = app.path().resolve(rel, BaseDirectory::Resource) {
+      if p.exists() {
+        return Ok(p);
+      }
+      tried.push(p);
+    }
+  }
+
+  if !cfg!(windows) {
+    for p in vec0_system_candidates() {
+      if p.is_file() {
+        return Ok(p);
+      }
+      tried.push(p);
+    }
+  }
+
+  let tried: Vec<String> = tried.iter().map(|p| p.display().to_string()).collect();
+  Err(anyhow::Error::new(VecExtensionNotFound(format!(
+    "sqlite-vec extension not found; set SQLITE_VEC_PATH to its vec0 library. Tried:\n  {}",
+    tried.join("\n  ")
+  ))))
+}
+
+fn open_db(app: &AppHandle) -> Result<Db> {
+  let in_use = Db::in_use();
+  let db_path = app_db_path(app)?;
+  let conn = Connection::open(db_path)?;
+  conn.busy_timeout(Duration::from_secs(10))?;
+
+  conn.execute_batch(
+    "PRAGMA journal_mode=WAL;
+     PRAGMA synchronous=NORMAL;"
+  )?;
+
+  let vec_path = vec0_extension_path(app)?;
+
+  unsafe {
+    let _guard = LoadExtensionGuard::new(&conn)?;
+    conn.load_extension(vec_path, None)?;
+  }
+
+  Ok(Db { conn, _in_use: in_use })
+}
+
+/// Connection for the chat path, which never writes. WAL lets it read a consistent snapshot
+/// while the indexer holds the write lock; vec0 loads and runs KNN fine without write access.
+/// Falls back to a regular connection when the database cannot be opened read-only (e.g. it
+/// does not exist yet).
+fn open_db_read_only(app: &AppHandle) -> Result<Db> {
+  let in_use = Db::in_use();
+  let db_path = app_db_path(app)?;
+  let conn = match Connection::open_with_flags(&db_path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX) {
+    Ok(conn) => conn,
+    Err(_) => {
+      drop(in_use);
+      return open_db(app);
+    }
+  };
+  // Readers can still see SQLITE_BUSY briefly while a checkpoint resets the WAL.
+  conn.busy_timeout(Duration::from_secs(30))?;
+
+  let vec_path = vec0_extension_path(app)?;
+  unsafe {
+    let _guard = LoadExtensionGuard::new(&conn)?;
+    conn.load_extension(vec_path, None)?;
+  }
+
+  Ok(Db { conn, _in_use: in_use })
+}
+
+/// Contentless so we can index diacritic-folded text (see `fold_for_fts`) that differs from `chunks.text`.
+fn create_fts_table(conn: &Connection, tokenizer: FtsTokenizer) -> Result<()> {
+  conn.execute_batch(&format!(
+    "CREATE VIRTUAL TABLE IF NOT EXISTS chunks_fts
+     USING fts5(text, content='', contentless_delete=1, tokenize='{}');",
+    tokenizer.as_sql()
+  ))?;
+  Ok(())
+}
+
+fn rebuild_fts(conn: &Connection) -> Result<()> {
+  conn.execute("INSERT INTO chunks_fts(chunks_fts) VALUES('delete-all')", [])?;
+  let mut stmt = conn.prepare("SELECT id, text FROM chunks")?;
+  let mut rows = stmt.query([])?;
+  while let Some(r) = rows.next()? {
+    let id: i64 = r.get(0)?;
+    let text: String = r.get(1)?;
+    conn.execute("INSERT INTO chunks_fts(rowid, text) VALUES(?1, ?2)", params![id, fold_for_fts(&text)])?;
+  }
+  Ok(())
+}
+
+/// unicode61 strips combining marks, but letters like "ł" or "ø" have no decomposition; map them by hand.
+fn fold_for_fts(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      'ł' => out.push('l'),
+      'Ł' => out.push('L'),
+      'đ' => out.push('d'),
+      'Đ' => out.push('D'),
+      'ø' => out.push('o'),
+      'Ø' => out.push('O'),
+      'ß' => out.push_str("ss"),
+      _ => out.push(c),
+    }
+  }
+  out
+}
+
+/// Whether `settings` would chunk or contextualize differently from the indexed chunks,
+/// which makes every file stale (see `ensure_schema`).
+fn chunking_changed(conn: &Connection, settings: &IndexSettings) -> bool {
+  let differs = |key: &str, value: usize| {
+    meta_value(conn, key).and_then(|v| v.parse::<usize>().ok()).is_some_and(|old| old != value)
+  };
+  differs("chunk_size", settings.chunk_size)
+    || differs("chunk_overlap", settings.effective_chunk_overlap())
+    || meta_value(conn, "context_model") != settings.context_model
+    || meta_value(conn, "embed_doc_prefix") != settings.embed_doc_prefix
+    || meta_value(conn, "chunk_strategy") != settings.chunk_strategy.meta_value()
 }
 
 fn ensure_schema(conn: &Connection, dim: usize, settings: &IndexSettings) -> Result<()> {
@@ -195,7 +1181,22 @@ fn ensure_schema(conn: &Connection, dim: usize, settings: &IndexSettings) -> Res
        lang TEXT,
        text TEXT NOT NULL
      );
-     CREATE INDEX IF NOT EXISTS idx_chunks_file_path ON chunks(file_path);"
+     CREATE INDEX IF NOT EXISTS idx_chunks_file_path ON chunks(file_path);
+
+     CREATE TABLE IF NOT EXISTS doc_meta(
+       path TEXT PRIMARY KEY,
+       title TEXT,
+       author TEXT
+     );"
+  )?;
+  create_embed_cache(conn)?;
+  conn.execute_batch(
+    "CREATE TABLE IF NOT EXISTS chat_cache(
+       key TEXT PRIMARY KEY,
+       index_version TEXT NOT NULL,
+       result TEXT NOT NULL,
+       created_at INTEGER NOT NULL
+     );"
   )?;
 
   conn.execute_batch(
@@ -209,6 +1210,9 @@ fn ensure_schema(conn: &Connection, dim: usize, settings: &IndexSettings) -> Res
   )?;
 
   let _ = conn.execute("ALTER TABLE files ADD COLUMN kind TEXT", []);
+  let _ = conn.execute("ALTER TABLE files ADD COLUMN text_hash TEXT", []);
+  let _ = conn.execute("ALTER TABLE files ADD COLUMN embed_model TEXT", []);
+  let _ = conn.execute("ALTER TABLE chunks ADD COLUMN origin TEXT", []);
 
   // check dim
   let old_dim: Option<i64> = conn.query_row(
@@ -217,34 +1221,23 @@ fn ensure_schema(conn: &Connection, dim: usize, settings: &IndexSettings) -> Res
     |r| r.get::<_, String>(0)
   ).ok().and_then(|s| s.parse::<i64>().ok());
 
-  let old_chunk_size: Option<i64> = conn
-    .query_row("SELECT value FROM meta WHERE key='chunk_size'", [], |r| r.get::<_, String>(0))
-    .ok()
-    .and_then(|s| s.parse::<i64>().ok());
-  let old_chunk_overlap: Option<i64> = conn
-    .query_row("SELECT value FROM meta WHERE key='chunk_overlap'", [], |r| r.get::<_, String>(0))
-    .ok()
-    .and_then(|s| s.parse::<i64>().ok());
-
-  let schema_changed = match old_dim {
-    Some(old) if old as usize != dim => true,
-    _ => false,
-  } || match old_chunk_size {
-    Some(old) if old as usize != settings.chunk_size => true,
-    _ => false,
-  } || match old_chunk_overlap {
-    Some(old) if old as usize != settings.chunk_overlap => true,
-    _ => false,
-  };
+  let dim_changed = matches!(old_dim, Some(old) if old as usize != dim);
 
-  if schema_changed {
+  if dim_changed {
+    // Old vectors cannot live in a table of the new width.
     conn.execute_batch(
       "DROP TABLE IF EXISTS vec_chunks;
        DROP TABLE IF EXISTS chunks_fts;
        DELETE FROM chunks;
        DELETE FROM files;
-       DELETE FROM meta WHERE key IN ('embedding_dim','chunk_size','chunk_overlap');"
+       DELETE FROM doc_meta;
+       DELETE FROM meta WHERE key IN ('embedding_dim','chunk_size','chunk_overlap','context_model','embed_doc_prefix','chunk_strategy');
+       DELETE FROM embed_cache;"
     )?;
+    bump_index_version(conn)?;
+  } else if chunking_changed(conn, settings) {
+    // Keep serving the old chunks until each file is replaced by the run that follows.
+    mark_files_stale(conn)?;
   }
 
   conn.execute(
@@ -257,13 +1250,32 @@ fn ensure_schema(conn: &Connection, dim: usize, settings: &IndexSettings) -> Res
   )?;
   conn.execute(
     "INSERT OR REPLACE INTO meta(key,value) VALUES('chunk_overlap', ?)",
-    params![settings.chunk_overlap.to_string()],
+    params![settings.effective_chunk_overlap().to_string()],
   )?;
+  let chunk_strategy = settings.chunk_strategy.meta_value();
+  for (key, value) in [
+    ("context_model", &settings.context_model),
+    ("embed_doc_prefix", &settings.embed_doc_prefix),
+    ("chunk_strategy", &chunk_strategy),
+  ] {
+    match value {
+      Some(value) => conn.execute("INSERT OR REPLACE INTO meta(key,value) VALUES(?1, ?2)", params![key, value])?,
+      None => conn.execute("DELETE FROM meta WHERE key=?1", params![key])?,
+    };
+  }
 
-  conn.execute_batch(
-    "CREATE VIRTUAL TABLE IF NOT EXISTS chunks_fts
-     USING fts5(text, content='chunks', content_rowid='id');"
-  )?;
+  // Databases without this key still have the older external-content FTS table.
+  let tokenizer = settings.fts_tokenizer.as_sql();
+  let retokenize = meta_value(conn, "fts_tokenizer").as_deref() != Some(tokenizer);
+  if retokenize {
+    conn.execute_batch("DROP TABLE IF EXISTS chunks_fts;")?;
+  }
+  create_fts_table(conn, settings.fts_tokenizer)?;
+  if retokenize {
+    // The chunks themselves are intact, only the postings need rebuilding.
+    rebuild_fts(conn)?;
+    conn.execute("INSERT OR REPLACE INTO meta(key,value) VALUES('fts_tokenizer', ?)", params![tokenizer])?;
+  }
 
   // vec0 virtual table (sqlite-vec) + cosine, KNN 
   conn.execute_batch(&format!(
@@ -274,22 +1286,121 @@ fn ensure_schema(conn: &Connection, dim: usize, settings: &IndexSettings) -> Res
   Ok(())
 }
 
-fn kind_from_path(p: &Path) -> Option<DocumentKind> {
+/// Which files count as documents: the code extensions plus the optional `enabled_kinds` allow-list.
+#[derive(Clone, Default)]
+pub struct KindFilter {
+  pub code_extensions: Vec<String>,
+  pub enabled_kinds: Option<Vec<String>>,
+}
+
+impl KindFilter {
+  /// Same filter with every kind enabled, e.g. to still notice removals of a disabled kind.
+  pub fn all_kinds(&self) -> KindFilter {
+    KindFilter { code_extensions: self.code_extensions.clone(), enabled_kinds: None }
+  }
+
+  fn allows(&self, kind: DocumentKind) -> bool {
+    match self.enabled_kinds.as_deref() {
+      Some(kinds) if !kinds.is_empty() => kinds.iter().any(|k| k.trim().eq_ignore_ascii_case(kind.as_str())),
+      _ => true,
+    }
+  }
+
+  fn kind_of(&self, p: &Path) -> Option<DocumentKind> {
+    kind_from_path(p, &self.code_extensions).filter(|k| self.allows(*k))
+  }
+}
+
+fn kind_from_path(p: &Path, code_extensions: &[String]) -> Option<DocumentKind> {
   let ext = p.extension()?.to_str()?.to_ascii_lowercase();
   match ext.as_str() {
     "pdf" => Some(DocumentKind::Pdf),
     "txt" => Some(DocumentKind::Txt),
     "md" | "markdown" => Some(DocumentKind::Md),
     "docx" => Some(DocumentKind::Docx),
+    "html" | "htm" => Some(DocumentKind::Html),
+    "epub" => Some(DocumentKind::Epub),
+    "rtf" => Some(DocumentKind::Rtf),
+    "pptx" => Some(DocumentKind::Pptx),
+    "csv" => Some(DocumentKind::Csv),
+    "xlsx" => Some(DocumentKind::Xlsx),
+    _ if code_extensions.iter().any(|c| c.trim_start_matches('.').eq_ignore_ascii_case(&ext)) => Some(DocumentKind::Code),
     _ => None,
   }
 }
 
-pub fn is_supported_document(path: &Path) -> bool {
-  kind_from_path(path).is_some()
+/// Stored in the `lang` column of code chunks in place of a natural-language guess. The
+/// `code:` prefix keeps them apart from whatlang codes.
+fn code_language(path: &Path) -> String {
+  let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+  let name = match ext.as_str() {
+    "rs" => "rust",
+    "py" => "python",
+    "js" | "jsx" | "mjs" | "cjs" => "javascript",
+    "ts" | "tsx" => "typescript",
+    "go" => "go",
+    "java" => "java",
+    "kt" | "kts" => "kotlin",
+    "c" | "h" => "c",
+    "cpp" | "cc" | "cxx" | "hpp" | "hh" => "cpp",
+    "cs" => "csharp",
+    "rb" => "ruby",
+    "php" => "php",
+    "swift" => "swift",
+    "sh" | "bash" | "zsh" => "shell",
+    "sql" => "sql",
+    other => other,
+  };
+  format!("code:{name}")
+}
+
+fn is_zip_archive(p: &Path) -> bool {
+  p.extension()
+    .and_then(|e| e.to_str())
+    .map(|e| e.eq_ignore_ascii_case("zip"))
+    .unwrap_or(false)
+}
+
+pub fn is_supported_document(path: &Path, kinds: &KindFilter) -> bool {
+  kinds.kind_of(path).is_some() || is_zip_archive(path)
+}
+
+/// Supported documents inside a zip, including nested folders. Nested archives are not descended into.
+fn list_zip_entries(archive: &Path, kinds: &KindFilter) -> Vec<(String, DocumentKind)> {
+  let Ok(file) = fs::File::open(archive) else { return vec![] };
+  let Ok(mut zip) = ZipArchive::new(file) else {
+    eprintln!("zip skip {}: not a readable archive", archive.display());
+    return vec![];
+  };
+  let mut out = vec![];
+  for i in 0..zip.len() {
+    let Ok(entry) = zip.by_index(i) else { continue };
+    if entry.is_dir() {
+      continue;
+    }
+    let Some(name) = entry.enclosed_name().map(|p| p.to_string_lossy().replace('\\', "/")) else { continue };
+    if let Some(kind) = kinds.kind_of(Path::new(&name)) {
+      out.push((name, kind));
+    }
+  }
+  out.sort_by(|a, b| a.0.cmp(&b.0));
+  out
+}
+
+fn document_candidates(path: &Path, kinds: &KindFilter) -> Vec<DocumentCandidate> {
+  if let Some(kind) = kinds.kind_of(path) {
+    return vec![DocumentCandidate { path: path.to_path_buf(), kind, entry: None }];
+  }
+  if is_zip_archive(path) {
+    return list_zip_entries(path, kinds)
+      .into_iter()
+      .map(|(entry, kind)| DocumentCandidate { path: path.to_path_buf(), kind, entry: Some(entry) })
+      .collect();
+  }
+  vec![]
 }
 
-fn list_documents(targets: &[IndexTarget]) -> Vec<DocumentCandidate> {
+fn list_documents(targets: &[IndexTarget], kinds: &KindFilter) -> Vec<DocumentCandidate> {
     let mut out = vec![];
     let mut seen: HashSet<String> = HashSet::new();
 
@@ -298,10 +1409,9 @@ fn list_documents(targets: &[IndexTarget]) -> Vec<DocumentCandidate> {
         match target.kind {
             IndexTargetKind::File => {
                 if base.is_file() {
-                    if let Some(kind) = kind_from_path(&base) {
-                        let key = base.to_string_lossy().to_string();
-                        if seen.insert(key) {
-                            out.push(DocumentCandidate { path: base.clone(), kind }); // Clone base here
+                    for doc in document_candidates(&base, kinds) {
+                        if seen.insert(doc.key()) {
+                            out.push(doc);
                         }
                     }
                 }
@@ -316,15 +1426,15 @@ fn list_documents(targets: &[IndexTarget]) -> Vec<DocumentCandidate> {
                     WalkDir::new(&base).max_depth(1)
                 };
 
-                for e in walker.into_iter().filter_map(|e| e.ok()) {
+                let exclusions = target.exclusions();
+                let entries = walker.into_iter().filter_entry(|e| !exclusions.is_excluded(e.path()));
+                for e in entries.filter_map(|e| e.ok()) {
                     if !e.file_type().is_file() {
                         continue;
                     }
-                    let p = e.path();
-                    if let Some(kind) = kind_from_path(p) {
-                        let key = p.to_string_lossy().to_string();
-                        if seen.insert(key) {
-                            out.push(DocumentCandidate { path: p.to_path_buf(), kind });
+                    for doc in document_candidates(e.path(), kinds) {
+                        if seen.insert(doc.key()) {
+                            out.push(doc);
                         }
                     }
                 }
@@ -339,10 +1449,11 @@ fn list_documents(targets: &[IndexTarget]) -> Vec<DocumentCandidate> {
 struct PreviewCandidate {
   path: PathBuf,
   kind: DocumentKind,
+  entry: Option<String>,
   exists: bool,
 }
 
-fn list_preview_items(targets: &[IndexTarget]) -> Vec<PreviewCandidate> {
+fn list_preview_items(targets: &[IndexTarget], kinds: &KindFilter) -> Vec<PreviewCandidate> {
     let mut out = vec![];
     let mut seen: HashSet<String> = HashSet::new();
 
@@ -350,10 +1461,16 @@ fn list_preview_items(targets: &[IndexTarget]) -> Vec<PreviewCandidate> {
         let base = PathBuf::from(&target.path);
         match target.kind {
             IndexTargetKind::File => {
-                if let Some(kind) = kind_from_path(&base) {
+                if let Some(kind) = kinds.kind_of(&base) {
                     let key = base.to_string_lossy().to_string();
                     if seen.insert(key) {
-                        out.push(PreviewCandidate { path: base.clone(), kind, exists: base.is_file() }); // Clone base here
+                        out.push(PreviewCandidate { path: base.clone(), kind, entry: None, exists: base.is_file() }); // Clone base here
+                    }
+                } else if base.is_file() {
+                    for doc in document_candidates(&base, kinds) {
+                        if seen.insert(doc.key()) {
+                            out.push(PreviewCandidate { path: doc.path, kind: doc.kind, entry: doc.entry, exists: true });
+                        }
                     }
                 }
             }
@@ -367,15 +1484,15 @@ fn list_preview_items(targets: &[IndexTarget]) -> Vec<PreviewCandidate> {
                     WalkDir::new(&base).max_depth(1)
                 };
 
-                for e in walker.into_iter().filter_map(|e| e.ok()) {
+                let exclusions = target.exclusions();
+                let entries = walker.into_iter().filter_entry(|e| !exclusions.is_excluded(e.path()));
+                for e in entries.filter_map(|e| e.ok()) {
                     if !e.file_type().is_file() {
                         continue;
                     }
-                    let p = e.path();
-                    if let Some(kind) = kind_from_path(p) {
-                        let key = p.to_string_lossy().to_string();
-                        if seen.insert(key) {
-                            out.push(PreviewCandidate { path: p.to_path_buf(), kind, exists: true });
+                    for doc in document_candidates(e.path(), kinds) {
+                        if seen.insert(doc.key()) {
+                            out.push(PreviewCandidate { path: doc.path, kind: doc.kind, entry: doc.entry, exists: true });
                         }
                     }
                 }
@@ -386,6 +1503,25 @@ fn list_preview_items(targets: &[IndexTarget]) -> Vec<PreviewCandidate> {
     out
 }
 
+/// Fingerprint for a document; zip entries hash their CRC and size, so touching the archive
+/// alone does not mark every entry as changed.
+fn document_fingerprint(path: &Path, entry: Option<&str>) -> Result<(String, i64, i64)> {
+  let Some(entry) = entry else {
+    return file_fingerprint(path);
+  };
+  let (_, _, mtime) = file_fingerprint(path)?;
+  let mut zip = ZipArchive::new(fs::File::open(path)?)?;
+  let file = zip.by_name(entry)?;
+  let size = file.size() as i64;
+
+  let mut h = Sha256::new();
+  h.update(document_key(path, Some(entry)).as_bytes());
+  h.update(size.to_le_bytes());
+  h.update(file.crc32().to_le_bytes());
+  let hash = format!("{:x}", h.finalize());
+  Ok((hash, size, mtime))
+}
+
 fn file_fingerprint(p: &Path) -> Result<(String, i64, i64)> {
   let md = fs::metadata(p)?;
   let size = md.len() as i64;
@@ -403,10 +1539,174 @@ fn file_fingerprint(p: &Path) -> Result<(String, i64, i64)> {
   Ok((hash, size, mtime))
 }
 
+fn text_hash(pages: &[String]) -> String {
+  let mut h = Sha256::new();
+  for page in pages {
+    h.update(page.as_bytes());
+    // Page breaks change chunk page numbers, so they are part of the content.
+    h.update([0x0c]);
+  }
+  format!("{:x}", h.finalize())
+}
+
+fn drop_short_chunks(chunks: Vec<String>, min_chars: usize) -> Vec<String> {
+  if chunks.len() <= 1 {
+    return chunks;
+  }
+  chunks.into_iter().filter(|c| c.chars().count() >= min_chars).collect()
+}
+
+const SUMMARY_EXCERPT_CHARS: usize = 4000;
+const SUMMARY_MAX_CHARS: usize = 400;
+
+/// One-off LLM summary of a document's opening text, used to give chunks document context.
+fn document_summary(ollama: &Ollama, model: &str, pages: &[String]) -> Option<String> {
+  let excerpt: String = pages.join("\n").chars().take(SUMMARY_EXCERPT_CHARS).collect();
+  if excerpt.trim().is_empty() {
+    return None;
+  }
+  let messages = vec![
+    ChatMessage {
+      role: "system".into(),
+      content: "Summarize what this document is about in one or two sentences: its type, subject and the parties or topics it covers. Keep the document's language. Reply with the summary only.".into(),
+    },
+    ChatMessage { role: "user".into(), content: excerpt },
+  ];
+  match chat_with_retry(|| ollama.chat(model, messages.clone())) {
+    Ok(raw) => {
+      let summary: String = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+      let summary: String = summary.chars().take(SUMMARY_MAX_CHARS).collect();
+      if summary.is_empty() { None } else { Some(summary) }
+    }
+    Err(e) => {
+      eprintln!("document summary failed: {e:#}");
+      None
+    }
+  }
+}
+
+fn contextualize_chunk(summary: Option<&str>, chunk: &str) -> String {
+  match summary {
+    Some(summary) => format!("{summary}\n\n{chunk}"),
+    None => chunk.to_string(),
+  }
+}
+
+fn with_embed_prefix(prefix: Option<&str>, text: &str) -> String {
+  match prefix {
+    Some(prefix) => format!("{prefix}{text}"),
+    None => text.to_string(),
+  }
+}
+
+/// Documents embedded without a task prefix sit in a different region of the space than
+/// prefixed queries (and vice versa), so one side being set without the other hurts recall.
+fn embed_prefix_mismatch(indexed_doc_prefix: Option<&str>, query_prefix: Option<&str>) -> bool {
+  let set = |p: Option<&str>| p.is_some_and(|p| !p.trim().is_empty());
+  set(indexed_doc_prefix) != set(query_prefix)
+}
+
+fn warn_if_embed_prefix_mismatch(app: &AppHandle, conn: &Connection, settings: &RetrievalSettings) {
+  let indexed = meta_value(conn, "embed_doc_prefix");
+  if embed_prefix_mismatch(indexed.as_deref(), settings.embed_query_prefix.as_deref()) {
+    let _ = app.emit(
+      "chat_warning",
+      "Embedding prefix settings changed since the library was indexed; reindex for consistent results.",
+    );
+  }
+}
+
+/// Ollama resolves a bare model name to its `:latest` tag, so both spellings are the same model.
+fn same_embed_model(a: &str, b: &str) -> bool {
+  let norm = |m: &str| {
+    let m = m.trim();
+    m.strip_suffix(":latest").unwrap_or(m).to_string()
+  };
+  norm(a) == norm(b)
+}
+
+/// Vectors from two models can share a dimension and still be incomparable, so a query
+/// with a different model than the index was built with is refused rather than answered.
+fn ensure_query_embed_model(conn: &Connection, embed_model: &str) -> Result<()> {
+  if let Some(indexed) = meta_value(conn, "embed_model") {
+    if !same_embed_model(&indexed, embed_model) {
+      anyhow::bail!(
+        "Index was built with embedding model {indexed}, but the query uses {embed_model}; reindex or switch the embedding model back."
+      );
+    }
+  }
+  Ok(())
+}
+
+/// How far back an overlap start may move to land on whitespace instead of mid-word.
+const OVERLAP_LOOKBACK_CHARS: usize = 24;
+
 fn is_chunk_boundary(c: char) -> bool {
   c.is_whitespace() || matches!(c, '.' | '!' | '?' | ';' | ',' | ':' | ')' | ']' | '}')
 }
 
+/// Packs whole lines (table rows) up to `max_chars`; a single longer row is split like prose.
+fn chunk_rows(s: &str, max_chars: usize, overlap: usize) -> Vec<String> {
+  pack_lines(s.lines().map(str::trim), max_chars, overlap)
+}
+
+fn pack_lines<'a>(lines: impl Iterator<Item = &'a str>, max_chars: usize, overlap: usize) -> Vec<String> {
+  let mut chunks = Vec::new();
+  let mut current = String::new();
+  let mut current_chars = 0;
+  for line in lines.filter(|l| !l.trim().is_empty()) {
+    let len = line.chars().count();
+    if current_chars > 0 && current_chars + 1 + len > max_chars {
+      chunks.push(std::mem::take(&mut current));
+      current_chars = 0;
+    }
+    if len > max_chars {
+      chunks.extend(chunk_text(line, max_chars, overlap));
+      continue;
+    }
+    if current_chars > 0 {
+      current.push('\n');
+      current_chars += 1;
+    }
+    current.push_str(line);
+    current_chars += len;
+  }
+  if !current.is_empty() {
+    chunks.push(current);
+  }
+  chunks
+}
+
+/// Packs blank-line separated blocks (usually whole functions) up to `max_chars`; a longer
+/// block is still cut between lines, keeping their indentation.
+fn chunk_code(s: &str, max_chars: usize, overlap: usize) -> Vec<String> {
+  let mut chunks = Vec::new();
+  let mut current = String::new();
+  let mut current_chars = 0;
+  let blocks = s.split("\n\n").map(|b| b.trim_matches('\n').trim_end()).filter(|b| !b.trim().is_empty());
+  for block in blocks {
+    let len = block.chars().count();
+    if current_chars > 0 && current_chars + 2 + len > max_chars {
+      chunks.push(std::mem::take(&mut current));
+      current_chars = 0;
+    }
+    if len > max_chars {
+      chunks.extend(pack_lines(block.lines().map(str::trim_end), max_chars, overlap));
+      continue;
+    }
+    if current_chars > 0 {
+      current.push_str("\n\n");
+      current_chars += 2;
+    }
+    current.push_str(block);
+    current_chars += len;
+  }
+  if !current.is_empty() {
+    chunks.push(current);
+  }
+  chunks
+}
+
 fn chunk_text(s: &str, max_chars: usize, overlap: usize) -> Vec<String> {
   let s = s.trim();
   if s.is_empty() || max_chars == 0 {
@@ -431,14 +1731,16 @@ fn chunk_text(s: &str, max_chars: usize, overlap: usize) -> Vec<String> {
 
   let mut out = Vec::new();
   let mut start = 0usize;
+  let mut prev_end = 0usize;
   // Prefer boundaries near the end, but fall back to hard cuts to avoid tiny chunks.
   let min_boundary_len = max_chars.saturating_div(3);
 
   while start < chars.len() {
     let mut end = (start + max_chars).min(chars.len());
 
+    // The cut must pass the previous chunk's end, or this chunk would be a suffix of that one.
     let mut boundary_end = None;
-    for idx in (start..end).rev() {
+    for idx in (start.max(prev_end)..end).rev() {
       if is_chunk_boundary(chars[idx]) {
         boundary_end = Some(idx + 1);
         break;
@@ -463,31 +1765,157 @@ fn chunk_text(s: &str, max_chars: usize, overlap: usize) -> Vec<String> {
     if end == chars.len() {
       break;
     }
-    start = end.saturating_sub(overlap);
+    start = overlap_start(&chars, start, end, overlap);
+    prev_end = end;
   }
 
   out
 }
 
-fn clean_text(s: &str) -> String {
-  s.replace('\u{0}', " ").trim().to_string()
-}
-
-fn split_pages(raw: &str) -> Vec<String> {
-  let parts = if raw.contains('\x0C') {
-    raw.split('\x0C').collect::<Vec<_>>()
-  } else if raw.contains('\u{000C}') {
-    raw.split('\u{000C}').collect::<Vec<_>>()
-  } else {
-    vec![raw]
-  };
-
-  parts
-    .into_iter()
-    .map(clean_text)
-    .filter(|s| !s.is_empty())
-    .collect()
-}
+/// Abbreviations whose trailing dot doesn't end a sentence (compared lowercased).
+const SENTENCE_ABBREVIATIONS: &[&str] = &[
+  "mr", "mrs", "ms", "dr", "prof", "st", "jr", "sr", "vs", "etc", "e.g", "i.e", "inc", "ltd", "no", "fig",
+  "np", "nr", "ul", "tzn", "tj", "itd", "itp", "ok", "godz", "mgr", "inż", "hab", "pkt", "art", "ust",
+];
+
+/// Rule-based splitter: a sentence ends at `.`, `!`, `?` or `…` (plus closing quotes or
+/// brackets) followed by whitespace and an uppercase letter or digit, unless the dot
+/// closes a known abbreviation or a single-letter initial.
+fn split_sentences(s: &str) -> Vec<&str> {
+  let chars: Vec<(usize, char)> = s.char_indices().collect();
+  let mut out = Vec::new();
+  let mut start = 0usize;
+  let mut i = 0usize;
+  while i < chars.len() {
+    let (_, c) = chars[i];
+    if !matches!(c, '.' | '!' | '?' | '…') {
+      i += 1;
+      continue;
+    }
+    let mut end = i + 1;
+    while end < chars.len() && matches!(chars[end].1, '.' | '!' | '?' | '"' | '\'' | '”' | '»' | ')' | ']') {
+      end += 1;
+    }
+    let mut next = end;
+    while next < chars.len() && chars[next].1.is_whitespace() {
+      next += 1;
+    }
+    let starts_sentence = next > end
+      && next < chars.len()
+      && (chars[next].1.is_uppercase() || chars[next].1.is_ascii_digit() || matches!(chars[next].1, '"' | '„' | '«' | '('));
+    if !starts_sentence || (c == '.' && ends_with_abbreviation(&s[chars[start].0..chars[i].0])) {
+      i = end;
+      continue;
+    }
+    let end_byte = chars.get(end).map(|(b, _)| *b).unwrap_or(s.len());
+    let sentence = s[chars[start].0..end_byte].trim();
+    if !sentence.is_empty() {
+      out.push(sentence);
+    }
+    start = next;
+    i = next;
+  }
+  if start < chars.len() {
+    let rest = s[chars[start].0..].trim();
+    if !rest.is_empty() {
+      out.push(rest);
+    }
+  }
+  out
+}
+
+fn ends_with_abbreviation(before_dot: &str) -> bool {
+  let word = before_dot.rsplit(|c: char| c.is_whitespace() || c == '(').next().unwrap_or("");
+  let mut letters = word.chars();
+  if let (Some(first), None) = (letters.next(), letters.next()) {
+    return first.is_alphabetic();
+  }
+  SENTENCE_ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+/// Packs whole sentences into chunks of at most `max_chars`; each new chunk starts with
+/// the trailing sentences of the previous one that fit in `overlap`. Only a sentence
+/// longer than `max_chars` on its own is cut with `chunk_text`.
+fn chunk_sentences(s: &str, max_chars: usize, overlap: usize) -> Vec<String> {
+  if max_chars == 0 {
+    return vec![];
+  }
+  let len = |t: &str| t.chars().count();
+  let mut out = Vec::new();
+  let mut current: Vec<&str> = Vec::new();
+  let mut current_len = 0usize;
+
+  for sentence in split_sentences(s) {
+    let sentence_len = len(sentence);
+    if sentence_len > max_chars {
+      if !current.is_empty() {
+        out.push(current.join(" "));
+      }
+      out.extend(chunk_text(sentence, max_chars, overlap));
+      current.clear();
+      current_len = 0;
+      continue;
+    }
+
+    let joined_len = if current.is_empty() { sentence_len } else { current_len + 1 + sentence_len };
+    if joined_len > max_chars {
+      out.push(current.join(" "));
+      let mut carried: Vec<&str> = Vec::new();
+      let mut carried_len = 0usize;
+      for prev in current.iter().rev() {
+        let next_len = carried_len + len(prev) + usize::from(!carried.is_empty());
+        if next_len > overlap || next_len + 1 + sentence_len > max_chars {
+          break;
+        }
+        carried.insert(0, prev);
+        carried_len = next_len;
+      }
+      current = carried;
+      current_len = carried_len;
+    }
+    current_len += sentence_len + usize::from(!current.is_empty());
+    current.push(sentence);
+  }
+  if !current.is_empty() {
+    out.push(current.join(" "));
+  }
+  out
+}
+
+/// Start of the next chunk: `overlap` chars before `end`, pulled back to the nearest
+/// whitespace within `OVERLAP_LOOKBACK_CHARS` (or, failing that, forward to the next word)
+/// so the overlap doesn't open mid-word.
+fn overlap_start(chars: &[char], prev_start: usize, end: usize, overlap: usize) -> usize {
+  let start = end.saturating_sub(overlap);
+  if start <= prev_start {
+    // The chunk ended on an early boundary, shorter than the overlap; step past it.
+    return end;
+  }
+  if overlap == 0 || chars[start - 1].is_whitespace() {
+    return start;
+  }
+  let floor = start.saturating_sub(OVERLAP_LOOKBACK_CHARS).max(prev_start + 1);
+  (floor..start)
+    .rev()
+    .chain(start + 1..=end)
+    .find(|&idx| chars[idx - 1].is_whitespace())
+    .unwrap_or(start)
+}
+
+fn clean_text(s: &str) -> String {
+  s.replace('\u{0}', " ").trim().to_string()
+}
+
+/// Cleans per-page text from `extract_text_by_pages`; `None` when nothing usable came back.
+fn clean_paged_text(pages: Option<Vec<String>>) -> Option<Vec<String>> {
+  let pages: Vec<String> = pages?.iter().map(|p| clean_text(p)).collect();
+  pages.iter().any(|p| !p.is_empty()).then_some(pages)
+}
+
+/// Splits on form feeds, keeping empty pages so indices stay aligned with the PDF's pages.
+fn split_pages(raw: &str) -> Vec<String> {
+  raw.split('\x0C').map(clean_text).collect()
+}
 
 fn tesseract_bin_path(app: &AppHandle) -> Option<PathBuf> {
   let candidates = [
@@ -532,6 +1960,71 @@ fn tessdata_dir(app: &AppHandle) -> Option<PathBuf> {
   None
 }
 
+/// Language codes with a `.traineddata` file in `dir`, sorted.
+fn traineddata_langs(dir: &Path) -> Vec<String> {
+  let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+  let mut langs: Vec<String> = entries
+    .filter_map(|e| e.ok())
+    .filter_map(|e| {
+      let path = e.path();
+      let is_model = path.extension().and_then(|x| x.to_str()) == Some("traineddata");
+      is_model.then(|| path.file_stem()?.to_str().map(str::to_string)).flatten()
+    })
+    .collect();
+  langs.sort();
+  langs
+}
+
+#[derive(Serialize)]
+pub struct EnvironmentCheck {
+  pub ollama_reachable: bool,
+  pub ollama_version: Option<String>,
+  pub ollama_error: Option<String>,
+  pub vec_extension_found: bool,
+  pub vec_extension_path: Option<String>,
+  pub vec_extension_error: Option<String>,
+  pub tesseract_found: bool,
+  /// Bundled binary, or `tesseract` when only the one on `PATH` answered.
+  pub tesseract_path: Option<String>,
+  pub tessdata_dir: Option<String>,
+  pub tessdata_langs: Vec<String>,
+}
+
+/// Readiness checklist for the UI. Every probe failure is reported as a field, never as an error;
+/// `ollama_version` is the result of pinging `/api/version`, done by the caller.
+pub fn check_environment(app: &AppHandle, ollama_version: Result<String>) -> EnvironmentCheck {
+  let (ollama_version, ollama_error) = match ollama_version {
+    Ok(v) => (Some(v), None),
+    Err(e) => (None, Some(format!("{:#}", e))),
+  };
+  let (vec_extension_path, vec_extension_error) = match vec0_extension_path(app) {
+    Ok(p) => (Some(p.to_string_lossy().to_string()), None),
+    Err(e) => (None, Some(format!("{:#}", e))),
+  };
+  let tesseract_path = match tesseract_bin_path(app) {
+    Some(p) => Some(p.to_string_lossy().to_string()),
+    None => Command::new("tesseract")
+      .arg("--version")
+      .output()
+      .ok()
+      .filter(|out| out.status.success())
+      .map(|_| "tesseract".to_string()),
+  };
+  let tessdata = tessdata_dir(app);
+  EnvironmentCheck {
+    ollama_reachable: ollama_version.is_some(),
+    ollama_version,
+    ollama_error,
+    vec_extension_found: vec_extension_path.is_some(),
+    vec_extension_path,
+    vec_extension_error,
+    tesseract_found: tesseract_path.is_some(),
+    tesseract_path,
+    tessdata_langs: tessdata.as_deref().map(traineddata_langs).unwrap_or_default(),
+    tessdata_dir: tessdata.map(|p| p.to_string_lossy().to_string()),
+  }
+}
+
 #[cfg(unix)]
 fn ensure_executable(path: &Path) -> Result<()> {
   let mut perms = fs::metadata(path)?.permissions();
@@ -543,6 +2036,111 @@ fn ensure_executable(path: &Path) -> Result<()> {
 }
 
 fn run_tesseract(app: &AppHandle, path: &Path, settings: &IndexSettings) -> Result<String> {
+  let lang = if settings.ocr_auto_lang {
+    detect_ocr_lang(app, path, settings)
+  } else {
+    None
+  };
+  let raw = run_tesseract_lang(app, path, settings, lang.as_deref().unwrap_or(&settings.ocr_lang), false)?;
+  Ok(if settings.ocr_reflow { reflow_ocr_text(&raw) } else { raw })
+}
+
+/// Tesseract breaks every printed line; rebuild paragraphs page by page (form feeds are kept).
+fn reflow_ocr_text(raw: &str) -> String {
+  raw
+    .split('\x0C')
+    .map(reflow_ocr_page)
+    .collect::<Vec<_>>()
+    .join("\x0C")
+}
+
+fn reflow_ocr_page(page: &str) -> String {
+  let mut paragraphs: Vec<String> = Vec::new();
+  let mut current = String::new();
+  for line in page.lines() {
+    let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.is_empty() {
+      if !current.is_empty() {
+        paragraphs.push(std::mem::take(&mut current));
+      }
+      continue;
+    }
+    if current.is_empty() {
+      current = line;
+      continue;
+    }
+    let hyphenated = current.ends_with('-')
+      && current.chars().rev().nth(1).is_some_and(char::is_alphabetic)
+      && line.chars().next().is_some_and(char::is_lowercase);
+    if hyphenated {
+      current.pop();
+    } else {
+      current.push(' ');
+    }
+    current.push_str(&line);
+  }
+  if !current.is_empty() {
+    paragraphs.push(current);
+  }
+  paragraphs.join("\n\n")
+}
+
+const OCR_LANG_MIN_CONFIDENCE: f64 = 0.5;
+
+/// OCRs only the first page with the full language combo and returns the single
+/// configured language pack it is written in, or `None` when detection is unsure.
+fn detect_ocr_lang(app: &AppHandle, path: &Path, settings: &IndexSettings) -> Option<String> {
+  let langs: Vec<&str> = settings.ocr_lang.split('+').map(str::trim).filter(|l| !l.is_empty()).collect();
+  if langs.len() < 2 {
+    return None;
+  }
+  let probe = run_tesseract_lang(app, path, settings, &settings.ocr_lang, true).ok()?;
+  pick_ocr_lang(&probe, &langs)
+}
+
+fn pick_ocr_lang(text: &str, langs: &[&str]) -> Option<String> {
+  let info = detect(text)?;
+  if !info.is_reliable() || info.confidence() < OCR_LANG_MIN_CONFIDENCE {
+    return None;
+  }
+  // whatlang codes are ISO 639-3, which is what tesseract names its language packs after.
+  let code = info.lang().code();
+  langs.iter().find(|l| **l == code).map(|l| l.to_string())
+}
+
+fn ocr_concurrency_limit(setting: usize, cpus: usize) -> usize {
+  if setting > 0 { setting } else { cpus.saturating_sub(1).max(1) }
+}
+
+/// Counting semaphore shared by every tesseract call (indexer, watcher worker, language probe).
+struct OcrSlots {
+  running: Mutex<usize>,
+  freed: Condvar,
+}
+
+struct OcrPermit(&'static OcrSlots);
+
+impl Drop for OcrPermit {
+  fn drop(&mut self) {
+    let mut running = self.0.running.lock().unwrap_or_else(|e| e.into_inner());
+    *running -= 1;
+    self.0.freed.notify_one();
+  }
+}
+
+fn acquire_ocr_slot(settings: &IndexSettings) -> OcrPermit {
+  static SLOTS: OcrSlots = OcrSlots { running: Mutex::new(0), freed: Condvar::new() };
+  let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+  let limit = ocr_concurrency_limit(settings.ocr_max_concurrency, cpus);
+  let mut running = SLOTS.running.lock().unwrap_or_else(|e| e.into_inner());
+  while *running >= limit {
+    running = SLOTS.freed.wait(running).unwrap_or_else(|e| e.into_inner());
+  }
+  *running += 1;
+  OcrPermit(&SLOTS)
+}
+
+fn tesseract_command(app: &AppHandle, path: &Path, settings: &IndexSettings, lang: &str, first_page_only: bool) -> Command {
   let mut cmd = if let Some(bin) = tesseract_bin_path(app) {
     #[cfg(unix)]
     let _ = ensure_executable(&bin);
@@ -570,23 +2168,79 @@ fn run_tesseract(app: &AppHandle, path: &Path, settings: &IndexSettings) -> Resu
   cmd.arg(path)
     .arg("stdout")
     .arg("-l")
-    .arg(&settings.ocr_lang)
+    .arg(lang)
     .arg("--dpi")
     .arg(settings.ocr_dpi.to_string());
 
+  if first_page_only {
+    cmd.arg("-c").arg("tessedit_page_number=0");
+  }
+
   if let Some(tessdata) = tessdata_dir(app) {
     cmd.arg("--tessdata-dir").arg(tessdata);
   }
+  cmd
+}
 
-  let out = cmd.output().context("Failed to run tesseract")?;
+fn run_tesseract_lang(app: &AppHandle, path: &Path, settings: &IndexSettings, lang: &str, first_page_only: bool) -> Result<String> {
+  let mut cmd = tesseract_command(app, path, settings, lang, first_page_only);
+  let _permit = acquire_ocr_slot(settings);
+  let out = cmd.output().context(OcrFailure("Failed to run tesseract".into()))?;
   if !out.status.success() {
     let err = String::from_utf8_lossy(&out.stderr);
-    anyhow::bail!("tesseract failed: {err}");
+    return Err(anyhow::Error::new(OcrFailure(format!("tesseract failed: {err}"))));
   }
 
   Ok(String::from_utf8_lossy(&out.stdout).to_string())
 }
 
+#[derive(Serialize)]
+pub struct OcrTestResult {
+  pub success: bool,
+  /// OCR output after the same reflow indexing applies; empty on failure.
+  pub text: String,
+  /// Bundled binary when found, otherwise `tesseract` from `PATH`.
+  pub binary: String,
+  pub tessdata_dir: Option<String>,
+  pub lang: String,
+  pub args: Vec<String>,
+  pub stderr: String,
+  pub elapsed_ms: u64,
+}
+
+/// Runs tesseract on one file exactly as indexing would and reports how it was invoked.
+/// Never touches the database.
+pub fn test_ocr(app: &AppHandle, path: String, settings: IndexSettings) -> Result<OcrTestResult> {
+  let path = PathBuf::from(path);
+  anyhow::ensure!(path.is_file(), "{} is not a file", path.display());
+  let lang = if settings.ocr_auto_lang { detect_ocr_lang(app, &path, &settings) } else { None }
+    .unwrap_or_else(|| settings.ocr_lang.clone());
+  let mut cmd = tesseract_command(app, &path, &settings, &lang, false);
+  let binary = cmd.get_program().to_string_lossy().to_string();
+  let args = cmd.get_args().map(|a| a.to_string_lossy().to_string()).collect();
+  let tessdata_dir = tessdata_dir(app).map(|p| p.to_string_lossy().to_string());
+
+  let started = std::time::Instant::now();
+  let output = {
+    let _permit = acquire_ocr_slot(&settings);
+    cmd.output()
+  };
+  let elapsed_ms = started.elapsed().as_millis() as u64;
+  let (success, text, stderr) = match output {
+    Ok(out) => {
+      let stderr = String::from_utf8_lossy(&out.stderr).to_string();
+      if out.status.success() {
+        let raw = String::from_utf8_lossy(&out.stdout).to_string();
+        (true, if settings.ocr_reflow { reflow_ocr_text(&raw) } else { raw }, stderr)
+      } else {
+        (false, String::new(), stderr)
+      }
+    }
+    Err(e) => (false, String::new(), format!("failed to start {binary}: {e}")),
+  };
+  Ok(OcrTestResult { success, text, binary, tessdata_dir, lang, args, stderr, elapsed_ms })
+}
+
 fn tesseract_base_dir(bin: &Path) -> Option<PathBuf> {
   let parent = bin.parent()?;
   if parent.file_name().and_then(|p| p.to_str()) == Some("bin") {
@@ -619,34 +2273,266 @@ where
   result
 }
 
-fn extract_pdf_text(app: &AppHandle, path: &Path, settings: &IndexSettings) -> Result<Vec<String>> {
-  let raw = match with_silenced_panic(|| pdf_extract::extract_text(path)) {
-    Ok(Ok(text)) => Ok(text),
-    Ok(Err(e)) => Err(anyhow::anyhow!(e)),
-    Err(_) => Err(anyhow::anyhow!("pdf_extract panicked")),
+fn pdfium_library_path(app: &AppHandle) -> Option<PathBuf> {
+  let candidates = [
+    "pdfium.dll",
+    "libpdfium.dylib",
+    "libpdfium.so",
+    "resources/pdfium.dll",
+    "resources/libpdfium.dylib",
+    "resources/libpdfium.so",
+  ];
+  for rel in candidates {
+    if let Ok(p) = app.path().resolve(rel, BaseDirectory::Resource) {
+      if p.exists() {
+        return Some(p);
+      }
+    }
   }
-  .with_context(|| format!("pdf extract failed for {}", path.display()));
+  None
+}
 
-  let raw = match raw {
-    Ok(text) => text,
-    Err(e) => {
-      if settings.ocr_enabled {
-        let ocr = run_tesseract(app, path, settings)
-          .with_context(|| format!("tesseract OCR failed for {}", path.display()))?;
-        return Ok(split_pages(&ocr));
+/// pdfium can only be bound once per process, so the handle (or the failure to find it) is cached.
+fn pdfium(app: &AppHandle) -> Option<&'static Pdfium> {
+  static PDFIUM: OnceLock<Option<Pdfium>> = OnceLock::new();
+  PDFIUM
+    .get_or_init(|| {
+      let bindings = match pdfium_library_path(app) {
+        Some(path) => Pdfium::bind_to_library(path),
+        None => Pdfium::bind_to_system_library(),
+      };
+      match bindings {
+        Ok(bindings) => Some(Pdfium::new(bindings)),
+        Err(e) => {
+          eprintln!("pdfium unavailable: {e}");
+          None
+        }
       }
-      return Err(e);
+    })
+    .as_ref()
+}
+
+fn extract_pdf_text_pdfium(app: &AppHandle, path: &Path) -> Result<Vec<String>> {
+  let pdfium = pdfium(app).context("pdfium library not found")?;
+  let doc = pdfium
+    .load_pdf_from_file(path, None)
+    .map_err(|e| anyhow::anyhow!("pdfium failed to open {}: {e}", path.display()))?;
+  let mut pages = Vec::new();
+  for page in doc.pages().iter() {
+    let text = page.text().map(|t| t.all()).unwrap_or_default();
+    pages.push(clean_text(&text));
+  }
+  Ok(pages)
+}
+
+fn form_field_value(field: &PdfFormField) -> Option<String> {
+  if let Some(f) = field.as_text_field() {
+    return f.value();
+  }
+  if let Some(f) = field.as_combo_box_field() {
+    return f.value();
+  }
+  if let Some(f) = field.as_list_box_field() {
+    return f.value();
+  }
+  None
+}
+
+/// Filled-in form values and comment text for one page, tagged so they read differently from body text.
+fn pdf_page_extras(page: &PdfPage) -> Vec<String> {
+  let mut out = vec![];
+  for annot in page.annotations().iter() {
+    if let Some(field) = annot.as_form_field() {
+      let value = form_field_value(field).map(|v| clean_text(&v)).unwrap_or_default();
+      if value.is_empty() {
+        continue;
+      }
+      match field.name().map(|n| clean_text(&n)).filter(|n| !n.is_empty()) {
+        Some(name) => out.push(format!("[Form field] {name}: {value}")),
+        None => out.push(format!("[Form field] {value}")),
+      }
+      continue;
+    }
+    // Popups repeat their parent's contents and links carry no prose.
+    if matches!(annot.annotation_type(), PdfPageAnnotationType::Popup | PdfPageAnnotationType::Link) {
+      continue;
+    }
+    if let Some(text) = annot.contents().map(|c| clean_text(&c)).filter(|c| !c.is_empty()) {
+      out.push(format!("[Annotation] {text}"));
+    }
+  }
+  out
+}
+
+/// Per-page form/annotation text, or `None` when pdfium is unavailable or the file has none.
+fn extract_pdf_extras(app: &AppHandle, path: &Path) -> Option<Vec<Vec<String>>> {
+  let pdfium = pdfium(app)?;
+  let doc = pdfium.load_pdf_from_file(path, None).ok()?;
+  let pages: Vec<Vec<String>> = doc.pages().iter().map(|page| pdf_page_extras(&page)).collect();
+  if pages.iter().all(|p| p.is_empty()) {
+    return None;
+  }
+  Some(pages)
+}
+
+/// Appends extras to the page with the same index; anything past the end goes onto the last page.
+fn append_page_extras(pages: &mut Vec<String>, extras: Vec<Vec<String>>) {
+  for (i, lines) in extras.into_iter().enumerate() {
+    if lines.is_empty() {
+      continue;
+    }
+    let block = lines.join("\n");
+    if pages.is_empty() {
+      pages.push(block);
+      continue;
+    }
+    let idx = i.min(pages.len() - 1);
+    pages[idx].push_str("\n\n");
+    pages[idx].push_str(&block);
+  }
+}
+
+fn pages_char_count(pages: &[String]) -> usize {
+  pages.iter().map(|p| p.chars().count()).sum()
+}
+
+fn extract_pdf_text(app: &AppHandle, path: &Path, settings: &IndexSettings) -> Result<ExtractedText> {
+  let (mut pages, page_report) = extract_pdf_body_text(app, path, settings)?;
+  if let Some(extras) = extract_pdf_extras(app, path) {
+    append_page_extras(&mut pages, extras);
+  }
+  let origins = page_report.iter().map(|d| d.origin).collect();
+  Ok(ExtractedText { pages, page_report, origins })
+}
+
+fn text_layer_report(pages: &[String]) -> Vec<PageDensity> {
+  pages
+    .iter()
+    .enumerate()
+    .map(|(i, p)| PageDensity { page: i + 1, chars: p.chars().count(), origin: PageOrigin::Text })
+    .collect()
+}
+
+/// Chunks every non-blank page; the page in each chunk's metadata is its index in `pages`,
+/// so blank pages still count towards the numbering.
+#[allow(clippy::type_complexity)]
+fn chunk_pages(
+  pages: &[String],
+  origins: &[PageOrigin],
+  kind: DocumentKind,
+  code_lang: Option<String>,
+  settings: &IndexSettings,
+) -> (Vec<(i32, i32, Option<String>, PageOrigin)>, Vec<String>) {
+  let mut chunk_meta = Vec::new();
+  let mut chunk_texts = Vec::new();
+  for (pi, page_text) in pages.iter().enumerate() {
+    if page_text.is_empty() {
+      continue;
+    }
+    let chunks = if kind.is_tabular() {
+      chunk_rows(page_text, settings.chunk_size, settings.effective_chunk_overlap())
+    } else if kind == DocumentKind::Code {
+      chunk_code(page_text, settings.chunk_size, settings.effective_chunk_overlap())
+    } else {
+      settings.chunk_strategy.chunk(page_text, settings.chunk_size, settings.effective_chunk_overlap())
+    };
+    let chunks = drop_short_chunks(chunks, settings.min_chunk_chars);
+    for (ci, ch) in chunks.into_iter().enumerate() {
+      let lang = code_lang.clone().or_else(|| detect_lang_code(&ch));
+      chunk_meta.push((pi as i32, ci as i32, lang, origins.get(pi).copied().unwrap_or(PageOrigin::Text)));
+      chunk_texts.push(ch);
+    }
+  }
+  (chunk_meta, chunk_texts)
+}
+
+/// Per page, keeps whichever of the text layer and OCR output has more text.
+fn merge_ocr_pages(text_layer: &[String], ocr: Vec<String>) -> (Vec<String>, Vec<PageDensity>) {
+  let len = text_layer.len().max(ocr.len());
+  let mut ocr = ocr.into_iter();
+  let mut pages = Vec::with_capacity(len);
+  let mut report = Vec::with_capacity(len);
+  for i in 0..len {
+    let text = text_layer.get(i).cloned().unwrap_or_default();
+    let ocr_page = ocr.next().unwrap_or_default();
+    let chars = text.chars().count();
+    let (page, origin) = if ocr_page.chars().count() > chars {
+      (ocr_page, PageOrigin::Ocr)
+    } else {
+      (text, PageOrigin::Text)
+    };
+    pages.push(page);
+    report.push(PageDensity { page: i + 1, chars, origin });
+  }
+  (pages, report)
+}
+
+/// Page texts (empty pages kept for alignment) plus the per-page density report.
+/// The PDF's own text layer, one entry per page with blank pages kept.
+fn extract_pdf_text_layer(path: &Path) -> Result<Vec<String>> {
+  let by_pages = with_silenced_panic(|| pdf_extract::extract_text_by_pages(path)).ok().and_then(|r| r.ok());
+  match clean_paged_text(by_pages) {
+    Some(pages) => Ok(pages),
+    // Form feeds are only a heuristic; many producers never emit them.
+    None => match with_silenced_panic(|| pdf_extract::extract_text(path)) {
+      Ok(Ok(text)) => Ok(split_pages(&text)),
+      Ok(Err(e)) => Err(anyhow::anyhow!(e)),
+      Err(_) => Err(anyhow::anyhow!("pdf_extract panicked")),
+    },
+  }
+  .with_context(|| format!("pdf extract failed for {}", path.display()))
+}
+
+fn extract_pdf_body_text(app: &AppHandle, path: &Path, settings: &IndexSettings) -> Result<(Vec<String>, Vec<PageDensity>)> {
+  let native = extract_pdf_text_layer(path);
+
+  if let Ok(pages) = &native {
+    if pages_char_count(pages) >= settings.ocr_min_chars {
+      let report = text_layer_report(pages);
+      return native.map(|pages| (pages, report));
+    }
+  }
+
+  // pdfium copes with many PDFs that trip up pdf_extract and is far cheaper than OCR.
+  let pdfium = match extract_pdf_text_pdfium(app, path) {
+    Ok(pages) => Some(pages),
+    Err(e) => {
+      eprintln!("pdfium fallback skipped: {e:#}");
+      None
+    }
+  };
+  if let Some(pages) = pdfium.clone() {
+    if pages_char_count(&pages) >= settings.ocr_min_chars {
+      let report = text_layer_report(&pages);
+      return Ok((pages, report));
     }
+  }
+
+  // Keep the richer text layer in case OCR is disabled or fails.
+  let text_layer = match (native, pdfium) {
+    (Ok(a), Some(b)) => Ok(if pages_char_count(&b) > pages_char_count(&a) { b } else { a }),
+    (Ok(a), None) => Ok(a),
+    (Err(_), Some(b)) => Ok(b),
+    (Err(e), None) => Err(e),
   };
 
-  let cleaned = clean_text(&raw);
-  if settings.ocr_enabled && cleaned.chars().count() < settings.ocr_min_chars {
-    if let Ok(ocr) = run_tesseract(app, path, settings) {
-      return Ok(split_pages(&ocr));
+  if settings.ocr_enabled {
+    match run_tesseract(app, path, settings) {
+      Ok(ocr) => {
+        let text_pages = text_layer.as_deref().unwrap_or(&[]);
+        return Ok(merge_ocr_pages(text_pages, split_pages(&ocr)));
+      }
+      Err(e) if text_layer.is_err() => {
+        return Err(e.context(format!("tesseract OCR failed for {}", path.display())));
+      }
+      Err(_) => {}
     }
   }
 
-  Ok(split_pages(&cleaned))
+  text_layer.map(|pages| {
+    let report = text_layer_report(&pages);
+    (pages, report)
+  })
 }
 
 fn extract_docx_text(path: &Path) -> Result<String> {
@@ -664,12 +2550,10 @@ fn extract_docx_text(path: &Path) -> Result<String> {
   loop {
     match reader.read_event_into(&mut buf) {
       Ok(Event::Text(e)) => {
-        out.push_str(&e.unescape()?.to_string());
+        out.push_str(&e.unescape()?);
       }
-      Ok(Event::End(e)) => {
-        if e.name().as_ref() == b"w:p" {
-          out.push('\n');
-        }
+      Ok(Event::End(e)) if e.name().as_ref() == b"w:p" => {
+        out.push('\n');
       }
       Ok(Event::Eof) => break,
       Err(e) => return Err(anyhow::anyhow!("docx parse error: {e}")),
@@ -681,964 +2565,4538 @@ fn extract_docx_text(path: &Path) -> Result<String> {
   Ok(out)
 }
 
-fn extract_text_for_document(app: &AppHandle, doc: &DocumentCandidate, settings: &IndexSettings) -> Result<Vec<String>> {
-  match doc.kind {
-    DocumentKind::Pdf => extract_pdf_text(app, &doc.path, settings),
-    DocumentKind::Docx => {
-      let text = extract_docx_text(&doc.path)?;
-      Ok(vec![clean_text(&text)])
+/// Title and author taken from the document itself, stored in `doc_meta`.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct DocMeta {
+  title: Option<String>,
+  author: Option<String>,
+}
+
+impl DocMeta {
+  fn is_empty(&self) -> bool {
+    self.title.is_none() && self.author.is_none()
+  }
+}
+
+fn meta_field(value: &str) -> Option<String> {
+  let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+  (!value.is_empty()).then_some(value)
+}
+
+/// First ATX `# ` heading, ignoring headings inside fenced code blocks.
+fn markdown_title(text: &str) -> Option<String> {
+  let mut in_fence = false;
+  for line in text.lines() {
+    let line = line.trim_start();
+    if line.starts_with("```") || line.starts_with("~~~") {
+      in_fence = !in_fence;
+      continue;
     }
-    DocumentKind::Txt | DocumentKind::Md => {
-      let raw = fs::read(&doc.path)?;
-      let text = String::from_utf8_lossy(&raw).to_string();
-      Ok(vec![clean_text(&text)])
+    if in_fence {
+      continue;
+    }
+    if let Some(rest) = line.strip_prefix("# ") {
+      return meta_field(rest.trim_end().trim_end_matches('#'));
     }
   }
+  None
 }
 
-fn detect_lang_code(text: &str) -> Option<String> {
-  detect(text).map(|i| i.lang().code().to_string())
+/// `dc:title` and `dc:creator` from a docx `docProps/core.xml`.
+fn docx_core_props(xml: &str) -> Result<DocMeta> {
+  let mut reader = Reader::from_str(xml);
+  reader.trim_text(true);
+  let mut buf = Vec::new();
+  let mut meta = DocMeta::default();
+  let mut field: Option<bool> = None;
+  loop {
+    match reader.read_event_into(&mut buf) {
+      Ok(Event::Start(e)) => {
+        field = match e.name().as_ref() {
+          b"dc:title" => Some(true),
+          b"dc:creator" => Some(false),
+          _ => None,
+        };
+      }
+      Ok(Event::Text(e)) => {
+        let value = meta_field(&e.unescape()?);
+        match field {
+          Some(true) if meta.title.is_none() => meta.title = value,
+          Some(false) if meta.author.is_none() => meta.author = value,
+          _ => {}
+        }
+      }
+      Ok(Event::End(_)) => field = None,
+      Ok(Event::Eof) => break,
+      Err(e) => return Err(anyhow::anyhow!("docx core.xml parse error: {e}")),
+      _ => {}
+    }
+    buf.clear();
+  }
+  Ok(meta)
 }
 
-fn ollama_embed_batch_size() -> usize {
-  std::env::var("OLLAMA_EMBED_BATCH")
-    .ok()
-    .and_then(|v| v.parse::<usize>().ok())
-    .filter(|v| *v > 0)
-    .unwrap_or(4)
+fn docx_metadata(path: &Path) -> Result<DocMeta> {
+  let file = fs::File::open(path)?;
+  let mut archive = ZipArchive::new(file)?;
+  let mut core = archive.by_name("docProps/core.xml")?;
+  let mut xml = String::new();
+  core.read_to_string(&mut xml)?;
+  docx_core_props(&xml)
 }
 
-fn ollama_embed_fallback_chars() -> usize {
-  // OLLAMA_EMBED_FALLBACK_CHARS caps subchunk size when a chunk times out or is too large.
-  std::env::var("OLLAMA_EMBED_FALLBACK_CHARS")
-    .ok()
-    .and_then(|v| v.parse::<usize>().ok())
-    .filter(|v| *v > 0)
-    .unwrap_or(800)
+/// `/Title` and `/Author` from the PDF info dictionary.
+fn pdf_metadata(app: &AppHandle, path: &Path) -> Option<DocMeta> {
+  let pdfium = pdfium(app)?;
+  let doc = pdfium.load_pdf_from_file(path, None).ok()?;
+  let tag = |t| doc.metadata().get(t).and_then(|tag| meta_field(tag.value()));
+  Some(DocMeta { title: tag(PdfDocumentMetadataTagType::Title), author: tag(PdfDocumentMetadataTagType::Author) })
 }
 
-#[derive(Clone, Copy, Debug)]
-enum FallbackStrategy {
-  Average,
-  First,
+/// Metadata for standalone files; archive entries and other kinds only get the file-name
+/// fallback at query time.
+fn document_metadata(app: &AppHandle, doc: &DocumentCandidate, pages: &[String]) -> DocMeta {
+  if doc.entry.is_some() {
+    return DocMeta::default();
+  }
+  match doc.kind {
+    DocumentKind::Pdf => pdf_metadata(app, &doc.path).unwrap_or_default(),
+    DocumentKind::Docx => docx_metadata(&doc.path).unwrap_or_default(),
+    DocumentKind::Md => DocMeta { title: pages.first().and_then(|p| markdown_title(p)), author: None },
+    _ => DocMeta::default(),
+  }
 }
 
-fn ollama_embed_fallback_strategy() -> FallbackStrategy {
-  // OLLAMA_EMBED_FALLBACK_STRATEGY controls how split embeddings are combined:
-  // - average: combines subchunks (better coverage, slower)
-  // - first: uses the first subchunk only (faster, less coverage)
-  match std::env::var("OLLAMA_EMBED_FALLBACK_STRATEGY").ok().as_deref() {
-    Some(raw) if raw.eq_ignore_ascii_case("first") => FallbackStrategy::First,
-    _ => FallbackStrategy::Average,
-  }
-}
-
-fn is_reqwest_timeout(err: &anyhow::Error) -> bool {
-  err
-    .downcast_ref::<reqwest::Error>()
-    .map(|e| e.is_timeout())
-    .unwrap_or(false)
-}
-
-fn is_ollama_input_too_large(err: &anyhow::Error) -> bool {
-  if let Some(http_err) = err.downcast_ref::<OllamaHttpError>() {
-    if http_err.status == StatusCode::PAYLOAD_TOO_LARGE {
-      return true;
+const HTML_BLOCK_TAGS: &[&str] = &[
+  "p", "br", "div", "li", "tr", "h1", "h2", "h3", "h4", "h5", "h6", "section", "article", "blockquote", "pre", "table",
+];
+
+/// Strips tags with a tolerant scanner rather than an XML parser, since saved pages are rarely
+/// well-formed. `<script>`/`<style>` bodies and comments are dropped; an unterminated tag
+/// leaves the rest of the input as plain text.
+fn extract_html_text(html: &str) -> String {
+  let mut out = String::with_capacity(html.len() / 2);
+  let lower = html.to_ascii_lowercase();
+  let mut i = 0;
+  while i < html.len() {
+    let Some(rel) = html[i..].find('<') else {
+      out.push_str(&html[i..]);
+      break;
+    };
+    out.push_str(&html[i..i + rel]);
+    let start = i + rel;
+    if lower[start..].starts_with("<!--") {
+      i = lower[start..].find("-->").map(|e| start + e + 3).unwrap_or(html.len());
+      continue;
     }
-    if matches!(http_err.status, StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY) {
-      let body = http_err.body.to_lowercase();
-      if body.contains("context")
-        && (body.contains("length") || body.contains("too long") || body.contains("limit"))
-      {
-        return true;
-      }
-      if body.contains("input")
-        && (body.contains("too long") || body.contains("limit") || body.contains("tokens"))
-      {
-        return true;
-      }
+    let Some(end) = html[start..].find('>').map(|e| start + e) else {
+      out.push_str(&html[start..]);
+      break;
+    };
+    let tag = lower[start + 1..end].trim_start_matches('/');
+    let name: String = tag.chars().take_while(|c| c.is_ascii_alphanumeric()).collect();
+    i = end + 1;
+    if (name == "script" || name == "style") && !lower[start + 1..].starts_with('/') {
+      let close = format!("</{name}");
+      i = lower[i..].find(&close).map(|c| i + c).unwrap_or(html.len());
+      continue;
+    }
+    if HTML_BLOCK_TAGS.contains(&name.as_str()) {
+      out.push('\n');
     }
   }
 
-  let msg = err.to_string().to_lowercase();
-  if msg.contains("context") && (msg.contains("length") || msg.contains("too long") || msg.contains("limit")) {
-    return true;
-  }
-  if msg.contains("input") && (msg.contains("too long") || msg.contains("limit") || msg.contains("tokens")) {
-    return true;
+  let decoded = decode_html_entities(&out);
+  let mut text = String::new();
+  let mut blank = false;
+  for line in decoded.lines() {
+    let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.is_empty() {
+      blank = !text.is_empty();
+      continue;
+    }
+    if !text.is_empty() {
+      text.push_str(if blank { "\n\n" } else { "\n" });
+    }
+    blank = false;
+    text.push_str(&line);
   }
-  false
+  text
 }
 
-fn is_embed_fallback_err(err: &anyhow::Error) -> bool {
-  is_reqwest_timeout(err) || is_ollama_input_too_large(err)
+/// Destinations whose text is metadata or binary, never body text.
+const RTF_SKIP_DESTINATIONS: &[&str] = &[
+  "fonttbl", "colortbl", "stylesheet", "info", "pict", "object", "objdata", "themedata", "colorschememapping",
+  "datastore", "latentstyles", "listtable", "listoverridetable", "rsidtbl", "generator", "fldinst", "filetbl",
+  "revtbl", "xmlnstbl", "header", "footer", "headerl", "headerr", "footerl", "footerr", "pgdsctbl",
+];
+
+/// Text being assembled by `extract_rtf_text`. 8-bit characters are buffered and decoded
+/// together, so double-byte code pages keep lead and trail bytes in one character.
+struct RtfText {
+  text: String,
+  bytes: Vec<u8>,
+  encoding: &'static Encoding,
 }
 
-fn average_embeddings(embeds: &[Vec<f32>]) -> Option<Vec<f32>> {
-  if embeds.is_empty() {
-    return None;
-  }
-  let dim = embeds[0].len();
-  if dim == 0 {
-    return None;
+impl RtfText {
+  fn flush(&mut self) {
+    if !self.bytes.is_empty() {
+      let (decoded, _) = self.encoding.decode_without_bom_handling(&self.bytes);
+      self.text.push_str(&decoded);
+      self.bytes.clear();
+    }
   }
-  let mut out = vec![0.0f32; dim];
-  for emb in embeds {
-    if emb.len() != dim {
-      return None;
+
+  fn push(&mut self, pending: &mut usize, skip: bool, c: char) {
+    if *pending > 0 {
+      *pending -= 1;
+    } else if !skip {
+      self.flush();
+      self.text.push(c);
     }
-    for (i, v) in emb.iter().enumerate() {
-      out[i] += *v;
+  }
+
+  fn push_byte(&mut self, pending: &mut usize, skip: bool, b: u8) {
+    if *pending > 0 {
+      *pending -= 1;
+    } else if !skip {
+      self.bytes.push(b);
     }
   }
-  let denom = embeds.len() as f32;
-  for v in &mut out {
-    *v /= denom;
+}
+
+/// Encoding for an `\ansicpgN` code page; unknown pages fall back to Windows-1252.
+fn rtf_code_page(page: i64) -> &'static Encoding {
+  match page {
+    874 => encoding_rs::WINDOWS_874,
+    932 => encoding_rs::SHIFT_JIS,
+    936 => encoding_rs::GBK,
+    949 => encoding_rs::EUC_KR,
+    950 => encoding_rs::BIG5,
+    1250 => encoding_rs::WINDOWS_1250,
+    1251 => encoding_rs::WINDOWS_1251,
+    1253 => encoding_rs::WINDOWS_1253,
+    1254 => encoding_rs::WINDOWS_1254,
+    1255 => encoding_rs::WINDOWS_1255,
+    1256 => encoding_rs::WINDOWS_1256,
+    1257 => encoding_rs::WINDOWS_1257,
+    1258 => encoding_rs::WINDOWS_1258,
+    10000 => encoding_rs::MACINTOSH,
+    _ => encoding_rs::WINDOWS_1252,
   }
-  Some(out)
 }
 
-fn embed_batch_with_retry(ollama: &Ollama, embed_model: &str, batch: &[String]) -> Result<Vec<Vec<f32>>> {
-  let mut attempts = 0;
-  loop {
-    match ollama.embed(embed_model, batch.to_vec()) {
-      Ok(embeds) => return Ok(embeds),
-      Err(err) => {
-        if is_reqwest_timeout(&err) && batch.len() > 1 {
-          let mid = batch.len() / 2;
-          let left = embed_batch_with_retry(ollama, embed_model, &batch[..mid])?;
-          let right = embed_batch_with_retry(ollama, embed_model, &batch[mid..])?;
-          let mut out = left;
-          out.extend(right);
-          return Ok(out);
+/// Minimal RTF reader: tracks groups to drop metadata destinations (`\fonttbl`, `\pict`, any
+/// `\*` group), maps `\par`/`\line` to newlines, and decodes `\uN` (skipping its `\ucN`
+/// fallback chars) and `\'hh` escapes. 8-bit text uses the `\ansicpgN` code page
+/// (Windows-1252 when absent).
+fn extract_rtf_text(raw: &[u8]) -> Result<String> {
+  let start = raw.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(raw.len());
+  anyhow::ensure!(raw[start..].starts_with(b"{\\rtf"), "not an RTF document");
+
+  // (skip, unicode fallback chars) per open group
+  let mut stack: Vec<(bool, usize)> = Vec::new();
+  let (mut skip, mut uc) = (false, 1usize);
+  let mut pending_fallback = 0usize;
+  let mut out = RtfText { text: String::new(), bytes: Vec::new(), encoding: encoding_rs::WINDOWS_1252 };
+  let mut i = start;
+
+  while i < raw.len() {
+    let b = raw[i];
+    match b {
+      b'{' => {
+        stack.push((skip, uc));
+        pending_fallback = 0;
+        i += 1;
+      }
+      b'}' => {
+        let Some((prev_skip, prev_uc)) = stack.pop() else { break };
+        (skip, uc) = (prev_skip, prev_uc);
+        pending_fallback = 0;
+        i += 1;
+      }
+      b'\\' => {
+        i += 1;
+        let Some(&next) = raw.get(i) else { break };
+        if next.is_ascii_alphabetic() {
+          let word_start = i;
+          while i < raw.len() && raw[i].is_ascii_alphabetic() {
+            i += 1;
+          }
+          let word = std::str::from_utf8(&raw[word_start..i]).unwrap_or("");
+          let num_start = i;
+          if i < raw.len() && raw[i] == b'-' {
+            i += 1;
+          }
+          while i < raw.len() && raw[i].is_ascii_digit() {
+            i += 1;
+          }
+          let param: Option<i64> = std::str::from_utf8(&raw[num_start..i]).ok().and_then(|n| n.parse().ok());
+          if i < raw.len() && raw[i] == b' ' {
+            i += 1;
+          }
+          match word {
+            "par" | "line" | "sect" | "page" | "row" => out.push(&mut 0, skip, '\n'),
+            "tab" | "cell" => out.push(&mut 0, skip, '\t'),
+            "emdash" => out.push(&mut pending_fallback, skip, '—'),
+            "endash" => out.push(&mut pending_fallback, skip, '–'),
+            "bullet" => out.push(&mut pending_fallback, skip, '•'),
+            "lquote" => out.push(&mut pending_fallback, skip, '‘'),
+            "rquote" => out.push(&mut pending_fallback, skip, '’'),
+            "ldblquote" => out.push(&mut pending_fallback, skip, '“'),
+            "rdblquote" => out.push(&mut pending_fallback, skip, '”'),
+            "uc" => uc = param.unwrap_or(1).max(0) as usize,
+            "ansicpg" => {
+              out.flush();
+              out.encoding = rtf_code_page(param.unwrap_or(1252));
+            }
+            "mac" => {
+              out.flush();
+              out.encoding = encoding_rs::MACINTOSH;
+            }
+            "u" => {
+              let code = param.unwrap_or(0);
+              let code = if code < 0 { code + 65536 } else { code };
+              if let Some(c) = char::from_u32(code as u32) {
+                out.push(&mut 0, skip, c);
+              }
+              pending_fallback = uc;
+            }
+            "bin" => i += param.unwrap_or(0).max(0) as usize,
+            w if RTF_SKIP_DESTINATIONS.contains(&w) => skip = true,
+            _ => {}
+          }
+        } else {
+          i += 1;
+          match next {
+            b'*' => skip = true,
+            b'\'' => {
+              let hex = raw.get(i..i + 2).and_then(|h| std::str::from_utf8(h).ok());
+              if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push_byte(&mut pending_fallback, skip, byte);
+                i += 2;
+              }
+            }
+            b'\\' | b'{' | b'}' => out.push(&mut pending_fallback, skip, next as char),
+            b'~' => out.push(&mut pending_fallback, skip, ' '),
+            b'_' => out.push(&mut pending_fallback, skip, '-'),
+            b'\n' | b'\r' => out.push(&mut 0, skip, '\n'),
+            _ => {}
+          }
         }
-        attempts += 1;
-        if attempts >= 2 {
-          return Err(err);
+      }
+      b'\r' | b'\n' => i += 1,
+      // Literal trail byte of a double-byte character whose lead byte came before it.
+      _ if b < 0x80 && !out.bytes.is_empty() && !out.encoding.is_single_byte() => {
+        out.push_byte(&mut pending_fallback, skip, b);
+        i += 1;
+      }
+      _ => {
+        // Raw text bytes; multi-byte UTF-8 (non-standard but common from converters) is kept whole.
+        let len = match b {
+          0xC0..=0xDF => 2,
+          0xE0..=0xEF => 3,
+          0xF0..=0xF7 => 4,
+          _ => 1,
+        };
+        let end = (i + len).min(raw.len());
+        match std::str::from_utf8(&raw[i..end]) {
+          Ok(text) => {
+            for c in text.chars() {
+              out.push(&mut pending_fallback, skip, c);
+            }
+            i = end;
+          }
+          Err(_) => {
+            out.push_byte(&mut pending_fallback, skip, b);
+            i += 1;
+          }
         }
-        std::thread::sleep(Duration::from_millis(400));
       }
     }
   }
+  out.flush();
+  Ok(out.text)
 }
 
-fn embed_chunk_with_fallback(ollama: &Ollama, embed_model: &str, text: &str) -> Result<Option<Vec<f32>>> {
-  match ollama.embed(embed_model, text) {
-    Ok(embeds) => Ok(embeds.into_iter().next()),
-    Err(err) => {
-      if !is_embed_fallback_err(&err) {
-        return Err(err);
+fn decode_html_entities(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  let mut rest = s;
+  while let Some(amp) = rest.find('&') {
+    out.push_str(&rest[..amp]);
+    let tail = &rest[amp..];
+    let decoded = tail.find(';').filter(|&semi| semi <= 10).and_then(|semi| {
+      let entity = &tail[1..semi];
+      let c = match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => entity
+          .strip_prefix("#x")
+          .or_else(|| entity.strip_prefix("#X"))
+          .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+          .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+          .and_then(char::from_u32),
+      }?;
+      Some((c, semi))
+    });
+    match decoded {
+      Some((c, semi)) => {
+        out.push(c);
+        rest = &tail[semi + 1..];
+      }
+      None => {
+        out.push('&');
+        rest = &tail[1..];
       }
+    }
+  }
+  out.push_str(rest);
+  out
+}
 
-      let fallback_chars = ollama_embed_fallback_chars();
-      let char_len = text.chars().count();
-      if fallback_chars == 0 || char_len <= fallback_chars {
-        eprintln!("embed fallback: chunk failed (len {char_len}), skipping");
-        return Ok(None);
+fn read_zip_string(archive: &mut ZipArchive<fs::File>, name: &str) -> Result<String> {
+  let mut entry = archive.by_name(name).with_context(|| format!("missing {name}"))?;
+  let mut raw = Vec::new();
+  entry.read_to_end(&mut raw)?;
+  Ok(String::from_utf8_lossy(&raw).to_string())
+}
+
+fn percent_decode(s: &str) -> String {
+  let bytes = s.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' && i + 2 < bytes.len() {
+      if let Some(b) = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+        out.push(b);
+        i += 3;
+        continue;
       }
+    }
+    out.push(bytes[i]);
+    i += 1;
+  }
+  String::from_utf8_lossy(&out).to_string()
+}
 
-      let parts = chunk_text(text, fallback_chars, 0);
-      if parts.len() <= 1 {
-        eprintln!("embed fallback: split produced no subchunks, skipping");
-        return Ok(None);
+/// `full-path` of the first rootfile in META-INF/container.xml.
+fn epub_rootfile(container_xml: &str) -> Option<String> {
+  let mut reader = Reader::from_str(container_xml);
+  loop {
+    match reader.read_event() {
+      Ok(Event::Start(e)) | Ok(Event::Empty(e)) if e.local_name().as_ref() == b"rootfile" => {
+        let attr = e.try_get_attribute("full-path").ok()??;
+        return attr.unescape_value().ok().map(|v| v.to_string());
       }
+      Ok(Event::Eof) | Err(_) => return None,
+      _ => {}
+    }
+  }
+}
 
-      let mut embeds = Vec::new();
-      for part in parts {
-        match ollama.embed(embed_model, part) {
-          Ok(mut out) => {
-            if let Some(emb) = out.pop() {
-              embeds.push(emb);
+/// Chapter paths in spine order, resolved against the OPF file's directory.
+fn epub_spine(opf_xml: &str, opf_path: &str) -> Vec<String> {
+  let base = opf_path.rsplit_once('/').map(|(dir, _)| format!("{dir}/")).unwrap_or_default();
+  let mut manifest: HashMap<String, String> = HashMap::new();
+  let mut spine: Vec<String> = Vec::new();
+  let mut reader = Reader::from_str(opf_xml);
+  loop {
+    match reader.read_event() {
+      Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+        let attr = |name: &str| {
+          e.try_get_attribute(name)
+            .ok()
+            .flatten()
+            .and_then(|a| a.unescape_value().ok().map(|v| v.to_string()))
+        };
+        match e.local_name().as_ref() {
+          b"item" => {
+            if let (Some(id), Some(href)) = (attr("id"), attr("href")) {
+              manifest.insert(id, href);
             }
           }
-          Err(part_err) => {
-            if is_embed_fallback_err(&part_err) {
-              eprintln!("embed fallback: subchunk failed, skipping");
-              continue;
+          b"itemref" => {
+            if let Some(idref) = attr("idref") {
+              spine.push(idref);
             }
-            return Err(part_err);
           }
+          _ => {}
         }
       }
-
-      if embeds.is_empty() {
-        return Ok(None);
-      }
-
-      // Only combine embeddings after a split fallback; direct embeddings are returned as-is.
-      let combined = match ollama_embed_fallback_strategy() {
-        FallbackStrategy::Average => average_embeddings(&embeds),
-        FallbackStrategy::First => embeds.first().cloned(),
-      };
-      Ok(combined)
+      Ok(Event::Eof) | Err(_) => break,
+      _ => {}
     }
   }
+  spine
+    .iter()
+    .filter_map(|id| manifest.get(id))
+    .map(|href| format!("{base}{}", percent_decode(href.split('#').next().unwrap_or(href))))
+    .collect()
 }
 
-fn embed_with_batches(ollama: &Ollama, embed_model: &str, texts: &[String]) -> Result<Vec<Option<Vec<f32>>>> {
-  if texts.is_empty() {
+/// First h1–h3 of a chapter, falling back to its <title>.
+fn html_heading(html: &str) -> Option<String> {
+  let lower = html.to_ascii_lowercase();
+  let inner = |tag: &str| -> Option<String> {
+    let open = lower.find(&format!("<{tag}"))?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find(&format!("</{tag}"))?;
+    let text = extract_html_text(&html[start..end]).split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() { None } else { Some(text) }
+  };
+  ["h1", "h2", "h3", "title"].iter().find_map(|tag| inner(tag))
+}
+
+/// One page per spine chapter so citations point at chapters. Image-only books yield no pages.
+fn extract_epub_text(path: &Path) -> Result<Vec<String>> {
+  let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+  let container = read_zip_string(&mut archive, "META-INF/container.xml")?;
+  let opf_path = epub_rootfile(&container).context("epub container has no rootfile")?;
+  let opf = read_zip_string(&mut archive, &opf_path)?;
+
+  let mut pages = Vec::new();
+  for chapter in epub_spine(&opf, &opf_path) {
+    let Ok(xhtml) = read_zip_string(&mut archive, &chapter) else {
+      pages.push(String::new());
+      continue;
+    };
+    let lower = xhtml.to_ascii_lowercase();
+    let body = lower.find("<body").map(|i| &xhtml[i..]).unwrap_or(&xhtml);
+    let text = clean_text(&extract_html_text(body));
+    let page = match html_heading(&xhtml) {
+      Some(title) if !text.is_empty() && !text.starts_with(&title) => format!("{title}\n\n{text}"),
+      _ => text,
+    };
+    pages.push(page);
+  }
+  if pages.iter().all(|p| p.is_empty()) {
     return Ok(vec![]);
   }
-  let batch_size = ollama_embed_batch_size();
-  let mut out: Vec<Option<Vec<f32>>> = Vec::with_capacity(texts.len());
-  let mut start = 0;
-  while start < texts.len() {
-    let end = usize::min(start + batch_size, texts.len());
-    let batch = &texts[start..end];
-    match embed_batch_with_retry(ollama, embed_model, batch) {
-      Ok(embeds) => {
-        if embeds.len() == batch.len() {
-          out.extend(embeds.into_iter().map(Some));
-        } else {
-          eprintln!("embed batch count mismatch, retrying per chunk");
-          for text in batch {
-            let emb = embed_chunk_with_fallback(ollama, embed_model, text)?;
-            out.push(emb);
-          }
-        }
-      }
-      Err(err) => {
-        if is_embed_fallback_err(&err) {
-          for text in batch {
-            let emb = embed_chunk_with_fallback(ollama, embed_model, text)?;
-            out.push(emb);
-          }
-        } else {
-          return Err(err);
-        }
-      }
+  Ok(pages)
+}
+
+/// Slide number from an entry like `ppt/slides/slide12.xml`.
+fn pptx_slide_number(name: &str) -> Option<u32> {
+  name.strip_prefix("ppt/slides/slide")?.strip_suffix(".xml")?.parse().ok()
+}
+
+/// Text runs (`<a:t>`) of one slide or notes part, a line per paragraph. Fields such as
+/// slide numbers and dates (`<a:fld>`) are skipped.
+fn pptx_xml_text(xml: &str) -> Result<String> {
+  let mut reader = Reader::from_str(xml);
+  let mut out = String::new();
+  let (mut in_run, mut in_field) = (false, false);
+  loop {
+    match reader.read_event() {
+      Ok(Event::Start(e)) => match e.name().as_ref() {
+        b"a:t" => in_run = true,
+        b"a:fld" => in_field = true,
+        _ => {}
+      },
+      Ok(Event::End(e)) => match e.name().as_ref() {
+        b"a:t" => in_run = false,
+        b"a:fld" => in_field = false,
+        b"a:p" => out.push('\n'),
+        _ => {}
+      },
+      Ok(Event::Empty(e)) if e.name().as_ref() == b"a:br" => out.push('\n'),
+      Ok(Event::Text(e)) if in_run && !in_field => out.push_str(&e.unescape()?),
+      Ok(Event::Eof) => break,
+      Err(e) => return Err(anyhow::anyhow!("pptx parse error: {e}")),
+      _ => {}
     }
-    start = end;
   }
   Ok(out)
 }
 
-fn sanitize_fts_token(token: &str) -> String {
-  token
-    .chars()
-    .filter(|c| c.is_alphanumeric())
-    .collect()
+/// Notes part linked from a slide's relationships, resolved to a zip entry name.
+fn pptx_notes_target(rels_xml: &str) -> Option<String> {
+  let mut reader = Reader::from_str(rels_xml);
+  loop {
+    match reader.read_event() {
+      Ok(Event::Start(e)) | Ok(Event::Empty(e)) if e.local_name().as_ref() == b"Relationship" => {
+        let attr = |name: &str| {
+          e.try_get_attribute(name)
+            .ok()
+            .flatten()
+            .and_then(|a| a.unescape_value().ok().map(|v| v.to_string()))
+        };
+        if attr("Type").is_some_and(|t| t.ends_with("/notesSlide")) {
+          let target = attr("Target")?;
+          return Some(match target.strip_prefix("../") {
+            Some(rest) => format!("ppt/{rest}"),
+            None => target.trim_start_matches('/').to_string(),
+          });
+        }
+      }
+      Ok(Event::Eof) | Err(_) => return None,
+      _ => {}
+    }
+  }
 }
 
-fn build_fts_query(input: &str) -> Option<String> {
-  let tokens: Vec<String> = input
-    .split_whitespace()
-    .map(sanitize_fts_token)
-    .filter(|t| t.len() > 1)
-    .map(|t| format!("{t}*"))
+/// One page per slide in slide-number order, so citations point at slide numbers; slides
+/// without text stay as empty pages to keep that numbering.
+fn extract_pptx_text(path: &Path, include_notes: bool) -> Result<Vec<String>> {
+  let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+  let mut slides: Vec<(u32, String)> = archive
+    .file_names()
+    .filter_map(|name| pptx_slide_number(name).map(|n| (n, name.to_string())))
     .collect();
-  if tokens.is_empty() {
-    None
-  } else {
-    Some(tokens.join(" "))
+  slides.sort();
+
+  let mut pages = Vec::new();
+  for (n, name) in slides {
+    let mut page = clean_text(&pptx_xml_text(&read_zip_string(&mut archive, &name)?)?);
+    if include_notes {
+      let notes = read_zip_string(&mut archive, &format!("ppt/slides/_rels/slide{n}.xml.rels"))
+        .ok()
+        .and_then(|rels| pptx_notes_target(&rels))
+        .and_then(|target| read_zip_string(&mut archive, &target).ok())
+        .and_then(|xml| pptx_xml_text(&xml).ok())
+        .map(|text| clean_text(&text))
+        .unwrap_or_default();
+      if !notes.is_empty() {
+        page = if page.is_empty() { notes } else { format!("{page}\n\n{notes}") };
+      }
+    }
+    pages.push(page);
   }
+  if pages.iter().all(|p| p.is_empty()) {
+    return Ok(vec![]);
+  }
+  Ok(pages)
 }
 
-fn fetch_fts_ranks(conn: &Connection, query: &str, limit: usize) -> HashMap<i64, usize> {
-  let mut ranks = HashMap::new();
-  let mut stmt = match conn.prepare(
-    "SELECT rowid, bm25(chunks_fts) AS score
-     FROM chunks_fts
-     WHERE chunks_fts MATCH ?1
-     ORDER BY score
-     LIMIT ?2",
-  ) {
-    Ok(stmt) => stmt,
-    Err(_) => return ranks,
-  };
+/// One row as `header: value | header: value`, so every chunk carries its column names.
+/// Empty cells are left out; columns past the header are named by position. Line breaks
+/// inside a cell are flattened so each row stays on one line.
+fn tabular_row_text(header: &[String], row: &[String]) -> String {
+  let flat = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+  row
+    .iter()
+    .enumerate()
+    .filter(|(_, v)| !v.trim().is_empty())
+    .map(|(i, v)| match header.get(i).map(|h| flat(h)).filter(|h| !h.is_empty()) {
+      Some(h) => format!("{h}: {}", flat(v)),
+      None => format!("col{}: {}", i + 1, flat(v)),
+    })
+    .collect::<Vec<_>>()
+    .join(" | ")
+}
 
-  let mut rows = match stmt.query(params![query, limit as i64]) {
-    Ok(rows) => rows,
-    Err(_) => return ranks,
-  };
+/// Turns rows into text one line at a time; the first non-empty row is the header.
+/// A table with only a header row keeps it as plain values.
+#[derive(Default)]
+struct TabularText {
+  header: Option<Vec<String>>,
+  out: String,
+}
 
-  let mut idx = 1usize;
-  while let Ok(Some(row)) = rows.next() {
-    if let Ok(id) = row.get::<_, i64>(0) {
-      ranks.insert(id, idx);
-      idx += 1;
+impl TabularText {
+  fn push_row(&mut self, row: &[String]) {
+    if row.iter().all(|v| v.trim().is_empty()) {
+      return;
+    }
+    match &self.header {
+      None => self.header = Some(row.to_vec()),
+      Some(header) => {
+        self.out.push_str(&tabular_row_text(header, row));
+        self.out.push('\n');
+      }
     }
   }
-  ranks
-}
 
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
-  if a.len() != b.len() || a.is_empty() {
-    return 0.0;
-  }
-  let mut dot = 0.0f64;
-  let mut norm_a = 0.0f64;
-  let mut norm_b = 0.0f64;
-  for i in 0..a.len() {
-    let av = a[i] as f64;
-    let bv = b[i] as f64;
-    dot += av * bv;
-    norm_a += av * av;
-    norm_b += bv * bv;
-  }
-  if norm_a == 0.0 || norm_b == 0.0 {
-    return 0.0;
+  fn finish(self) -> String {
+    match self.header {
+      Some(header) if self.out.is_empty() => {
+        header.iter().map(|h| h.trim()).filter(|h| !h.is_empty()).collect::<Vec<_>>().join(" | ")
+      }
+      _ => self.out.trim_end().to_string(),
+    }
   }
-  dot / (norm_a.sqrt() * norm_b.sqrt())
 }
 
-fn now_ts() -> i64 {
-  SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+/// Picks `,`, `;` or tab by which occurs most in the first line (Excel writes `;` in many locales).
+fn csv_delimiter(text: &str) -> char {
+  let first = text.lines().next().unwrap_or("");
+  [',', ';', '\t']
+    .into_iter()
+    .max_by_key(|d| first.matches(*d).count())
+    .unwrap_or(',')
 }
 
-fn has_table(conn: &Connection, name: &str) -> Result<bool> {
-  let exists: Option<i32> = conn
-    .query_row(
-      "SELECT 1 FROM sqlite_master WHERE type='table' AND name=?1 LIMIT 1",
-      params![name],
-      |r| r.get(0),
-    )
-    .ok();
-  Ok(exists.is_some())
+/// RFC 4180 records: quoted fields may hold delimiters, newlines and `""` escapes.
+fn parse_csv(text: &str, delimiter: char) -> Vec<Vec<String>> {
+  let mut rows = Vec::new();
+  let mut row = Vec::new();
+  let mut field = String::new();
+  let mut quoted = false;
+  let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+  while let Some(c) = chars.next() {
+    if quoted {
+      match c {
+        '"' if chars.peek() == Some(&'"') => {
+          field.push('"');
+          chars.next();
+        }
+        '"' => quoted = false,
+        _ => field.push(c),
+      }
+      continue;
+    }
+    match c {
+      '"' if field.is_empty() => quoted = true,
+      '\r' => {}
+      '\n' => {
+        row.push(std::mem::take(&mut field));
+        rows.push(std::mem::take(&mut row));
+      }
+      c if c == delimiter => row.push(std::mem::take(&mut field)),
+      _ => field.push(c),
+    }
+  }
+  if !field.is_empty() || !row.is_empty() {
+    row.push(field);
+    rows.push(row);
+  }
+  rows
 }
 
-fn load_indexed_hashes(conn: &Connection) -> Result<HashMap<String, String>> {
-  if !has_table(conn, "files")? {
-    return Ok(HashMap::new());
+fn extract_csv_text(text: &str) -> String {
+  let mut table = TabularText::default();
+  for row in parse_csv(text, csv_delimiter(text)) {
+    table.push_row(&row);
   }
+  table.finish()
+}
 
-  let mut map = HashMap::new();
-  let mut stmt = conn.prepare("SELECT path, hash FROM files")?;
-  let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?;
-  for row in rows {
-    let (path, hash) = row?;
-    map.insert(path, hash);
+/// Zero-based column of a cell reference such as `AB12`.
+fn xlsx_column(cell_ref: &str) -> Option<usize> {
+  let letters: Vec<u8> = cell_ref.bytes().take_while(|b| b.is_ascii_alphabetic()).collect();
+  if letters.is_empty() {
+    return None;
   }
-  Ok(map)
+  let n = letters.iter().fold(0usize, |n, b| n * 26 + (b.to_ascii_uppercase() - b'A') as usize + 1);
+  Some(n - 1)
 }
 
-fn ensure_targets_schema(conn: &Connection) -> Result<()> {
-  conn.execute_batch(
-    "CREATE TABLE IF NOT EXISTS targets(
-       path TEXT NOT NULL,
-       kind TEXT NOT NULL,
-       include_subfolders INTEGER NOT NULL,
-       added_at INTEGER NOT NULL,
-       PRIMARY KEY(path, kind)
-     );"
-  )?;
-  Ok(())
+/// `xl/sharedStrings.xml` entries in order; rich-text runs are concatenated and phonetic
+/// hints (`<rPh>`) dropped.
+fn xlsx_shared_strings<R: BufRead>(xml: R) -> Result<Vec<String>> {
+  let mut reader = Reader::from_reader(xml);
+  let mut buf = Vec::new();
+  let mut strings = Vec::new();
+  let mut current = String::new();
+  let (mut in_text, mut in_phonetic) = (false, false);
+  loop {
+    match reader.read_event_into(&mut buf) {
+      Ok(Event::Start(e)) => match e.local_name().as_ref() {
+        b"si" => current.clear(),
+        b"t" => in_text = true,
+        b"rPh" => in_phonetic = true,
+        _ => {}
+      },
+      Ok(Event::End(e)) => match e.local_name().as_ref() {
+        b"si" => strings.push(std::mem::take(&mut current)),
+        b"t" => in_text = false,
+        b"rPh" => in_phonetic = false,
+        _ => {}
+      },
+      Ok(Event::Empty(e)) if e.local_name().as_ref() == b"si" => strings.push(String::new()),
+      Ok(Event::Text(e)) if in_text && !in_phonetic => current.push_str(&e.unescape()?),
+      Ok(Event::Eof) => break,
+      Err(e) => return Err(anyhow::anyhow!("xlsx shared strings parse error: {e}")),
+      _ => {}
+    }
+    buf.clear();
+  }
+  Ok(strings)
 }
 
-fn matches_target(path: &Path, target: &IndexTarget) -> bool {
-  if target.path.trim().is_empty() {
-    return false;
-  }
-  let target_path = PathBuf::from(&target.path);
-  match target.kind {
-    IndexTargetKind::File => path == target_path,
-    IndexTargetKind::Folder => {
-      if target.include_subfolders {
-        path.starts_with(&target_path)
-      } else {
-        path.parent() == Some(target_path.as_path())
-      }
+/// One worksheet streamed row by row, so only the current row is held besides the output.
+fn xlsx_sheet_text<R: BufRead>(xml: R, shared: &[String]) -> Result<String> {
+  let mut reader = Reader::from_reader(xml);
+  let mut buf = Vec::new();
+  let mut table = TabularText::default();
+  let mut row: Vec<String> = Vec::new();
+  let mut cell: Option<(usize, Option<String>)> = None;
+  let mut value = String::new();
+  let mut in_value = false;
+  loop {
+    match reader.read_event_into(&mut buf) {
+      Ok(Event::Start(e)) => match e.local_name().as_ref() {
+        b"c" => {
+          let attr = |name: &str| {
+            e.try_get_attribute(name)
+              .ok()
+              .flatten()
+              .and_then(|a| a.unescape_value().ok().map(|v| v.to_string()))
+          };
+          let col = attr("r").and_then(|r| xlsx_column(&r)).unwrap_or(row.len());
+          cell = Some((col, attr("t")));
+          value.clear();
+        }
+        b"v" | b"t" if cell.is_some() => in_value = true,
+        _ => {}
+      },
+      Ok(Event::Text(e)) if in_value => value.push_str(&e.unescape()?),
+      Ok(Event::End(e)) => match e.local_name().as_ref() {
+        b"v" | b"t" => in_value = false,
+        b"c" => {
+          if let Some((col, kind)) = cell.take() {
+            let text = match kind.as_deref() {
+              Some("s") => value.trim().parse::<usize>().ok().and_then(|i| shared.get(i)).cloned().unwrap_or_default(),
+              Some("b") => if value.trim() == "1" { "TRUE".into() } else { "FALSE".into() },
+              _ => value.clone(),
+            };
+            if !text.trim().is_empty() {
+              if row.len() <= col {
+                row.resize(col + 1, String::new());
+              }
+              row[col] = text;
+            }
+          }
+        }
+        b"row" => {
+          table.push_row(&row);
+          row.clear();
+        }
+        _ => {}
+      },
+      Ok(Event::Eof) => break,
+      Err(e) => return Err(anyhow::anyhow!("xlsx sheet parse error: {e}")),
+      _ => {}
     }
+    buf.clear();
   }
+  Ok(table.finish())
 }
 
-fn matches_any_target(path: &Path, targets: &[IndexTarget]) -> bool {
-  targets.iter().any(|t| matches_target(path, t))
+/// Worksheet number from an entry like `xl/worksheets/sheet3.xml`.
+fn xlsx_sheet_number(name: &str) -> Option<u32> {
+  name.strip_prefix("xl/worksheets/sheet")?.strip_suffix(".xml")?.parse().ok()
 }
 
-pub fn list_targets(app: &AppHandle) -> Result<Vec<IndexTarget>> {
-  let conn = open_db(app)?;
-  ensure_targets_schema(&conn)?;
-
-  let mut targets = vec![];
-  let mut stmt = conn.prepare("SELECT path, kind, include_subfolders FROM targets ORDER BY added_at ASC")?;
-  let rows = stmt.query_map([], |r| {
-    let path: String = r.get(0)?;
-    let kind_str: String = r.get(1)?;
-    let include_subfolders: i64 = r.get(2)?;
-    let kind = if kind_str == "folder" { IndexTargetKind::Folder } else { IndexTargetKind::File };
-    Ok(IndexTarget {
-      path,
-      kind,
-      include_subfolders: include_subfolders != 0,
-    })
-  })?;
+/// One page per worksheet, in sheet-file order.
+fn extract_xlsx_text(path: &Path) -> Result<Vec<String>> {
+  let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+  let shared = match archive.by_name("xl/sharedStrings.xml") {
+    Ok(entry) => xlsx_shared_strings(BufReader::new(entry))?,
+    Err(_) => Vec::new(),
+  };
+  let mut sheets: Vec<(u32, String)> = archive
+    .file_names()
+    .filter_map(|name| xlsx_sheet_number(name).map(|n| (n, name.to_string())))
+    .collect();
+  sheets.sort();
 
-  for row in rows {
-    targets.push(row?);
+  let mut pages = Vec::new();
+  for (_, name) in sheets {
+    let entry = archive.by_name(&name)?;
+    pages.push(xlsx_sheet_text(BufReader::new(entry), &shared)?);
   }
-  Ok(targets)
+  if pages.iter().all(|p| p.is_empty()) {
+    return Ok(vec![]);
+  }
+  Ok(pages)
 }
 
-pub fn save_targets(app: &AppHandle, targets: Vec<IndexTarget>) -> Result<()> {
-  let mut conn = open_db(app)?;
-  ensure_targets_schema(&conn)?;
-  let tx = conn.transaction()?;
-  tx.execute("DELETE FROM targets", [])?;
-
-  for target in targets {
-    let kind = match target.kind {
-      IndexTargetKind::Folder => "folder",
-      IndexTargetKind::File => "file",
-    };
-    tx.execute(
-      "INSERT OR REPLACE INTO targets(path, kind, include_subfolders, added_at) VALUES(?1, ?2, ?3, ?4)",
-      params![target.path, kind, if target.include_subfolders { 1 } else { 0 }, now_ts()]
-    )?;
-  }
+static ZIP_TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
-  tx.commit()?;
-  Ok(())
+/// Reads at most `limit` bytes of an archive entry. The size an entry declares is not trusted:
+/// it is checked before allocating, and the read itself stops past `limit`.
+fn read_zip_entry(entry: impl Read, declared: u64, limit: u64) -> Result<Vec<u8>> {
+  anyhow::ensure!(declared <= limit, "archive entry declares {declared} bytes, over the {limit}-byte limit");
+  let mut buf = Vec::with_capacity(declared as usize);
+  entry.take(limit + 1).read_to_end(&mut buf)?;
+  anyhow::ensure!(buf.len() as u64 <= limit, "archive entry is larger than the {limit}-byte limit");
+  Ok(buf)
 }
 
-pub fn prune_index(app: &AppHandle, targets: Vec<IndexTarget>) -> Result<usize> {
-  let mut conn = open_db(app)?;
-  if !has_table(&conn, "files")? {
-    return Ok(0);
-  }
-
-  let has_chunks = has_table(&conn, "chunks")?;
-  let has_fts = has_table(&conn, "chunks_fts")?;
-  let has_vec = has_table(&conn, "vec_chunks")?;
+/// Copies a zip entry to a temp file so the regular extractors (pdfium, tesseract) can read it.
+fn extract_zip_entry_text(app: &AppHandle, doc: &DocumentCandidate, entry: &str, settings: &IndexSettings) -> Result<ExtractedText> {
+  let mut zip = ZipArchive::new(fs::File::open(&doc.path)?)?;
+  let file = zip.by_name(entry)?;
+  let limit = settings.max_file_bytes.unwrap_or(MAX_ZIP_ENTRY_BYTES).min(MAX_ZIP_ENTRY_BYTES);
+  let declared = file.size();
+  let buf = read_zip_entry(file, declared, limit)?;
+
+  let ext = Path::new(entry).extension().and_then(|e| e.to_str()).unwrap_or("bin");
+  let n = ZIP_TEMP_COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+  let tmp = std::env::temp_dir().join(format!("local-files-chat-{}-{}.{}", std::process::id(), n, ext));
+  fs::write(&tmp, &buf)?;
+
+  let inner = DocumentCandidate { path: tmp.clone(), kind: doc.kind, entry: None };
+  let res = extract_text_for_document(app, &inner, settings);
+  let _ = fs::remove_file(&tmp);
+  res
+}
 
-  let total_files: i64 = conn.query_row("SELECT COUNT(*) FROM files", [], |r| r.get(0))?;
-  if total_files == 0 {
-    return Ok(0);
+fn extract_text_for_document(app: &AppHandle, doc: &DocumentCandidate, settings: &IndexSettings) -> Result<ExtractedText> {
+  if let Some(entry) = &doc.entry {
+    return extract_zip_entry_text(app, doc, entry, settings);
   }
-
-  if targets.is_empty() {
-    let tx = conn.transaction()?;
-    if has_vec {
-      tx.execute("DELETE FROM vec_chunks", [])?;
-    }
-    if has_fts {
-      tx.execute("DELETE FROM chunks_fts", [])?;
+  let pages = match doc.kind {
+    DocumentKind::Pdf => return extract_pdf_text(app, &doc.path, settings),
+    DocumentKind::Docx => {
+      let text = extract_docx_text(&doc.path)?;
+      vec![clean_text(&text)]
     }
-    if has_chunks {
-      tx.execute("DELETE FROM chunks", [])?;
+    DocumentKind::Txt | DocumentKind::Md => {
+      let raw = fs::read(&doc.path)?;
+      let text = String::from_utf8_lossy(&raw).to_string();
+      vec![clean_text(&text)]
     }
-    tx.execute("DELETE FROM files", [])?;
-    tx.commit()?;
-    return Ok(total_files as usize);
-  }
-
-  let mut paths_to_delete = Vec::new();
-  {
-    let mut stmt = conn.prepare("SELECT path FROM files")?;
-    let rows = stmt.query_map([], |r| r.get::<_, String>(0))?;
-    for row in rows {
-      let path = row?;
-      let path_buf = PathBuf::from(&path);
-      if !matches_any_target(&path_buf, &targets) {
-        paths_to_delete.push(path);
-      }
+    DocumentKind::Html => {
+      let raw = fs::read(&doc.path)?;
+      vec![clean_text(&extract_html_text(&String::from_utf8_lossy(&raw)))]
     }
-  }
-
-  if paths_to_delete.is_empty() {
-    return Ok(0);
-  }
-
-  let tx = conn.transaction()?;
-  for path in &paths_to_delete {
-    if has_vec && has_chunks {
-      tx.execute(
-        "DELETE FROM vec_chunks WHERE rowid IN (SELECT id FROM chunks WHERE file_path=?1)",
-        params![path],
-      )?;
+    DocumentKind::Epub => extract_epub_text(&doc.path)?,
+    DocumentKind::Rtf => {
+      let raw = fs::read(&doc.path)?;
+      vec![clean_text(&extract_rtf_text(&raw)?)]
     }
-    if has_fts && has_chunks {
-      tx.execute(
-        "DELETE FROM chunks_fts WHERE rowid IN (SELECT id FROM chunks WHERE file_path=?1)",
-        params![path],
-      )?;
+    DocumentKind::Pptx => extract_pptx_text(&doc.path, settings.pptx_notes)?,
+    DocumentKind::Csv => {
+      let raw = fs::read(&doc.path)?;
+      vec![extract_csv_text(&String::from_utf8_lossy(&raw))]
     }
-    if has_chunks {
-      tx.execute("DELETE FROM chunks WHERE file_path=?1", params![path])?;
+    DocumentKind::Xlsx => extract_xlsx_text(&doc.path)?,
+    DocumentKind::Code => {
+      let raw = fs::read(&doc.path)?;
+      vec![clean_text(&String::from_utf8_lossy(&raw).replace("\r\n", "\n"))]
     }
-    tx.execute("DELETE FROM files WHERE path=?1", params![path])?;
-  }
-  tx.commit()?;
-
-  Ok(paths_to_delete.len())
+  };
+  Ok(ExtractedText { pages, page_report: vec![], origins: vec![] })
 }
 
-fn index_documents(
-  app: &AppHandle,
-  docs: Vec<DocumentCandidate>,
-  embed_model: &str,
-  settings: &IndexSettings,
-  emit_progress: bool,
-) -> Result<()> {
-  let ollama = Ollama::new();
+fn detect_lang_code(text: &str) -> Option<String> {
+  detect(text).map(|i| i.lang().code().to_string())
+}
 
-  let test = ollama.embed(embed_model, "dim probe")?;
-  let dim = test.get(0).map(|v| v.len()).unwrap_or(0);
-  anyhow::ensure!(dim > 0, "Embedding dim is 0 (model embed failed?)");
+fn ollama_embed_batch_size() -> usize {
+  std::env::var("OLLAMA_EMBED_BATCH")
+    .ok()
+    .and_then(|v| v.parse::<usize>().ok())
+    .filter(|v| *v > 0)
+    .unwrap_or(4)
+}
 
-  let mut conn = open_db(app)?;
-  ensure_schema(&conn, dim, settings)?;
+fn ollama_embed_batch_chars() -> usize {
+  // OLLAMA_EMBED_BATCH_CHARS bounds the text (and so the response) size of one embed request.
+  std::env::var("OLLAMA_EMBED_BATCH_CHARS")
+    .ok()
+    .and_then(|v| v.parse::<usize>().ok())
+    .filter(|v| *v > 0)
+    .unwrap_or(32_000)
+}
 
-  let total = docs.len();
-  if emit_progress {
-    app.emit("index_progress", IndexProgress { current: 0, total, file: "".into(), status: "start".into() })?;
+/// End of the batch starting at `start`: at most `max_count` texts and `max_chars` characters,
+/// but always at least one text so an oversized chunk still goes through on its own.
+fn embed_batch_end(texts: &[String], start: usize, max_count: usize, max_chars: usize) -> usize {
+  let mut end = start;
+  let mut chars = 0;
+  while end < texts.len() && end - start < max_count.max(1) {
+    let len = texts[end].chars().count();
+    if end > start && chars + len > max_chars {
+      break;
+    }
+    chars += len;
+    end += 1;
   }
+  end
+}
 
-  for (i, doc) in docs.into_iter().enumerate() {
-    if !doc.path.is_file() {
-      if emit_progress {
-        app.emit("index_progress", IndexProgress { current: i + 1, total, file: doc.path.to_string_lossy().to_string(), status: "missing".into() })?;
-      }
-      continue;
-    }
+fn ollama_embed_fallback_chars() -> usize {
+  // OLLAMA_EMBED_FALLBACK_CHARS caps subchunk size when a chunk times out or is too large.
+  std::env::var("OLLAMA_EMBED_FALLBACK_CHARS")
+    .ok()
+    .and_then(|v| v.parse::<usize>().ok())
+    .filter(|v| *v > 0)
+    .unwrap_or(800)
+}
 
-    let file_str = doc.path.to_string_lossy().to_string();
-    let (hash, size, mtime) = file_fingerprint(&doc.path)?;
+#[derive(Clone, Copy, Debug)]
+enum FallbackStrategy {
+  Average,
+  First,
+}
 
-    let old_hash: Option<String> = conn.query_row(
-      "SELECT hash FROM files WHERE path=?1",
-      params![file_str],
-      |r| r.get(0)
-    ).ok();
+fn ollama_embed_fallback_strategy() -> FallbackStrategy {
+  // OLLAMA_EMBED_FALLBACK_STRATEGY controls how split embeddings are combined:
+  // - average: combines subchunks (better coverage, slower)
+  // - first: uses the first subchunk only (faster, less coverage)
+  match std::env::var("OLLAMA_EMBED_FALLBACK_STRATEGY").ok().as_deref() {
+    Some(raw) if raw.eq_ignore_ascii_case("first") => FallbackStrategy::First,
+    _ => FallbackStrategy::Average,
+  }
+}
 
-    if old_hash.as_deref() == Some(&hash) {
-      if emit_progress {
-        app.emit("index_progress", IndexProgress { current: i + 1, total, file: file_str, status: "skip".into() })?;
-      }
-      continue;
+/// Embeds a probe string to learn the vector width, turning the usual setup mistakes into
+/// messages that say what to do.
+fn explain_embed_error(e: anyhow::Error, embed_model: &str) -> anyhow::Error {
+  match classify_ollama_error(&e) {
+    OllamaErrorKind::ModelNotFound => {
+      e.context(format!("Embedding model '{embed_model}' is not installed — run `ollama pull {embed_model}`"))
     }
+    OllamaErrorKind::Unreachable => e.context("Ollama is not running or not reachable at the configured host"),
+    OllamaErrorKind::Other => e,
+  }
+}
 
-    if emit_progress {
-      app.emit("index_progress", IndexProgress { current: i + 1, total, file: file_str.clone(), status: "extract".into() })?;
-    }
+fn probe_embed_dim(ollama: &Ollama, embed_model: &str) -> Result<usize> {
+  let test = ollama.embed(embed_model, "dim probe").map_err(|e| explain_embed_error(e, embed_model))?;
+  let dim = test.first().map(|v| v.len()).unwrap_or(0);
+  anyhow::ensure!(dim > 0, "Embedding dim is 0 (model embed failed?)");
+  Ok(dim)
+}
 
-    let pages = match extract_text_for_document(app, &doc, settings)
-      .with_context(|| format!("extract failed: {file_str}")) {
-      Ok(pages) => pages,
-      Err(e) => {
-        if emit_progress {
-          let _ = app.emit(
-            "index_progress",
-            IndexProgress {
-              current: i + 1,
-              total,
-              file: file_str.clone(),
-              status: "error".into(),
-            },
-          );
-        }
-        eprintln!("index skip {}: {}", file_str, e);
-        continue;
-      }
-    };
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EmbeddingProbe {
+  pub dim: usize,
+  pub norm: f64,
+  /// First 8 components.
+  pub sample: Vec<f32>,
+  /// Empty, all zeros, or containing NaN/inf: every chunk would collapse to the same point.
+  pub degenerate: bool,
+  pub warning: Option<String>,
+}
 
-    let mut chunk_meta: Vec<(i32, i32, Option<String>)> = Vec::new();
-    let mut chunk_texts: Vec<String> = Vec::new();
+fn embedding_probe(v: &[f32]) -> EmbeddingProbe {
+  let non_finite = v.iter().any(|x| !x.is_finite());
+  let norm = v.iter().map(|&x| (x as f64) * (x as f64)).sum::<f64>().sqrt();
+  let warning = if v.is_empty() {
+    Some("the model returned an empty vector".to_string())
+  } else if non_finite {
+    Some("the vector contains NaN or infinite components".to_string())
+  } else if norm == 0.0 {
+    Some("the vector is all zeros".to_string())
+  } else {
+    None
+  };
+  EmbeddingProbe {
+    dim: v.len(),
+    norm,
+    sample: v.iter().take(8).copied().collect(),
+    degenerate: warning.is_some(),
+    warning,
+  }
+}
 
-    for (pi, page_text) in pages.iter().enumerate() {
-      let chunks = chunk_text(page_text, settings.chunk_size, settings.chunk_overlap);
-      for (ci, ch) in chunks.into_iter().enumerate() {
-        let lang = detect_lang_code(&ch);
-        chunk_meta.push((pi as i32, ci as i32, lang));
-        chunk_texts.push(ch);
+/// Embeds `text` once with `model` so a broken install shows up before indexing, not after.
+/// Uses the same host, truncation and options as indexing with `settings` would.
+pub fn probe_embedding(model: &str, text: &str, settings: &IndexSettings) -> Result<EmbeddingProbe> {
+  let embeds = settings.embed_client()?.embed(model, text).map_err(|e| explain_embed_error(e, model))?;
+  Ok(embedding_probe(embeds.first().map(Vec::as_slice).unwrap_or(&[])))
+}
+
+fn is_reqwest_timeout(err: &anyhow::Error) -> bool {
+  err
+    .downcast_ref::<reqwest::Error>()
+    .map(|e| e.is_timeout())
+    .unwrap_or(false)
+}
+
+/// Timeouts plus failures to connect or to get a response at all (e.g. a reset connection).
+/// Kept apart from `is_reqwest_timeout`, which decides when embedding splits a batch.
+fn is_reqwest_transient(err: &anyhow::Error) -> bool {
+  err
+    .downcast_ref::<reqwest::Error>()
+    .is_some_and(|e| e.is_timeout() || e.is_connect() || e.is_request())
+}
+
+fn is_ollama_input_too_large(err: &anyhow::Error) -> bool {
+  if let Some(http_err) = err.downcast_ref::<OllamaHttpError>() {
+    if http_err.status == StatusCode::PAYLOAD_TOO_LARGE {
+      return true;
+    }
+    if matches!(http_err.status, StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY) {
+      let body = http_err.body.to_lowercase();
+      if body.contains("context")
+        && (body.contains("length") || body.contains("too long") || body.contains("limit"))
+      {
+        return true;
+      }
+      if body.contains("input")
+        && (body.contains("too long") || body.contains("limit") || body.contains("tokens"))
+      {
+        return true;
+      }
+    }
+  }
+
+  let msg = err.to_string().to_lowercase();
+  if msg.contains("context") && (msg.contains("length") || msg.contains("too long") || msg.contains("limit")) {
+    return true;
+  }
+  if msg.contains("input") && (msg.contains("too long") || msg.contains("limit") || msg.contains("tokens")) {
+    return true;
+  }
+  false
+}
+
+fn ollama_chat_attempts() -> usize {
+  // OLLAMA_CHAT_ATTEMPTS: total tries for a chat request (1 disables retries).
+  std::env::var("OLLAMA_CHAT_ATTEMPTS")
+    .ok()
+    .and_then(|v| v.parse::<usize>().ok())
+    .filter(|v| *v > 0)
+    .unwrap_or(3)
+}
+
+fn ollama_chat_backoff(attempt: usize) -> Duration {
+  // OLLAMA_CHAT_BACKOFF_MS: delay before the first retry, doubled for each further one.
+  let base = std::env::var("OLLAMA_CHAT_BACKOFF_MS")
+    .ok()
+    .and_then(|v| v.parse::<u64>().ok())
+    .unwrap_or(500);
+  Duration::from_millis(base.saturating_mul(1u64 << attempt.saturating_sub(1).min(6)))
+}
+
+fn is_chat_retryable(err: &anyhow::Error) -> bool {
+  if let Some(http_err) = err.downcast_ref::<OllamaHttpError>() {
+    // Older Ollama builds report a missing model as a 500; retrying cannot fix that.
+    return (http_err.status.is_server_error() || http_err.status == StatusCode::TOO_MANY_REQUESTS)
+      && classify_ollama_error(err) != OllamaErrorKind::ModelNotFound;
+  }
+  is_reqwest_transient(err)
+}
+
+/// Adds the same actionable hint `probe_embed_dim` gives for indexing to a failed answer call.
+fn explain_chat_error(err: anyhow::Error, llm_model: &str) -> anyhow::Error {
+  match classify_ollama_error(&err) {
+    OllamaErrorKind::ModelNotFound => {
+      err.context(format!("Chat model '{llm_model}' is not installed — run `ollama pull {llm_model}`"))
+    }
+    OllamaErrorKind::Unreachable => err.context("Ollama is not running or not reachable at the configured host"),
+    OllamaErrorKind::Other => err,
+  }
+}
+
+fn chat_with_retry<T>(mut call: impl FnMut() -> Result<T>) -> Result<T> {
+  let attempts = ollama_chat_attempts();
+  let mut attempt = 1;
+  loop {
+    match call() {
+      Ok(out) => return Ok(out),
+      Err(err) if attempt < attempts && is_chat_retryable(&err) => {
+        eprintln!("chat attempt {attempt} failed, retrying: {err}");
+        std::thread::sleep(ollama_chat_backoff(attempt));
+        attempt += 1;
+      }
+      Err(err) => return Err(err),
+    }
+  }
+}
+
+fn is_embed_fallback_err(err: &anyhow::Error) -> bool {
+  is_reqwest_timeout(err) || is_ollama_input_too_large(err)
+}
+
+fn average_embeddings(embeds: &[Vec<f32>]) -> Option<Vec<f32>> {
+  if embeds.is_empty() {
+    return None;
+  }
+  let dim = embeds[0].len();
+  if dim == 0 {
+    return None;
+  }
+  let mut out = vec![0.0f32; dim];
+  for emb in embeds {
+    if emb.len() != dim {
+      return None;
+    }
+    for (i, v) in emb.iter().enumerate() {
+      out[i] += *v;
+    }
+  }
+  let denom = embeds.len() as f32;
+  for v in &mut out {
+    *v /= denom;
+  }
+  Some(out)
+}
+
+fn embed_batch_with_retry(ollama: &Ollama, embed_model: &str, batch: &[String]) -> Result<Vec<Vec<f32>>> {
+  let mut attempts = 0;
+  loop {
+    match ollama.embed(embed_model, batch.to_vec()) {
+      Ok(embeds) => return Ok(embeds),
+      Err(err) => {
+        if is_reqwest_timeout(&err) && batch.len() > 1 {
+          let mid = batch.len() / 2;
+          let left = embed_batch_with_retry(ollama, embed_model, &batch[..mid])?;
+          let right = embed_batch_with_retry(ollama, embed_model, &batch[mid..])?;
+          let mut out = left;
+          out.extend(right);
+          return Ok(out);
+        }
+        attempts += 1;
+        if attempts >= 2 {
+          return Err(err);
+        }
+        std::thread::sleep(Duration::from_millis(400));
+      }
+    }
+  }
+}
+
+fn embed_chunk_with_fallback(ollama: &Ollama, embed_model: &str, text: &str) -> Result<Option<Vec<f32>>> {
+  match ollama.embed(embed_model, text) {
+    Ok(embeds) => Ok(embeds.into_iter().next()),
+    Err(err) => {
+      if !is_embed_fallback_err(&err) {
+        return Err(err);
+      }
+
+      let fallback_chars = ollama_embed_fallback_chars();
+      let char_len = text.chars().count();
+      if fallback_chars == 0 || char_len <= fallback_chars {
+        eprintln!("embed fallback: chunk failed (len {char_len}), skipping");
+        return Ok(None);
+      }
+
+      let parts = chunk_text(text, fallback_chars, 0);
+      if parts.len() <= 1 {
+        eprintln!("embed fallback: split produced no subchunks, skipping");
+        return Ok(None);
+      }
+
+      let mut embeds = Vec::new();
+      for part in parts {
+        match ollama.embed(embed_model, part) {
+          Ok(mut out) => {
+            if let Some(emb) = out.pop() {
+              embeds.push(emb);
+            }
+          }
+          Err(part_err) => {
+            if is_embed_fallback_err(&part_err) {
+              eprintln!("embed fallback: subchunk failed, skipping");
+              continue;
+            }
+            return Err(part_err);
+          }
+        }
+      }
+
+      if embeds.is_empty() {
+        return Ok(None);
+      }
+
+      // Only combine embeddings after a split fallback; direct embeddings are returned as-is.
+      let combined = match ollama_embed_fallback_strategy() {
+        FallbackStrategy::Average => average_embeddings(&embeds),
+        FallbackStrategy::First => embeds.first().cloned(),
+      };
+      Ok(combined)
+    }
+  }
+}
+
+/// Forgets every file's fingerprint and text hash so the next run re-chunks and re-embeds it,
+/// while its current chunks stay searchable until then.
+fn mark_files_stale(conn: &Connection) -> Result<usize> {
+  Ok(conn.execute("UPDATE files SET hash='', text_hash=NULL", [])?)
+}
+
+/// Marks files whose vectors came from a model other than `embed_model` as stale, so the run
+/// that follows re-embeds them in the same space as the rest of the index.
+fn mark_other_model_files_stale(conn: &Connection, embed_model: &str) -> Result<usize> {
+  let mut marked = 0;
+  for path in files_embedded_with_other_models(conn, embed_model) {
+    marked += conn.execute("UPDATE files SET hash='', text_hash=NULL WHERE path=?1", params![path])?;
+  }
+  Ok(marked)
+}
+
+/// Files embedded with a model other than `embed_model`; their distances to the query vector
+/// are meaningless. Rows from before the column existed are assumed to match.
+fn files_embedded_with_other_models(conn: &Connection, embed_model: &str) -> HashSet<String> {
+  let Ok(mut stmt) = conn.prepare("SELECT path, embed_model FROM files WHERE embed_model IS NOT NULL") else {
+    return HashSet::new();
+  };
+  let Ok(rows) = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?))) else {
+    return HashSet::new();
+  };
+  rows
+    .filter_map(|r| r.ok())
+    .filter(|(_, model)| !same_embed_model(model, embed_model))
+    .map(|(path, _)| path)
+    .collect()
+}
+
+/// Vectors by `(model, sha256 of the embedded text)`, so re-chunking only embeds new text.
+fn create_embed_cache(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "CREATE TABLE IF NOT EXISTS embed_cache(
+       model TEXT NOT NULL,
+       text_hash TEXT NOT NULL,
+       embedding BLOB NOT NULL,
+       PRIMARY KEY(model, text_hash)
+     );"
+  )?;
+  Ok(())
+}
+
+/// Forgets every cached vector so the next indexing run embeds from scratch.
+pub fn clear_embed_cache(app: &AppHandle, confirm: bool) -> Result<usize> {
+  anyhow::ensure!(confirm, "refusing to clear the embedding cache without confirm: true");
+  let conn = open_db(app)?;
+  if !has_table(&conn, "embed_cache")? {
+    return Ok(0);
+  }
+  Ok(conn.execute("DELETE FROM embed_cache", [])?)
+}
+
+fn embed_cache_key(text: &str) -> String {
+  format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+fn f32_blob(v: &[f32]) -> Vec<u8> {
+  v.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn blob_f32(b: &[u8]) -> Vec<f32> {
+  b.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect()
+}
+
+/// Stored embeddings by chunk id, read back from `vec_chunks`; ids without a row are absent.
+fn load_vectors(conn: &Connection, ids: &[i64]) -> Result<HashMap<i64, Vec<f32>>> {
+  let mut stmt = conn.prepare("SELECT embedding FROM vec_chunks WHERE rowid=?1")?;
+  let mut out = HashMap::with_capacity(ids.len());
+  for &id in ids {
+    let blob: Option<Vec<u8>> = stmt.query_row(params![id], |r| r.get(0)).ok();
+    if let Some(blob) = blob {
+      out.insert(id, blob_f32(&blob));
+    }
+  }
+  Ok(out)
+}
+
+fn embed_cache_get(conn: &Connection, embed_model: &str, text: &str) -> Option<Vec<f32>> {
+  conn
+    .query_row(
+      "SELECT embedding FROM embed_cache WHERE model=?1 AND text_hash=?2",
+      params![embed_model, embed_cache_key(text)],
+      |r| r.get::<_, Vec<u8>>(0),
+    )
+    .ok()
+    .map(|b| blob_f32(&b))
+}
+
+fn embed_cache_put(conn: &Connection, embed_model: &str, text: &str, embedding: &[f32]) -> Result<()> {
+  conn.execute(
+    "INSERT OR REPLACE INTO embed_cache(model, text_hash, embedding) VALUES(?1, ?2, ?3)",
+    params![embed_model, embed_cache_key(text), f32_blob(embedding)],
+  )?;
+  Ok(())
+}
+
+/// Embeds `texts`, taking vectors already in `embed_cache` (when a connection is given)
+/// and sending only the misses to Ollama. Callers store new vectors with `embed_cache_put`.
+/// `on_progress(done, total)` runs after each request to Ollama.
+fn embed_with_batches(
+  ollama: &Ollama,
+  embed_model: &str,
+  texts: &[String],
+  cache: Option<&Connection>,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<Vec<Option<Vec<f32>>>> {
+  let Some(cache) = cache else {
+    return embed_uncached(ollama, embed_model, texts, on_progress);
+  };
+  let mut out: Vec<Option<Vec<f32>>> = texts.iter().map(|t| embed_cache_get(cache, embed_model, t)).collect();
+  let misses: Vec<usize> = (0..texts.len()).filter(|&i| out[i].is_none()).collect();
+  if misses.is_empty() {
+    return Ok(out);
+  }
+  let miss_texts: Vec<String> = misses.iter().map(|&i| texts[i].clone()).collect();
+  let hits = texts.len() - misses.len();
+  let embeds = embed_uncached(ollama, embed_model, &miss_texts, |done, _| on_progress(hits + done, texts.len()))?;
+  for (i, emb) in misses.into_iter().zip(embeds) {
+    out[i] = emb;
+  }
+  Ok(out)
+}
+
+fn embed_uncached(
+  ollama: &Ollama,
+  embed_model: &str,
+  texts: &[String],
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<Vec<Option<Vec<f32>>>> {
+  if texts.is_empty() {
+    return Ok(vec![]);
+  }
+  let batch_size = ollama_embed_batch_size();
+  let batch_chars = ollama_embed_batch_chars();
+  let mut out: Vec<Option<Vec<f32>>> = Vec::with_capacity(texts.len());
+  let mut start = 0;
+  while start < texts.len() {
+    let end = embed_batch_end(texts, start, batch_size, batch_chars);
+    let batch = &texts[start..end];
+    match embed_batch_with_retry(ollama, embed_model, batch) {
+      Ok(embeds) => {
+        if embeds.len() == batch.len() {
+          out.extend(embeds.into_iter().map(Some));
+        } else {
+          eprintln!("embed batch count mismatch, retrying per chunk");
+          for text in batch {
+            let emb = embed_chunk_with_fallback(ollama, embed_model, text)?;
+            out.push(emb);
+          }
+        }
+      }
+      Err(err) => {
+        if is_embed_fallback_err(&err) {
+          for text in batch {
+            let emb = embed_chunk_with_fallback(ollama, embed_model, text)?;
+            out.push(emb);
+          }
+        } else {
+          return Err(err);
+        }
+      }
+    }
+    start = end;
+    on_progress(start, texts.len());
+  }
+  Ok(out)
+}
+
+fn sanitize_fts_token(token: &str) -> String {
+  token
+    .chars()
+    .filter(|c| c.is_alphanumeric())
+    .collect()
+}
+
+const STOPWORDS_EN: &[&str] = &[
+  "a", "an", "and", "are", "as", "at", "be", "but", "by", "do", "does", "for", "from", "how", "in", "is", "it",
+  "of", "on", "or", "that", "the", "this", "to", "was", "what", "when", "where", "which", "who", "why", "with",
+];
+
+const STOPWORDS_PL: &[&str] = &[
+  "a", "aby", "ale", "albo", "czy", "do", "i", "jak", "jaki", "jest", "lub", "na", "nie", "o", "od", "oraz",
+  "po", "przez", "się", "są", "ta", "te", "to", "w", "we", "z", "za", "ze", "że", "co", "gdzie", "kiedy",
+];
+
+// Ordered longest first so the most specific ending is stripped.
+const SUFFIXES_EN: &[&str] = &["ations", "ation", "ings", "ing", "edly", "ed", "es", "ly", "s"];
+const SUFFIXES_PL: &[&str] = &[
+  "ami", "ach", "owi", "ego", "emu", "ymi", "imi", "ów", "om", "ie", "ej", "ą", "ę", "a", "y", "i", "u", "e",
+];
+
+/// Queries with this many tokens or fewer keep their stop words, otherwise they could end up empty.
+const FTS_MIN_TOKENS_FOR_STOPWORDS: usize = 2;
+
+#[derive(Clone, Copy, Default)]
+struct FtsQueryOptions<'a> {
+  lang: Option<&'a str>,
+  stopwords: bool,
+  stemming: bool,
+}
+
+fn is_stopword(token: &str, lang: Option<&str>) -> bool {
+  let lower = token.to_lowercase();
+  match lang {
+    Some("eng") => STOPWORDS_EN.contains(&lower.as_str()),
+    Some("pol") => STOPWORDS_PL.contains(&lower.as_str()),
+    _ => STOPWORDS_EN.contains(&lower.as_str()) || STOPWORDS_PL.contains(&lower.as_str()),
+  }
+}
+
+/// Strips one common inflectional ending; the query uses prefix matching, so the stem still hits every form.
+fn light_stem(token: &str, lang: Option<&str>) -> String {
+  let lower = token.to_lowercase();
+  let suffixes: &[&str] = match lang {
+    Some("pol") => SUFFIXES_PL,
+    Some("eng") => SUFFIXES_EN,
+    _ => return lower,
+  };
+  for suffix in suffixes {
+    if let Some(stem) = lower.strip_suffix(suffix) {
+      if stem.chars().count() >= 4 {
+        return stem.to_string();
+      }
+    }
+  }
+  lower
+}
+
+fn build_fts_query(input: &str, opts: FtsQueryOptions) -> Option<String> {
+  let raw: Vec<String> = input
+    .split_whitespace()
+    .map(sanitize_fts_token)
+    .filter(|t| !t.is_empty())
+    .collect();
+
+  let mut tokens = raw.clone();
+  if opts.stopwords && raw.len() > FTS_MIN_TOKENS_FOR_STOPWORDS {
+    let kept: Vec<String> = raw.iter().filter(|t| !is_stopword(t, opts.lang)).cloned().collect();
+    if !kept.is_empty() {
+      tokens = kept;
+    }
+  }
+  if opts.stemming {
+    tokens = tokens.iter().map(|t| light_stem(t, opts.lang)).collect();
+  }
+
+  let tokens: Vec<String> = tokens
+    .into_iter()
+    .filter(|t| t.len() > 1)
+    .map(|t| format!("{t}*"))
+    .collect();
+  if tokens.is_empty() {
+    None
+  } else {
+    Some(tokens.join(" "))
+  }
+}
+
+/// Trigram tables match substrings, so prefix markers go and terms shorter than a trigram are dropped.
+fn trigram_fts_query(query: &str) -> Option<String> {
+  let terms: Vec<&str> = query
+    .split_whitespace()
+    .map(|t| t.trim_end_matches('*'))
+    .filter(|t| t.chars().count() >= 3)
+    .collect();
+  if terms.is_empty() { None } else { Some(terms.join(" ")) }
+}
+
+/// MATCH expression for `input` against the tokenizer the index was built with.
+fn fts_match_query(conn: &Connection, input: &str, opts: FtsQueryOptions) -> Option<String> {
+  let trigram = meta_value(conn, "fts_tokenizer").as_deref() == Some(FtsTokenizer::Trigram.as_sql());
+  build_fts_query(input, opts)
+    .map(|q| fold_for_fts(&q))
+    .and_then(|q| if trigram { trigram_fts_query(&q) } else { Some(q) })
+}
+
+/// Upper bound on `search_fts` results per call.
+const MAX_FTS_SEARCH_LIMIT: usize = 500;
+
+/// Keyword-only search: bm25-ranked FTS5 hits without embedding the query, so it needs no
+/// Ollama. `distance` holds the bm25 score (more negative is a better match).
+pub fn search_fts(app: &AppHandle, query: &str, limit: usize) -> Result<Vec<Source>> {
+  let conn = open_db_read_only(app)?;
+  fts_search(&conn, query, limit)
+}
+
+fn fts_search(conn: &Connection, query: &str, limit: usize) -> Result<Vec<Source>> {
+  if !has_table(conn, "chunks_fts")? {
+    return Ok(Vec::new());
+  }
+  let lang = detect_lang_code(query);
+  let opts = FtsQueryOptions { lang: lang.as_deref(), ..Default::default() };
+  let Some(fts_query) = fts_match_query(conn, query, opts) else { return Ok(Vec::new()) };
+
+  let mut stmt = conn.prepare(
+    "SELECT c.id, c.file_path, c.page, c.chunk_index, c.text, bm25(chunks_fts) AS score
+     FROM chunks_fts
+     JOIN chunks c ON c.id = chunks_fts.rowid
+     WHERE chunks_fts MATCH ?1
+     ORDER BY score
+     LIMIT ?2",
+  )?;
+  let limit = limit.clamp(1, MAX_FTS_SEARCH_LIMIT) as i64;
+  let rows = stmt.query_map(params![fts_query, limit], |r| {
+    Ok(Source {
+      id: r.get(0)?,
+      file_path: r.get(1)?,
+      page: r.get(2)?,
+      chunk_index: r.get(3)?,
+      snippet: r.get(4)?,
+      distance: r.get(5)?,
+      origin: None,
+      highlight: None,
+      title: None,
+      author: None,
+    })
+  })?;
+  let mut sources = rows.collect::<rusqlite::Result<Vec<Source>>>()?;
+  let origins = chunk_origins(conn, sources.iter().map(|s| s.id));
+  let terms = highlight_terms(&fts_query);
+  for s in &mut sources {
+    s.origin = origins.get(&s.id).cloned();
+    (s.snippet, s.highlight) = snippet_with_highlight(&s.snippet, &terms, default_snippet_max_chars());
+  }
+  fill_doc_meta(conn, &mut sources);
+  Ok(sources)
+}
+
+/// Folds the accents unicode61 strips (plus the letters `fold_for_fts` maps) so displayed
+/// text can be matched against the folded FTS terms.
+fn fold_match_char(c: char) -> char {
+  match c {
+    'ą' | 'á' | 'à' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+    'ć' | 'č' | 'ç' => 'c',
+    'ď' | 'đ' => 'd',
+    'ę' | 'é' | 'è' | 'ê' | 'ë' | 'ě' => 'e',
+    'í' | 'ì' | 'î' | 'ï' => 'i',
+    'ł' => 'l',
+    'ń' | 'ň' | 'ñ' => 'n',
+    'ó' | 'ò' | 'ô' | 'ö' | 'õ' | 'ø' => 'o',
+    'ř' => 'r',
+    'ś' | 'š' => 's',
+    'ť' => 't',
+    'ú' | 'ù' | 'û' | 'ü' | 'ů' => 'u',
+    'ý' | 'ÿ' => 'y',
+    'ź' | 'ż' | 'ž' => 'z',
+    _ => c,
+  }
+}
+
+fn fold_for_match(word: &str) -> String {
+  word.chars().flat_map(char::to_lowercase).map(fold_match_char).collect()
+}
+
+/// (term, is_prefix) pairs from a MATCH expression; trigram queries carry no `*` and match substrings.
+fn highlight_terms(fts_query: &str) -> Vec<(String, bool)> {
+  fts_query
+    .split_whitespace()
+    .map(|t| (fold_for_match(t.trim_end_matches('*')), t.ends_with('*')))
+    .filter(|(t, _)| !t.is_empty())
+    .collect()
+}
+
+/// Window of at most `max_chars` around the first keyword match, with the matched words'
+/// offsets; `None` when no word matches.
+fn keyword_highlight(text: &str, terms: &[(String, bool)], max_chars: Option<usize>) -> Option<SnippetHighlight> {
+  if terms.is_empty() {
+    return None;
+  }
+  let chars: Vec<char> = text.chars().collect();
+  let mut ranges: Vec<[usize; 2]> = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    if !chars[i].is_alphanumeric() {
+      i += 1;
+      continue;
+    }
+    let start = i;
+    while i < chars.len() && chars[i].is_alphanumeric() {
+      i += 1;
+    }
+    let word = fold_for_match(&chars[start..i].iter().collect::<String>());
+    let hit = terms
+      .iter()
+      .any(|(term, prefix)| if *prefix { word.starts_with(term.as_str()) } else { word.contains(term.as_str()) });
+    if hit {
+      ranges.push([start, i]);
+    }
+  }
+  let first = ranges.first()?[0];
+
+  let max = max_chars.unwrap_or(chars.len()).max(1);
+  let (mut start, mut end) = (0, chars.len());
+  if chars.len() > max {
+    // Lead in with a little context before the first match, starting on a word boundary.
+    start = first.saturating_sub(max / 4);
+    while start > 0 && start < first && !chars[start - 1].is_whitespace() {
+      start += 1;
+    }
+    end = (start + max).min(chars.len());
+  }
+  let lead = if start > 0 { "…" } else { "" };
+  let tail = if end < chars.len() { "…" } else { "" };
+  let offset = lead.chars().count();
+  let window: String = chars[start..end].iter().collect();
+  let ranges = ranges
+    .into_iter()
+    .filter(|[s, e]| *s >= start && *e <= end)
+    .map(|[s, e]| [s - start + offset, e - start + offset])
+    .collect();
+  Some(SnippetHighlight { text: format!("{lead}{window}{tail}"), ranges })
+}
+
+/// The displayed snippet for a full chunk text: the window around the first keyword match
+/// when there is one, otherwise the first `max_chars`.
+fn snippet_with_highlight(text: &str, terms: &[(String, bool)], max_chars: Option<usize>) -> (String, Option<SnippetHighlight>) {
+  match keyword_highlight(text, terms, max_chars) {
+    Some(h) => (h.text.clone(), Some(h)),
+    None => (truncate_snippet(text, max_chars), None),
+  }
+}
+
+fn fetch_fts_ranks(conn: &Connection, query: &str, limit: usize) -> HashMap<i64, usize> {
+  let mut ranks = HashMap::new();
+  let mut stmt = match conn.prepare(
+    "SELECT rowid, bm25(chunks_fts) AS score
+     FROM chunks_fts
+     WHERE chunks_fts MATCH ?1
+     ORDER BY score
+     LIMIT ?2",
+  ) {
+    Ok(stmt) => stmt,
+    Err(_) => return ranks,
+  };
+
+  let mut rows = match stmt.query(params![query, limit as i64]) {
+    Ok(rows) => rows,
+    Err(_) => return ranks,
+  };
+
+  let mut idx = 1usize;
+  while let Ok(Some(row)) = rows.next() {
+    if let Ok(id) = row.get::<_, i64>(0) {
+      ranks.insert(id, idx);
+      idx += 1;
+    }
+  }
+  ranks
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
+  if a.len() != b.len() || a.is_empty() {
+    return 0.0;
+  }
+  let mut dot = 0.0f64;
+  let mut norm_a = 0.0f64;
+  let mut norm_b = 0.0f64;
+  for i in 0..a.len() {
+    let av = a[i] as f64;
+    let bv = b[i] as f64;
+    dot += av * bv;
+    norm_a += av * av;
+    norm_b += bv * bv;
+  }
+  if norm_a == 0.0 || norm_b == 0.0 {
+    return 0.0;
+  }
+  dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+fn now_ts() -> i64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+fn has_table(conn: &Connection, name: &str) -> Result<bool> {
+  let exists: Option<i32> = conn
+    .query_row(
+      "SELECT 1 FROM sqlite_master WHERE type='table' AND name=?1 LIMIT 1",
+      params![name],
+      |r| r.get(0),
+    )
+    .ok();
+  Ok(exists.is_some())
+}
+
+fn load_indexed_hashes(conn: &Connection) -> Result<HashMap<String, String>> {
+  if !has_table(conn, "files")? {
+    return Ok(HashMap::new());
+  }
+
+  let mut map = HashMap::new();
+  let mut stmt = conn.prepare("SELECT path, hash FROM files")?;
+  let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?;
+  for row in rows {
+    let (path, hash) = row?;
+    map.insert(path, hash);
+  }
+  Ok(map)
+}
+
+fn load_indexed_times(conn: &Connection) -> Result<HashMap<String, i64>> {
+  if !has_table(conn, "files")? {
+    return Ok(HashMap::new());
+  }
+  let mut stmt = conn.prepare("SELECT path, indexed_at FROM files WHERE indexed_at IS NOT NULL")?;
+  let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?)))?;
+  Ok(rows.collect::<rusqlite::Result<HashMap<_, _>>>()?)
+}
+
+fn ensure_targets_schema(conn: &Connection) -> Result<()> {
+  conn.execute_batch(
+    "CREATE TABLE IF NOT EXISTS targets(
+       path TEXT NOT NULL,
+       kind TEXT NOT NULL,
+       include_subfolders INTEGER NOT NULL,
+       added_at INTEGER NOT NULL,
+       PRIMARY KEY(path, kind)
+     );"
+  )?;
+  let _ = conn.execute("ALTER TABLE targets ADD COLUMN exclude_paths TEXT", []);
+  let _ = conn.execute("ALTER TABLE targets ADD COLUMN exclude_globs TEXT", []);
+  let _ = conn.execute("ALTER TABLE targets ADD COLUMN default_ignores INTEGER", []);
+  Ok(())
+}
+
+fn matches_target(path: &Path, target: &IndexTarget) -> bool {
+  if target.path.trim().is_empty() {
+    return false;
+  }
+  let target_path = PathBuf::from(&target.path);
+  if target.is_excluded(path) {
+    return false;
+  }
+  match target.kind {
+    IndexTargetKind::File => path == target_path,
+    IndexTargetKind::Folder => {
+      if target.include_subfolders {
+        path.starts_with(&target_path)
+      } else {
+        path.parent() == Some(target_path.as_path())
+      }
+    }
+  }
+}
+
+fn matches_any_target(path: &Path, targets: &[IndexTarget]) -> bool {
+  targets.iter().any(|t| matches_target(path, t))
+}
+
+pub fn list_targets(app: &AppHandle) -> Result<Vec<IndexTarget>> {
+  let conn = open_db(app)?;
+  ensure_targets_schema(&conn)?;
+
+  let mut targets = vec![];
+  let mut stmt = conn.prepare(
+    "SELECT path, kind, include_subfolders, exclude_paths, exclude_globs, default_ignores FROM targets ORDER BY added_at ASC"
+  )?;
+  let rows = stmt.query_map([], |r| {
+    let path: String = r.get(0)?;
+    let kind_str: String = r.get(1)?;
+    let include_subfolders: i64 = r.get(2)?;
+    let exclude_paths: Option<String> = r.get(3)?;
+    let exclude_globs: Option<String> = r.get(4)?;
+    let default_ignores: Option<i64> = r.get(5)?;
+    let kind = if kind_str == "folder" { IndexTargetKind::Folder } else { IndexTargetKind::File };
+    Ok(IndexTarget {
+      path,
+      kind,
+      include_subfolders: include_subfolders != 0,
+      exclude_paths: exclude_paths
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default(),
+      exclude_globs: exclude_globs
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default(),
+      default_ignores: default_ignores.unwrap_or(1) != 0,
+    })
+  })?;
+
+  for row in rows {
+    targets.push(row?);
+  }
+  Ok(targets)
+}
+
+pub fn save_targets(app: &AppHandle, targets: Vec<IndexTarget>) -> Result<()> {
+  let mut conn = open_db(app)?;
+  ensure_targets_schema(&conn)?;
+  let tx = conn.transaction()?;
+  tx.execute("DELETE FROM targets", [])?;
+
+  for target in targets {
+    let kind = match target.kind {
+      IndexTargetKind::Folder => "folder",
+      IndexTargetKind::File => "file",
+    };
+    let exclude_paths = serde_json::to_string(&target.exclude_paths)?;
+    let exclude_globs = serde_json::to_string(&target.exclude_globs)?;
+    tx.execute(
+      "INSERT OR REPLACE INTO targets(path, kind, include_subfolders, added_at, exclude_paths, exclude_globs, default_ignores)
+       VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+      params![
+        target.path,
+        kind,
+        if target.include_subfolders { 1 } else { 0 },
+        now_ts(),
+        exclude_paths,
+        exclude_globs,
+        if target.default_ignores { 1 } else { 0 }
+      ]
+    )?;
+  }
+
+  tx.commit()?;
+  Ok(())
+}
+
+/// Indexed keys covered by `removed` and by none of `remaining`; files another target still
+/// reaches stay indexed.
+fn keys_only_under(keys: Vec<String>, removed: &IndexTarget, remaining: &[IndexTarget]) -> Vec<String> {
+  keys
+    .into_iter()
+    .filter(|key| {
+      let path = Path::new(split_archive_path(key).0);
+      matches_target(path, removed) && !matches_any_target(path, remaining)
+    })
+    .collect()
+}
+
+/// Indexed files only the saved target `path`/`kind` covers; empty when there is no such target.
+fn files_only_under_target(app: &AppHandle, conn: &Connection, path: &str, kind: IndexTargetKind) -> Result<Vec<String>> {
+  let (removed, remaining): (Vec<IndexTarget>, Vec<IndexTarget>) =
+    list_targets(app)?.into_iter().partition(|t| t.path == path && t.kind == kind);
+  let Some(removed) = removed.first() else {
+    return Ok(Vec::new());
+  };
+  if !has_table(conn, "files")? {
+    return Ok(Vec::new());
+  }
+  let mut keys: Vec<String> = Vec::new();
+  let mut stmt = conn.prepare("SELECT path FROM files")?;
+  let rows = stmt.query_map([], |r| r.get::<_, String>(0))?;
+  for row in rows {
+    keys.push(row?);
+  }
+  Ok(keys_only_under(keys, removed, &remaining))
+}
+
+/// Removes one saved target and purges the files only it covered from the index.
+/// Returns the number of files removed.
+pub fn remove_target(app: &AppHandle, path: String, kind: IndexTargetKind, confirm: bool) -> Result<usize> {
+  anyhow::ensure!(confirm, "refusing to delete index entries without confirm: true");
+  let mut conn = open_db(app)?;
+  let keys = files_only_under_target(app, &conn, &path, kind)?;
+  let tx = conn.transaction()?;
+  let kind_str = match kind {
+    IndexTargetKind::Folder => "folder",
+    IndexTargetKind::File => "file",
+  };
+  tx.execute("DELETE FROM targets WHERE path=?1 AND kind=?2", params![path, kind_str])?;
+
+  let mut purged = 0;
+  for key in keys {
+    purged += delete_file_rows(&tx, &key)?;
+  }
+  if purged > 0 {
+    bump_index_version(&tx)?;
+  }
+  tx.commit()?;
+  Ok(purged)
+}
+
+/// Operations that permanently drop embeddings, for `preview_destructive`.
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DestructiveAction {
+  ClearIndex,
+  Prune {
+    targets: Vec<IndexTarget>,
+    #[serde(default)]
+    missing: bool,
+  },
+  RemoveTarget { path: String, target_kind: IndexTargetKind },
+  /// Indexing with these settings and model. A different embedding dimension wipes the index
+  /// in `ensure_schema`; other chunking, context or model changes only re-embed files, which
+  /// keep serving their old chunks until replaced.
+  SettingsChange { settings: Box<IndexSettings>, embed_model: String },
+}
+
+#[derive(Serialize, Default)]
+pub struct DestructivePreview {
+  pub files: usize,
+  pub chunks: usize,
+  /// Files (and their chunks) that will be re-extracted and re-embedded but not deleted first.
+  pub reembed_files: usize,
+  pub reembed_chunks: usize,
+}
+
+fn count_all(conn: &Connection) -> Result<DestructivePreview> {
+  let files: i64 = conn.query_row("SELECT COUNT(*) FROM files", [], |r| r.get(0))?;
+  let chunks: i64 = if has_table(conn, "chunks")? {
+    conn.query_row("SELECT COUNT(*) FROM chunks", [], |r| r.get(0))?
+  } else {
+    0
+  };
+  Ok(DestructivePreview { files: files as usize, chunks: chunks as usize, ..Default::default() })
+}
+
+fn count_paths(conn: &Connection, paths: &[String]) -> Result<DestructivePreview> {
+  let mut chunks = 0i64;
+  if has_table(conn, "chunks")? {
+    for path in paths {
+      let n: i64 = conn.query_row("SELECT COUNT(*) FROM chunks WHERE file_path=?1", params![path], |r| r.get(0))?;
+      chunks += n;
+    }
+  }
+  Ok(DestructivePreview { files: paths.len(), chunks: chunks as usize, ..Default::default() })
+}
+
+/// How many files and chunks `action` would delete, so the UI can ask for an informed confirmation.
+pub fn preview_destructive(app: &AppHandle, action: DestructiveAction) -> Result<DestructivePreview> {
+  let conn = open_db(app)?;
+  if !has_table(&conn, "files")? {
+    return Ok(DestructivePreview::default());
+  }
+  match action {
+    DestructiveAction::ClearIndex => count_all(&conn),
+    DestructiveAction::Prune { targets, missing } => {
+      let paths = prune_paths(&conn, &targets, missing, |_, _, _| Ok(()))?;
+      count_paths(&conn, &paths)
+    }
+    DestructiveAction::RemoveTarget { path, target_kind } => {
+      let paths = files_only_under_target(app, &conn, &path, target_kind)?;
+      count_paths(&conn, &paths)
+    }
+    DestructiveAction::SettingsChange { settings, embed_model } => {
+      let model_changed = meta_value(&conn, "embed_model").is_some_and(|old| !same_embed_model(&old, &embed_model));
+      let old_dim = meta_value(&conn, "embedding_dim").and_then(|v| v.parse::<usize>().ok());
+      // Only a new model can change the dimension; probing it needs Ollama.
+      if model_changed && old_dim.is_some() {
+        let dim = probe_embed_dim(&settings.embed_client()?, &embed_model)?;
+        if old_dim != Some(dim) {
+          return count_all(&conn);
+        }
+      }
+      if model_changed || chunking_changed(&conn, &settings) {
+        let all = count_all(&conn)?;
+        Ok(DestructivePreview { reembed_files: all.files, reembed_chunks: all.chunks, ..Default::default() })
+      } else {
+        Ok(DestructivePreview::default())
+      }
+    }
+  }
+}
+
+/// Drops every indexed file; the only full wipe a command can trigger.
+pub fn clear_index(app: &AppHandle, confirm: bool) -> Result<usize> {
+  anyhow::ensure!(confirm, "refusing to delete index entries without confirm: true");
+  let mut conn = open_db(app)?;
+  let tx = conn.transaction()?;
+  let removed = wipe_index(&tx)?;
+  tx.commit()?;
+  Ok(removed)
+}
+
+/// Deletes every indexed row, document metadata included; returns the number of files removed.
+fn wipe_index(conn: &Connection) -> Result<usize> {
+  if !has_table(conn, "files")? {
+    return Ok(0);
+  }
+  let total_files: i64 = conn.query_row("SELECT COUNT(*) FROM files", [], |r| r.get(0))?;
+  if total_files == 0 {
+    return Ok(0);
+  }
+
+  for table in ["vec_chunks", "chunks_fts", "chunks", "doc_meta"] {
+    if has_table(conn, table)? {
+      conn.execute(&format!("DELETE FROM {table}"), [])?;
+    }
+  }
+  conn.execute("DELETE FROM files", [])?;
+  bump_index_version(conn)?;
+  Ok(total_files as usize)
+}
+
+#[derive(Serialize)]
+pub struct PruneSummary {
+  pub removed_files: usize,
+  pub removed_chunks: usize,
+}
+
+/// Whether the file behind an index key is still there (for archive entries, the entry too).
+fn document_exists(key: &str) -> bool {
+  let (path, entry) = split_archive_path(key);
+  let path = Path::new(path);
+  match entry {
+    None => path.is_file(),
+    Some(entry) => fs::File::open(path)
+      .ok()
+      .and_then(|f| ZipArchive::new(f).ok())
+      .is_some_and(|mut zip| zip.by_name(entry).is_ok()),
+  }
+}
+
+/// Indexed files `prune_index` would drop: those outside `targets` (an empty list keeps every
+/// file) and, with `missing`, those deleted or moved outside the app. `progress` sees each
+/// file before it is checked.
+fn prune_paths(
+  conn: &Connection,
+  targets: &[IndexTarget],
+  missing: bool,
+  mut progress: impl FnMut(usize, usize, &str) -> Result<()>,
+) -> Result<Vec<String>> {
+  let mut paths: Vec<String> = Vec::new();
+  {
+    let mut stmt = conn.prepare("SELECT path FROM files ORDER BY path")?;
+    let rows = stmt.query_map([], |r| r.get::<_, String>(0))?;
+    for row in rows {
+      paths.push(row?);
+    }
+  }
+  let total = paths.len();
+  let mut out = Vec::new();
+  for (i, path) in paths.into_iter().enumerate() {
+    progress(i, total, &path)?;
+    let untargeted = !targets.is_empty() && !matches_any_target(Path::new(split_archive_path(&path).0), targets);
+    if untargeted || (missing && !document_exists(&path)) {
+      out.push(path);
+    }
+  }
+  Ok(out)
+}
+
+/// Drops indexed files outside `targets` and, with `missing`, files that no longer exist, in
+/// one transaction. An empty target list prunes nothing by target; use `clear_index` to wipe
+/// the whole index.
+pub fn prune_index(app: &AppHandle, targets: Vec<IndexTarget>, missing: bool, confirm: bool) -> Result<PruneSummary> {
+  anyhow::ensure!(confirm, "refusing to delete index entries without confirm: true");
+  let mut summary = PruneSummary { removed_files: 0, removed_chunks: 0 };
+  if targets.is_empty() && !missing {
+    return Ok(summary);
+  }
+  let mut conn = open_db(app)?;
+  if !has_table(&conn, "files")? {
+    return Ok(summary);
+  }
+  let paths = prune_paths(&conn, &targets, missing, |current, total, file| {
+    if current % 100 == 0 {
+      app.emit("index_progress", IndexProgress { current, total, file: file.into(), status: "prune".into(), chunk_current: None, chunk_total: None })?;
+    }
+    Ok(())
+  })?;
+
+  let has_chunks = has_table(&conn, "chunks")?;
+  let tx = conn.transaction()?;
+  for path in &paths {
+    if has_chunks {
+      let chunks: i64 = tx.query_row("SELECT COUNT(*) FROM chunks WHERE file_path=?1", params![path], |r| r.get(0))?;
+      summary.removed_chunks += chunks as usize;
+    }
+    summary.removed_files += delete_file_rows(&tx, path)?;
+  }
+  if summary.removed_files > 0 {
+    bump_index_version(&tx)?;
+  }
+  tx.commit()?;
+  let total = paths.len();
+  app.emit("index_progress", IndexProgress { current: total, total, file: "".into(), status: "done".into(), chunk_current: None, chunk_total: None })?;
+  Ok(summary)
+}
+
+/// Deletes one indexed file with its chunks, keyword postings and vectors; returns the
+/// number of `files` rows removed (0 when it was not indexed).
+fn delete_file_rows(conn: &Connection, path: &str) -> Result<usize> {
+  let has_chunks = has_table(conn, "chunks")?;
+  if has_chunks && has_table(conn, "vec_chunks")? {
+    conn.execute(
+      "DELETE FROM vec_chunks WHERE rowid IN (SELECT id FROM chunks WHERE file_path=?1)",
+      params![path],
+    )?;
+  }
+  if has_chunks && has_table(conn, "chunks_fts")? {
+    conn.execute(
+      "DELETE FROM chunks_fts WHERE rowid IN (SELECT id FROM chunks WHERE file_path=?1)",
+      params![path],
+    )?;
+  }
+  if has_chunks {
+    conn.execute("DELETE FROM chunks WHERE file_path=?1", params![path])?;
+  }
+  if has_table(conn, "doc_meta")? {
+    conn.execute("DELETE FROM doc_meta WHERE path=?1", params![path])?;
+  }
+  Ok(conn.execute("DELETE FROM files WHERE path=?1", params![path])?)
+}
+
+/// Drops the given files (and, for archives, their entries) from the index. Paths that
+/// are not indexed are ignored. Returns how many indexed files were removed.
+pub fn forget_files(app: &AppHandle, files: Vec<String>, confirm: bool) -> Result<usize> {
+  anyhow::ensure!(confirm, "refusing to delete index entries without confirm: true");
+  let mut conn = open_db(app)?;
+  if !has_table(&conn, "files")? {
+    return Ok(0);
+  }
+  let tx = conn.transaction()?;
+  let mut removed = 0;
+  for file in files {
+    let mut keys: Vec<String> = Vec::new();
+    {
+      let mut stmt = tx.prepare("SELECT path FROM files WHERE path=?1 OR substr(path, 1, length(?1) + 1) = ?1 || '!'")?;
+      let rows = stmt.query_map(params![file], |r| r.get::<_, String>(0))?;
+      for row in rows {
+        keys.push(row?);
+      }
+    }
+    for key in keys {
+      removed += delete_file_rows(&tx, &key)?;
+    }
+  }
+  if removed > 0 {
+    bump_index_version(&tx)?;
+  }
+  tx.commit()?;
+  Ok(removed)
+}
+
+#[derive(Serialize)]
+pub struct CompactStats {
+  pub orphan_chunks: usize,
+  pub orphan_vectors: usize,
+  pub bytes_before: u64,
+  pub bytes_after: u64,
+  pub reclaimed_bytes: u64,
+}
+
+/// Drops chunks whose file row is gone and vectors whose chunk is gone.
+fn delete_orphans(conn: &Connection) -> Result<(usize, usize)> {
+  if !has_table(conn, "chunks")? {
+    return Ok((0, 0));
+  }
+  let mut orphan_chunks = 0;
+  if has_table(conn, "files")? {
+    if has_table(conn, "chunks_fts")? {
+      conn.execute(
+        "DELETE FROM chunks_fts WHERE rowid IN (SELECT id FROM chunks WHERE file_path NOT IN (SELECT path FROM files))",
+        [],
+      )?;
+    }
+    orphan_chunks = conn.execute("DELETE FROM chunks WHERE file_path NOT IN (SELECT path FROM files)", [])?;
+  }
+  let orphan_vectors = if has_table(conn, "vec_chunks")? {
+    let ids: Vec<i64> = {
+      let mut stmt = conn.prepare("SELECT rowid FROM vec_chunks WHERE rowid NOT IN (SELECT id FROM chunks)")?;
+      let rows = stmt.query_map([], |r| r.get::<_, i64>(0))?;
+      rows.collect::<rusqlite::Result<_>>()?
+    };
+    // vec0 only supports deleting by rowid, one row at a time.
+    for id in &ids {
+      conn.execute("DELETE FROM vec_chunks WHERE rowid=?1", params![id])?;
+    }
+    ids.len()
+  } else {
+    0
+  };
+  Ok((orphan_chunks, orphan_vectors))
+}
+
+fn db_size(app: &AppHandle) -> Result<u64> {
+  Ok(db_files(&app_db_path(app)?)
+    .iter()
+    .filter_map(|p| fs::metadata(p).ok())
+    .map(|m| m.len())
+    .sum())
+}
+
+#[derive(Serialize)]
+pub struct IndexStats {
+  pub file_count: usize,
+  pub chunk_count: usize,
+  /// Sum of the indexed source files' sizes.
+  pub total_size_bytes: u64,
+  /// Database file plus its WAL and shared-memory files.
+  pub db_size_bytes: u64,
+  pub embedding_dim: usize,
+  pub embed_model: String,
+  pub chunk_size: usize,
+  pub chunk_overlap: usize,
+}
+
+/// Read-only counts and settings for a dashboard; all zeros before the first index run.
+pub fn index_stats(app: &AppHandle) -> Result<IndexStats> {
+  let conn = open_db(app)?;
+  let (file_count, total_size_bytes): (i64, i64) = if has_table(&conn, "files")? {
+    conn.query_row("SELECT COUNT(*), COALESCE(SUM(size), 0) FROM files", [], |r| Ok((r.get(0)?, r.get(1)?)))?
+  } else {
+    (0, 0)
+  };
+  let chunk_count: i64 = if has_table(&conn, "chunks")? {
+    conn.query_row("SELECT COUNT(*) FROM chunks", [], |r| r.get(0))?
+  } else {
+    0
+  };
+  let meta_usize = |key: &str| meta_value(&conn, key).and_then(|v| v.parse::<usize>().ok()).unwrap_or(0);
+  Ok(IndexStats {
+    file_count: file_count as usize,
+    chunk_count: chunk_count as usize,
+    total_size_bytes: total_size_bytes.max(0) as u64,
+    db_size_bytes: db_size(app)?,
+    embedding_dim: meta_usize("embedding_dim"),
+    embed_model: meta_value(&conn, "embed_model").unwrap_or_default(),
+    chunk_size: meta_usize("chunk_size"),
+    chunk_overlap: meta_usize("chunk_overlap"),
+  })
+}
+
+/// Removes orphaned rows, rebuilds and optimizes the FTS index, then vacuums the database.
+pub fn compact_index(app: &AppHandle) -> Result<CompactStats> {
+  let bytes_before = db_size(app)?;
+  let mut conn = open_db(app)?;
+
+  let tx = conn.transaction()?;
+  let (orphan_chunks, orphan_vectors) = delete_orphans(&tx)?;
+  if has_table(&tx, "chunks_fts")? {
+    // Re-derive postings from `chunks` so stale entries from missed deletes go away.
+    rebuild_fts(&tx)?;
+    tx.execute("INSERT INTO chunks_fts(chunks_fts) VALUES('optimize')", [])?;
+  }
+  bump_index_version(&tx)?;
+  tx.commit()?;
+
+  conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+  conn.execute_batch("VACUUM;")?;
+  conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+  drop(conn);
+
+  let bytes_after = db_size(app)?;
+  Ok(CompactStats {
+    orphan_chunks,
+    orphan_vectors,
+    bytes_before,
+    bytes_after,
+    reclaimed_bytes: bytes_before.saturating_sub(bytes_after),
+  })
+}
+
+/// Rewrites `path` when it is `old_prefix` itself or lies under it (whole components only,
+/// so `/docs` does not match `/docs2`). Archive keys (`a.zip!entry`) follow their archive.
+fn remap_path(path: &str, old_prefix: &str, new_prefix: &str) -> Option<String> {
+  let rest = path.strip_prefix(old_prefix)?;
+  if !(rest.is_empty() || rest.starts_with(['/', '\\', '!'])) {
+    return None;
+  }
+  Some(format!("{new_prefix}{rest}"))
+}
+
+/// Moves index entries from `old_prefix` to `new_prefix` after a folder move, keeping chunks and
+/// embeddings. Every remapped file must exist at its new location. Returns the files remapped.
+pub fn remap_paths(app: &AppHandle, old_prefix: String, new_prefix: String) -> Result<usize> {
+  let trim = |p: &str| p.trim().trim_end_matches(['/', '\\']).to_string();
+  let (old_prefix, new_prefix) = (trim(&old_prefix), trim(&new_prefix));
+  anyhow::ensure!(!old_prefix.is_empty() && !new_prefix.is_empty(), "both prefixes are required");
+  anyhow::ensure!(old_prefix != new_prefix, "old and new prefix are the same");
+  anyhow::ensure!(Path::new(&new_prefix).exists(), "{new_prefix} does not exist");
+
+  let mut conn = open_db(app)?;
+  ensure_targets_schema(&conn)?;
+  if !has_table(&conn, "files")? {
+    return Ok(0);
+  }
+
+  let mut moves: Vec<(String, String)> = Vec::new();
+  {
+    let mut stmt = conn.prepare("SELECT path FROM files")?;
+    let rows = stmt.query_map([], |r| r.get::<_, String>(0))?;
+    for row in rows {
+      let path = row?;
+      if let Some(new_path) = remap_path(&path, &old_prefix, &new_prefix) {
+        moves.push((path, new_path));
+      }
+    }
+  }
+  let missing: Vec<&str> = moves
+    .iter()
+    .map(|(_, new_path)| split_archive_path(new_path).0)
+    .filter(|p| !Path::new(p).is_file())
+    .collect();
+  if let Some(first) = missing.first() {
+    anyhow::bail!("{} remapped file(s) do not exist, e.g. {first}", missing.len());
+  }
+
+  let has_chunks = has_table(&conn, "chunks")?;
+  let tx = conn.transaction()?;
+  for (old_path, new_path) in &moves {
+    tx.execute("UPDATE files SET path=?2 WHERE path=?1", params![old_path, new_path])?;
+    if has_chunks {
+      tx.execute("UPDATE chunks SET file_path=?2 WHERE file_path=?1", params![old_path, new_path])?;
+    }
+  }
+
+  let mut targets: Vec<(String, Option<String>)> = Vec::new();
+  {
+    let mut stmt = tx.prepare("SELECT path, exclude_paths FROM targets")?;
+    let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+    for row in rows {
+      targets.push(row?);
+    }
+  }
+  for (path, exclude_paths) in targets {
+    let excludes: Vec<String> = exclude_paths
+      .and_then(|raw| serde_json::from_str(&raw).ok())
+      .unwrap_or_default();
+    let excludes: Vec<String> = excludes
+      .into_iter()
+      .map(|e| remap_path(&e, &old_prefix, &new_prefix).unwrap_or(e))
+      .collect();
+    let new_path = remap_path(&path, &old_prefix, &new_prefix).unwrap_or_else(|| path.clone());
+    tx.execute(
+      "UPDATE targets SET path=?2, exclude_paths=?3 WHERE path=?1",
+      params![path, new_path, serde_json::to_string(&excludes)?],
+    )?;
+  }
+  bump_index_version(&tx)?;
+  tx.commit()?;
+  Ok(moves.len())
+}
+
+/// Recomputes `chunks.lang` without re-embedding; `all: false` only fills rows where it is NULL.
+/// Returns how many rows changed.
+pub fn redetect_languages(app: &AppHandle, all: bool) -> Result<usize> {
+  let mut conn = open_db(app)?;
+  if !has_table(&conn, "chunks")? {
+    return Ok(0);
+  }
+  let tx = conn.transaction()?;
+  let mut updated = 0;
+  {
+    let sql = if all {
+      "SELECT id, text, lang FROM chunks"
+    } else {
+      "SELECT id, text, lang FROM chunks WHERE lang IS NULL"
+    };
+    let mut stmt = tx.prepare(sql)?;
+    let mut rows = stmt.query([])?;
+    let mut update = tx.prepare("UPDATE chunks SET lang=?2 WHERE id=?1")?;
+    while let Some(r) = rows.next()? {
+      let id: i64 = r.get(0)?;
+      let text: String = r.get(1)?;
+      let old: Option<String> = r.get(2)?;
+      let lang = detect_lang_code(&text);
+      if lang != old {
+        update.execute(params![id, lang])?;
+        updated += 1;
+      }
+    }
+  }
+  bump_index_version(&tx)?;
+  tx.commit()?;
+  Ok(updated)
+}
+
+/// Conservative estimate so mostly-Polish text (shorter tokens than English) still fits.
+const CHARS_PER_TOKEN_ESTIMATE: usize = 3;
+
+fn chunk_exceeds_context(chunk_size: usize, context_tokens: u64) -> bool {
+  chunk_size.div_ceil(CHARS_PER_TOKEN_ESTIMATE) as u64 > context_tokens
+}
+
+fn warn_if_chunks_exceed_context(app: &AppHandle, ollama: &Ollama, embed_model: &str, settings: &IndexSettings) {
+  let Ok(Some(ctx)) = ollama.context_length(embed_model) else { return };
+  if chunk_exceeds_context(settings.chunk_size, ctx) {
+    let action = if settings.embed_truncate { "will be truncated" } else { "may be rejected" };
+    let msg = format!(
+      "chunk size {} chars (~{} tokens) is larger than {}'s {} token context; long chunks {}",
+      settings.chunk_size,
+      settings.chunk_size.div_ceil(CHARS_PER_TOKEN_ESTIMATE),
+      embed_model,
+      ctx,
+      action
+    );
+    eprintln!("{msg}");
+    let _ = app.emit("index_warning", msg);
+  }
+}
+
+/// Upper bound on documents extracted and embedded at once; 0 uses the CPU count.
+fn index_concurrency(setting: usize, cpus: usize) -> usize {
+  if setting > 0 { setting } else { cpus.max(1) }
+}
+
+/// Shared, read-only inputs of the indexing workers.
+struct IndexJob<'a> {
+  app: &'a AppHandle,
+  ollama: &'a Ollama,
+  embed_model: &'a str,
+  settings: &'a IndexSettings,
+  dim: usize,
+}
+
+enum IndexMessage {
+  /// A worker began extracting this document.
+  Started(String),
+  /// `done` of the document's `of` chunks have vectors.
+  Embedding { file: String, done: usize, of: usize },
+  Prepared(PreparedDocument),
+}
+
+struct PreparedDocument {
+  key: String,
+  outcome: DocumentOutcome,
+}
+
+enum DocumentOutcome {
+  Missing,
+  Unchanged,
+  TooLarge,
+  /// Extraction failed or produced no usable embeddings.
+  Failed,
+  ChangedDuringIndex,
+  /// Same text as indexed; only the fingerprint needs refreshing.
+  Touched { hash: String, size: i64, mtime: i64 },
+  Chunks {
+    kind: DocumentKind,
+    hash: String,
+    size: i64,
+    mtime: i64,
+    text_hash: String,
+    meta: DocMeta,
+    chunks: Vec<PreparedChunk>,
+  },
+}
+
+struct PreparedChunk {
+  page: i32,
+  chunk_index: i32,
+  lang: Option<String>,
+  origin: PageOrigin,
+  text: String,
+  /// Exact text sent to the embedding model (with prefix and context); keys `embed_cache`.
+  input: String,
+  embedding: Vec<f32>,
+}
+
+/// Extracts, chunks and embeds one document off the writer thread. `old` is the indexed
+/// `(hash, text_hash)` of the document, if any.
+fn prepare_document(
+  job: &IndexJob,
+  doc: DocumentCandidate,
+  old: Option<&(String, Option<String>)>,
+  cache: Option<&Connection>,
+  events: &mpsc::SyncSender<Result<IndexMessage>>,
+) -> Result<PreparedDocument> {
+  let IndexJob { app, ollama, embed_model, settings, dim } = *job;
+  let file_str = doc.key();
+  let done = |outcome| Ok(PreparedDocument { key: file_str.clone(), outcome });
+  if !doc.path.is_file() {
+    return done(DocumentOutcome::Missing);
+  }
+
+  let (hash, size, mtime) = document_fingerprint(&doc.path, doc.entry.as_deref())?;
+  let (old_hash, old_text_hash) = match old {
+    Some((h, t)) => (Some(h.as_str()), t.as_deref()),
+    None => (None, None),
+  };
+  if old_hash == Some(hash.as_str()) {
+    return done(DocumentOutcome::Unchanged);
+  }
+  if exceeds_max_file_bytes(size, settings.max_file_bytes) {
+    eprintln!("index skip {}: {} bytes exceeds max_file_bytes", file_str, size);
+    return done(DocumentOutcome::TooLarge);
+  }
+
+  let _ = events.send(Ok(IndexMessage::Started(file_str.clone())));
+  let (pages, origins) = match extract_text_for_document(app, &doc, settings)
+    .with_context(|| ExtractFailure(file_str.clone())) {
+    Ok(extracted) => {
+      if !extracted.page_report.is_empty() {
+        let _ = app.emit("page_density", PageDensityReport { file: file_str.clone(), pages: extracted.page_report });
+      }
+      (extracted.pages, extracted.origins)
+    }
+    Err(e) => {
+      eprintln!("index skip {}: {}", file_str, e);
+      let _ = app.emit("index_file_error", IndexError::from_anyhow(&e, Some(&file_str)));
+      return done(DocumentOutcome::Failed);
+    }
+  };
+
+  // A file still being written (e.g. mid-sync) would yield partial text; leave it for the next event.
+  let changed_during_index = || {
+    document_fingerprint(&doc.path, doc.entry.as_deref())
+      .map(|(h, _, _)| h != hash)
+      .unwrap_or(true)
+  };
+  if changed_during_index() {
+    eprintln!("index skip {}: changed during extraction", file_str);
+    return done(DocumentOutcome::ChangedDuringIndex);
+  }
+
+  let new_text_hash = text_hash(&pages);
+  if settings.verify_text_hash && old_text_hash == Some(new_text_hash.as_str()) {
+    // Touched but not edited: keep the existing chunks and only refresh the fingerprint.
+    return done(DocumentOutcome::Touched { hash, size, mtime });
+  }
+
+  let code_lang = (doc.kind == DocumentKind::Code)
+    .then(|| code_language(Path::new(doc.entry.as_deref().unwrap_or(&file_str))));
+  let (chunk_meta, chunk_texts) = chunk_pages(&pages, &origins, doc.kind, code_lang, settings);
+
+  let embeds = if chunk_texts.is_empty() {
+    Vec::new()
+  } else {
+    let summary = settings.context_model.as_deref().and_then(|model| document_summary(ollama, model, &pages));
+    let inputs: Vec<String> = chunk_texts
+      .iter()
+      .map(|c| with_embed_prefix(settings.embed_doc_prefix.as_deref(), &contextualize_chunk(summary.as_deref(), c)))
+      .collect();
+    let embeds = embed_with_batches(ollama, embed_model, &inputs, cache, |done, of| {
+      let _ = events.send(Ok(IndexMessage::Embedding { file: file_str.clone(), done, of }));
+    })?;
+    embeds.into_iter().zip(inputs).collect()
+  };
+
+  let had_chunks = !chunk_texts.is_empty();
+  let (chunks, too_long) = embedded_chunks(&file_str, dim, chunk_meta, chunk_texts, embeds);
+  if !settings.embed_truncate {
+    for idx in too_long {
+      let _ = app.emit("index_warning", format!("{file_str}: chunk {idx} exceeds the embedding model input limit and was not indexed"));
+    }
+  }
+
+  if had_chunks && chunks.is_empty() {
+    eprintln!("index skip {}: no embeddings produced", file_str);
+    return done(DocumentOutcome::Failed);
+  }
+
+  if changed_during_index() {
+    eprintln!("index skip {}: changed during embedding", file_str);
+    return done(DocumentOutcome::ChangedDuringIndex);
+  }
+
+  let meta = document_metadata(app, &doc, &pages);
+  done(DocumentOutcome::Chunks { kind: doc.kind, hash, size, mtime, text_hash: new_text_hash, meta, chunks })
+}
+
+/// Pairs chunks with their embeddings, dropping chunks the model did not embed and vectors
+/// whose length differs from the probed `dim`. Also returns the indices the model refused
+/// as too long.
+#[allow(clippy::type_complexity)]
+fn embedded_chunks(
+  file_str: &str,
+  dim: usize,
+  chunk_meta: Vec<(i32, i32, Option<String>, PageOrigin)>,
+  chunk_texts: Vec<String>,
+  embeds: Vec<(Option<Vec<f32>>, String)>,
+) -> (Vec<PreparedChunk>, Vec<usize>) {
+  let mut chunks = Vec::new();
+  let mut too_long = Vec::new();
+  for (idx, (((emb, input), text), (page, chunk_index, lang, origin))) in
+    embeds.into_iter().zip(chunk_texts).zip(chunk_meta).enumerate()
+  {
+    if let Some(emb) = emb {
+      // vec0 columns are fixed-width; one mismatched vector would abort the whole file's transaction.
+      if emb.len() != dim {
+        eprintln!("embed skip: {} (chunk {}) dim {} != {}", file_str, idx, emb.len(), dim);
+        continue;
+      }
+      chunks.push(PreparedChunk { page, chunk_index, lang, origin, text, input, embedding: emb });
+    } else {
+      eprintln!("embed skip: {} (chunk {})", file_str, idx);
+      too_long.push(idx);
+    }
+  }
+  (chunks, too_long)
+}
+
+/// Applies one prepared document on the single writer connection and returns its
+/// `index_progress` status.
+fn write_prepared_document(
+  conn: &mut Connection,
+  embed_model: &str,
+  file_str: String,
+  outcome: DocumentOutcome,
+) -> Result<&'static str> {
+  let (kind, hash, size, mtime, text_hash, meta, chunks) = match outcome {
+    DocumentOutcome::Missing => return Ok("missing"),
+    DocumentOutcome::Unchanged => return Ok("skip"),
+    DocumentOutcome::TooLarge => return Ok("too_large"),
+    DocumentOutcome::Failed => return Ok("error"),
+    DocumentOutcome::ChangedDuringIndex => return Ok("changed_during_index"),
+    DocumentOutcome::Touched { hash, size, mtime } => {
+      conn.execute(
+        "UPDATE files SET hash=?2, size=?3, mtime=?4, indexed_at=?5 WHERE path=?1",
+        params![file_str, hash, size, mtime, now_ts()]
+      )?;
+      return Ok("skip");
+    }
+    DocumentOutcome::Chunks { kind, hash, size, mtime, text_hash, meta, chunks } => (kind, hash, size, mtime, text_hash, meta, chunks),
+  };
+
+  let tx = conn.transaction()?;
+  tx.execute("DELETE FROM vec_chunks WHERE rowid IN (SELECT id FROM chunks WHERE file_path=?1)", params![file_str])?;
+  tx.execute("DELETE FROM chunks_fts WHERE rowid IN (SELECT id FROM chunks WHERE file_path=?1)", params![file_str])?;
+  tx.execute("DELETE FROM chunks WHERE file_path=?1", params![file_str])?;
+  tx.execute(
+    "INSERT OR REPLACE INTO files(path, kind, hash, size, mtime, indexed_at, text_hash, embed_model) VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    params![file_str, kind.as_str(), hash, size, mtime, now_ts(), text_hash, embed_model]
+  )?;
+  tx.execute("DELETE FROM doc_meta WHERE path=?1", params![file_str])?;
+  if !meta.is_empty() {
+    tx.execute(
+      "INSERT INTO doc_meta(path, title, author) VALUES(?1, ?2, ?3)",
+      params![file_str, meta.title, meta.author]
+    )?;
+  }
+
+  for chunk in &chunks {
+    tx.execute(
+      "INSERT INTO chunks(file_path, page, chunk_index, lang, text, origin) VALUES(?1, ?2, ?3, ?4, ?5, ?6)",
+      params![&file_str, chunk.page, chunk.chunk_index, chunk.lang, chunk.text, chunk.origin.as_str()]
+    )?;
+    let id = tx.last_insert_rowid();
+    tx.execute(
+      "INSERT INTO chunks_fts(rowid, text) VALUES(?1, ?2)",
+      params![id, fold_for_fts(&chunk.text)]
+    )?;
+
+    let emb_json = serde_json::to_string(&chunk.embedding)?;
+    tx.execute(
+      "INSERT INTO vec_chunks(rowid, embedding) VALUES(?1, vec_f32(?2))",
+      params![id, emb_json]
+    )?;
+    embed_cache_put(&tx, embed_model, &chunk.input, &chunk.embedding)?;
+  }
+  bump_index_version(&tx)?;
+  tx.commit()?;
+  Ok("done")
+}
+
+/// Runs `worker` on `workers` threads and hands every message they send to `write` on the
+/// calling thread. Workers pull items through `next`, which returns `None` once the run stops.
+/// The channel holds at most two messages per worker, so fast workers wait for the writer
+/// instead of queueing whole prepared documents in memory. The first error from either side,
+/// or `write` returning `Ok(false)`, stops the run.
+fn run_pipeline<T: Send, M: Send>(
+  items: Vec<T>,
+  workers: usize,
+  worker: impl Fn(&(dyn Fn() -> Option<T> + Sync), &mpsc::SyncSender<Result<M>>) + Sync,
+  mut write: impl FnMut(M) -> Result<bool>,
+) -> Result<()> {
+  let workers = workers.max(1);
+  let queue = Mutex::new(items.into_iter());
+  let stop = AtomicBool::new(false);
+  let (tx, rx) = mpsc::sync_channel::<Result<M>>(workers * 2);
+  let next = || if stop.load(AtomicOrdering::Relaxed) { None } else { queue.lock().unwrap().next() };
+  std::thread::scope(|scope| {
+    for _ in 0..workers {
+      let tx = tx.clone();
+      let (next, worker) = (&next, &worker);
+      scope.spawn(move || worker(next, &tx));
+    }
+    drop(tx);
+
+    let result = (|| -> Result<()> {
+      for msg in &rx {
+        if !write(msg?)? {
+          break;
+        }
+      }
+      Ok(())
+    })();
+    // Workers finish the item in hand; with `rx` gone their sends fail instead of blocking,
+    // and the scope joins them.
+    stop.store(true, AtomicOrdering::Relaxed);
+    drop(rx);
+    result
+  })
+}
+
+fn index_documents(
+  app: &AppHandle,
+  docs: Vec<DocumentCandidate>,
+  embed_model: &str,
+  settings: &IndexSettings,
+  emit_progress: bool,
+) -> Result<()> {
+  let ollama = settings.embed_client()?;
+  let total = docs.len();
+  let cancel = app.try_state::<IndexCancel>();
+  let cancel = cancel.as_deref().map(|c| &c.0);
+
+  // The first embed call may block while Ollama loads the model, so report it.
+  if emit_progress {
+    app.emit("index_progress", IndexProgress { current: 0, total, file: embed_model.into(), status: "loading_model".into(), chunk_current: None, chunk_total: None })?;
+  }
+  let Some(dim) = probe_dim_unless_cancelled(cancel, || probe_embed_dim(&ollama, embed_model))? else {
+    app.emit("index_cancelled", IndexCancelled { processed: 0, total })?;
+    return Ok(());
+  };
+  if emit_progress {
+    app.emit("index_progress", IndexProgress { current: 0, total, file: embed_model.into(), status: "ready".into(), chunk_current: None, chunk_total: None })?;
+  }
+  warn_if_chunks_exceed_context(app, &ollama, embed_model, settings);
+
+  let mut conn = open_db(app)?;
+  ensure_schema(&conn, dim, settings)?;
+  mark_other_model_files_stale(&conn, embed_model)?;
+
+  if emit_progress {
+    app.emit("index_progress", IndexProgress { current: 0, total, file: "".into(), status: "start".into(), chunk_current: None, chunk_total: None })?;
+  }
+
+  // Workers only read the database through this snapshot; every write stays on `conn`.
+  let mut known: HashMap<String, (String, Option<String>)> = HashMap::new();
+  {
+    let mut stmt = conn.prepare("SELECT path, hash, text_hash FROM files")?;
+    let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, (r.get(1)?, r.get(2)?))))?;
+    for row in rows {
+      let (path, hashes) = row?;
+      known.insert(path, hashes);
+    }
+  }
+
+  let cancelled = || cancel.is_some_and(|c| c.load(AtomicOrdering::Relaxed));
+  let mut was_cancelled = false;
+
+  let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+  let workers = index_concurrency(settings.max_concurrency, cpus).min(total.max(1));
+  let job = IndexJob { app, ollama: &ollama, embed_model, settings, dim };
+
+  let mut current = 0usize;
+  let result = run_pipeline(
+    docs,
+    workers,
+    |next, events| {
+      // Read-only: vectors found here skip Ollama, new ones are stored by the writer.
+      let cache = open_db(app).ok();
+      while !cancelled() {
+        let Some(doc) = next() else { break };
+        let old = known.get(&doc.key());
+        let prepared = prepare_document(&job, doc, old, cache.as_deref(), events);
+        if events.send(prepared.map(IndexMessage::Prepared)).is_err() {
+          break;
+        }
+      }
+    },
+    |msg| {
+      if cancelled() {
+        // Documents prepared but not yet written are dropped; the next run picks them up.
+        was_cancelled = true;
+        return Ok(false);
+      }
+      let prepared = match msg {
+        IndexMessage::Started(file) => {
+          if emit_progress {
+            app.emit("index_progress", IndexProgress { current: current + 1, total, file, status: "extract".into(), chunk_current: None, chunk_total: None })?;
+          }
+          return Ok(true);
+        }
+        IndexMessage::Embedding { file, done, of } => {
+          if emit_progress {
+            app.emit(
+              "index_progress",
+              IndexProgress { current: current + 1, total, file, status: "embed".into(), chunk_current: Some(done), chunk_total: Some(of) },
+            )?;
+          }
+          return Ok(true);
+        }
+        IndexMessage::Prepared(prepared) => prepared,
+      };
+      current += 1;
+      let status = write_prepared_document(&mut conn, embed_model, prepared.key.clone(), prepared.outcome)?;
+      if emit_progress {
+        app.emit("index_progress", IndexProgress { current, total, file: prepared.key, status: status.into(), chunk_current: None, chunk_total: None })?;
+      }
+      Ok(true)
+    },
+  );
+  if was_cancelled {
+    app.emit("index_cancelled", IndexCancelled { processed: current, total })?;
+  }
+  result?;
+
+  // Only a finished run moves the index to this model; a failed or cancelled one keeps the old name.
+  if !was_cancelled && !meta_value(&conn, "embed_model").is_some_and(|old| same_embed_model(&old, embed_model)) {
+    conn.execute("INSERT OR REPLACE INTO meta(key,value) VALUES('embed_model', ?)", params![embed_model])?;
+  }
+  if emit_progress && !was_cancelled {
+    app.emit("index_done", true)?;
+  }
+  Ok(())
+}
+
+pub fn index_library(app: AppHandle, targets: Vec<IndexTarget>, embed_model: String, settings: IndexSettings) -> Result<()> {
+  let docs = list_documents(&targets, &settings.kind_filter());
+  index_documents(&app, docs, &embed_model, &settings, true)
+}
+
+pub fn index_files(app: &AppHandle, files: Vec<String>, embed_model: String, settings: IndexSettings) -> Result<()> {
+  let mut docs = vec![];
+  for file in files {
+    let path = PathBuf::from(&file);
+    if path.is_file() {
+      docs.extend(document_candidates(&path, &settings.kind_filter()));
+    }
+  }
+  index_documents(app, docs, &embed_model, &settings, true)
+}
+
+/// Incremented on every write that can change retrieval results; keys the chat cache.
+fn bump_index_version(conn: &Connection) -> Result<()> {
+  conn.execute(
+    "INSERT INTO meta(key,value) VALUES('index_version', '1')
+     ON CONFLICT(key) DO UPDATE SET value = CAST(value AS INTEGER) + 1",
+    [],
+  )?;
+  Ok(())
+}
+
+fn meta_value(conn: &Connection, key: &str) -> Option<String> {
+  conn
+    .query_row("SELECT value FROM meta WHERE key=?1", params![key], |r| r.get::<_, String>(0))
+    .ok()
+}
+
+/// Moves the index to a new embedding model. Stored chunk text is re-embedded in place when the
+/// chunking settings still match and contextual chunks are off; otherwise every saved target is
+/// reindexed from scratch. In-place vectors go to `vec_chunks_new` and replace `vec_chunks` in
+/// one transaction at the end, so a failed or interrupted switch leaves the old index usable.
+pub fn switch_embed_model(app: &AppHandle, new_model: String, settings: IndexSettings) -> Result<()> {
+  let ollama = settings.embed_client()?;
+  app.emit("index_progress", IndexProgress { current: 0, total: 0, file: new_model.clone(), status: "loading_model".into(), chunk_current: None, chunk_total: None })?;
+  let dim = probe_embed_dim(&ollama, &new_model)?;
+  app.emit("index_progress", IndexProgress { current: 0, total: 0, file: new_model.clone(), status: "ready".into(), chunk_current: None, chunk_total: None })?;
+
+  let mut conn = open_db(app)?;
+  let has_chunks = has_table(&conn, "chunks")?
+    && conn.query_row("SELECT COUNT(*) FROM chunks", [], |r| r.get::<_, i64>(0))? > 0;
+  let same_chunking = meta_value(&conn, "chunk_size").and_then(|v| v.parse::<usize>().ok()) == Some(settings.chunk_size)
+    && meta_value(&conn, "chunk_overlap").and_then(|v| v.parse::<usize>().ok()) == Some(settings.effective_chunk_overlap())
+    && settings.context_model.is_none()
+    && meta_value(&conn, "embed_doc_prefix") == settings.embed_doc_prefix
+    && meta_value(&conn, "chunk_strategy") == settings.chunk_strategy.meta_value();
+
+  if !has_chunks || !same_chunking {
+    // Nothing reusable: forget file hashes so every target is extracted and embedded again.
+    if has_table(&conn, "files")? {
+      conn.execute("DELETE FROM files", [])?;
+    }
+    // The rebuild writes fresh metadata; rows for files no longer there would linger.
+    if has_table(&conn, "doc_meta")? {
+      conn.execute("DELETE FROM doc_meta", [])?;
+    }
+    drop(conn);
+    app.emit("index_progress", IndexProgress { current: 0, total: 0, file: new_model.clone(), status: "rebuild".into(), chunk_current: None, chunk_total: None })?;
+    let targets = list_targets(app)?;
+    return index_library(app.clone(), targets, new_model, settings);
+  }
+
+  let mut files: Vec<String> = Vec::new();
+  {
+    let mut stmt = conn.prepare("SELECT DISTINCT file_path FROM chunks ORDER BY file_path")?;
+    let rows = stmt.query_map([], |r| r.get::<_, String>(0))?;
+    for row in rows {
+      files.push(row?);
+    }
+  }
+  let total = files.len();
+  app.emit("index_progress", IndexProgress { current: 0, total, file: new_model.clone(), status: "rebuild".into(), chunk_current: None, chunk_total: None })?;
+
+  // Left over from an interrupted switch, possibly with another dimension.
+  conn.execute_batch(&format!(
+    "DROP TABLE IF EXISTS vec_chunks_new;
+     CREATE VIRTUAL TABLE vec_chunks_new
+     USING vec0(embedding float[{dim}] distance_metric=cosine);"
+  ))?;
+  create_embed_cache(&conn)?;
+  let mut unembedded: Vec<i64> = Vec::new();
+
+  for (i, file_str) in files.into_iter().enumerate() {
+    let mut ids: Vec<i64> = Vec::new();
+    let mut texts: Vec<String> = Vec::new();
+    {
+      let mut stmt = conn.prepare("SELECT id, text FROM chunks WHERE file_path=?1 ORDER BY page, chunk_index")?;
+      let rows = stmt.query_map(params![file_str], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))?;
+      for row in rows {
+        let (id, text) = row?;
+        ids.push(id);
+        texts.push(with_embed_prefix(settings.embed_doc_prefix.as_deref(), &text));
+      }
+    }
+
+    let embeds = embed_with_batches(&ollama, &new_model, &texts, Some(&conn), |_, _| {})?;
+    let tx = conn.transaction()?;
+    for (idx, emb) in embeds.into_iter().enumerate() {
+      let id = ids[idx];
+      match emb {
+        Some(emb) if emb.len() == dim => {
+          let emb_json = serde_json::to_string(&emb)?;
+          tx.execute("INSERT INTO vec_chunks_new(rowid, embedding) VALUES(?1, vec_f32(?2))", params![id, emb_json])?;
+          embed_cache_put(&tx, &new_model, &texts[idx], &emb)?;
+        }
+        _ => {
+          eprintln!("embed skip: {} (chunk {})", file_str, idx);
+          unembedded.push(id);
+        }
+      }
+    }
+    tx.commit()?;
+
+    app.emit("index_progress", IndexProgress { current: i + 1, total, file: file_str, status: "done".into(), chunk_current: None, chunk_total: None })?;
+  }
+
+  let tx = conn.transaction()?;
+  for id in &unembedded {
+    // A chunk without a vector would only ever match via FTS, so drop it entirely.
+    tx.execute("DELETE FROM chunks_fts WHERE rowid=?1", params![id])?;
+    tx.execute("DELETE FROM chunks WHERE id=?1", params![id])?;
+  }
+  tx.execute_batch(&format!(
+    "DROP TABLE IF EXISTS vec_chunks;
+     CREATE VIRTUAL TABLE vec_chunks
+     USING vec0(embedding float[{dim}] distance_metric=cosine);
+     INSERT INTO vec_chunks(rowid, embedding) SELECT rowid, embedding FROM vec_chunks_new;
+     DROP TABLE vec_chunks_new;"
+  ))?;
+  tx.execute("INSERT OR REPLACE INTO meta(key,value) VALUES('embedding_dim', ?)", params![dim.to_string()])?;
+  tx.execute("INSERT OR REPLACE INTO meta(key,value) VALUES('embed_model', ?)", params![new_model])?;
+  tx.execute("UPDATE files SET embed_model=?1", params![new_model])?;
+  bump_index_version(&tx)?;
+  tx.commit()?;
+
+  app.emit("index_done", true)?;
+  Ok(())
+}
+
+/// Indexes only new or changed files under the saved targets; the on-demand counterpart to the watcher.
+pub fn scan_targets(app: &AppHandle, embed_model: String, settings: IndexSettings) -> Result<()> {
+  let targets = list_targets(app)?;
+  let indexed = {
+    let conn = open_db(app)?;
+    load_indexed_hashes(&conn)?
+  };
+
+  let mut docs = vec![];
+  for doc in list_documents(&targets, &settings.kind_filter()) {
+    let unchanged = match (indexed.get(&doc.key()), document_fingerprint(&doc.path, doc.entry.as_deref())) {
+      (Some(old), Ok((hash, _, _))) => old == &hash,
+      _ => false,
+    };
+    if !unchanged {
+      docs.push(doc);
+    }
+  }
+
+  index_documents(app, docs, &embed_model, &settings, true)
+}
+
+/// Files per kind whose text is actually extracted to calibrate the chars-per-byte ratio.
+const ESTIMATE_SAMPLES_PER_KIND: usize = 3;
+
+/// Fallback chars-per-byte when no sample of a kind could be extracted.
+fn default_chars_per_byte(kind: DocumentKind) -> f64 {
+  match kind {
+    DocumentKind::Txt | DocumentKind::Md => 1.0,
+    DocumentKind::Docx => 0.3,
+    DocumentKind::Html => 0.4,
+    DocumentKind::Epub => 0.5,
+    DocumentKind::Rtf => 0.5,
+    DocumentKind::Pptx => 0.1,
+    DocumentKind::Csv => 1.0,
+    DocumentKind::Xlsx => 0.2,
+    DocumentKind::Code => 1.0,
+    DocumentKind::Pdf => 0.05,
+  }
+}
+
+fn estimate_chunk_count(chars: usize, chunk_size: usize, chunk_overlap: usize) -> usize {
+  if chars == 0 || chunk_size == 0 {
+    return 0;
+  }
+  let stride = chunk_size.saturating_sub(chunk_overlap.min(chunk_size.saturating_sub(1))).max(1);
+  if chars <= chunk_size {
+    return 1;
+  }
+  1 + (chars - chunk_size).div_ceil(stride)
+}
+
+/// Seconds per chunk for one timed batch of chunk-sized inputs, after a warm-up call loads the model.
+fn benchmark_embed(ollama: &Ollama, embed_model: &str, chunk_size: usize) -> Result<f64> {
+  ollama.embed(embed_model, "warm up")?;
+  let sample: String = "lorem ipsum dolor sit amet ".chars().cycle().take(chunk_size.max(1)).collect();
+  let batch = vec![sample; ollama_embed_batch_size()];
+  let started = std::time::Instant::now();
+  let out = ollama.embed(embed_model, batch.clone())?;
+  anyhow::ensure!(!out.is_empty(), "benchmark returned no embeddings");
+  Ok(started.elapsed().as_secs_f64() / batch.len() as f64)
+}
+
+/// Rough size/time projection for indexing `targets`, without writing to the index.
+pub fn estimate_index(app: &AppHandle, targets: Vec<IndexTarget>, embed_model: String, settings: IndexSettings) -> Result<IndexEstimate> {
+  let docs = list_documents(&targets, &settings.kind_filter());
+  let sample_settings = IndexSettings { ocr_enabled: false, ..settings.clone() };
+
+  // kind -> (total bytes, sampled bytes, sampled chars, samples taken)
+  let mut per_kind: HashMap<&'static str, (u64, u64, usize, usize)> = HashMap::new();
+  let mut bytes = 0u64;
+  for doc in &docs {
+    let size = match &doc.entry {
+      Some(entry) => document_fingerprint(&doc.path, Some(entry)).map(|(_, size, _)| size as u64).unwrap_or(0),
+      None => fs::metadata(&doc.path).map(|m| m.len()).unwrap_or(0),
+    };
+    bytes += size;
+    let slot = per_kind.entry(doc.kind.as_str()).or_insert((0, 0, 0, 0));
+    slot.0 += size;
+    if slot.3 < ESTIMATE_SAMPLES_PER_KIND && size > 0 {
+      // OCR is skipped for sampling; scanned PDFs will be underestimated.
+      if let Ok(extracted) = extract_text_for_document(app, doc, &sample_settings) {
+        slot.1 += size;
+        slot.2 += pages_char_count(&extracted.pages);
+        slot.3 += 1;
+      }
+    }
+  }
+
+  let mut estimated_chunks = 0usize;
+  for kind in [DocumentKind::Pdf, DocumentKind::Txt, DocumentKind::Md, DocumentKind::Docx, DocumentKind::Html, DocumentKind::Epub, DocumentKind::Rtf, DocumentKind::Pptx, DocumentKind::Csv, DocumentKind::Xlsx, DocumentKind::Code] {
+    let Some((total, sampled_bytes, sampled_chars, _)) = per_kind.get(kind.as_str()) else { continue };
+    let ratio = if *sampled_bytes > 0 && *sampled_chars > 0 {
+      *sampled_chars as f64 / *sampled_bytes as f64
+    } else {
+      default_chars_per_byte(kind)
+    };
+    let chars = (*total as f64 * ratio) as usize;
+    estimated_chunks += estimate_chunk_count(chars, settings.chunk_size, settings.effective_chunk_overlap());
+  }
+
+  let ollama = settings.embed_client()?;
+  let estimated_seconds = match benchmark_embed(&ollama, &embed_model, settings.chunk_size) {
+    Ok(per_chunk) => Some(per_chunk * estimated_chunks as f64),
+    Err(e) => {
+      eprintln!("estimate: embed benchmark failed: {e:#}");
+      None
+    }
+  };
+
+  Ok(IndexEstimate { files: docs.len(), bytes, estimated_chunks, estimated_seconds })
+}
+
+pub fn preview_index(
+  app: &AppHandle,
+  targets: Vec<IndexTarget>,
+  max_file_bytes: Option<u64>,
+  kinds: &KindFilter,
+) -> Result<Vec<IndexFilePreview>> {
+  let conn = open_db(app)?;
+  let indexed = load_indexed_hashes(&conn)?;
+  let indexed_at = load_indexed_times(&conn)?;
+  let mut out = vec![];
+
+  for item in list_preview_items(&targets, kinds) {
+    let path_str = document_key(&item.path, item.entry.as_deref());
+    let (status, size, mtime) = if !item.exists {
+      ("missing".to_string(), 0, 0)
+    } else {
+      let (hash, size, mtime) = document_fingerprint(&item.path, item.entry.as_deref())?;
+      let status = match indexed.get(&path_str) {
+        Some(old) if old == &hash => "indexed",
+        _ if exceeds_max_file_bytes(size, max_file_bytes) => "too_large",
+        None => "new",
+        Some(_) => "changed",
+      };
+      (status.to_string(), size, mtime)
+    };
+
+    out.push(IndexFilePreview {
+      indexed_at: indexed_at.get(&path_str).copied(),
+      path: path_str,
+      kind: item.kind.as_str().to_string(),
+      status,
+      size,
+      mtime,
+    });
+  }
+
+  out.sort_by(|a, b| a.path.cmp(&b.path));
+  Ok(out)
+}
+
+/// Files `preview_index` reports as `new` or `changed`, in the form `index_files` takes:
+/// archive entries collapse to their archive, whose unchanged entries are skipped by hash.
+fn changed_preview_paths(preview: &[IndexFilePreview]) -> Vec<String> {
+  let mut out: Vec<String> = Vec::new();
+  for item in preview.iter().filter(|p| p.status == "new" || p.status == "changed") {
+    let (path, _) = split_archive_path(&item.path);
+    if !out.iter().any(|p| p == path) {
+      out.push(path.to_string());
+    }
+  }
+  out
+}
+
+/// Runs the preview for `targets` and returns the files that need indexing, without the
+/// full scan-and-skip of `index_library`.
+pub fn changed_files(app: &AppHandle, targets: Vec<IndexTarget>, settings: &IndexSettings) -> Result<Vec<String>> {
+  let preview = preview_index(app, targets, settings.max_file_bytes, &settings.kind_filter())?;
+  Ok(changed_preview_paths(&preview))
+}
+
+/// Every indexed file with its chunk count, for the library contents view.
+pub fn list_indexed_files(app: &AppHandle) -> Result<Vec<IndexedFile>> {
+  let conn = open_db_read_only(app)?;
+  indexed_files(&conn)
+}
+
+fn indexed_files(conn: &Connection) -> Result<Vec<IndexedFile>> {
+  if !has_table(conn, "files")? {
+    return Ok(vec![]);
+  }
+  let chunk_count = if has_table(conn, "chunks")? {
+    "(SELECT COUNT(*) FROM chunks c WHERE c.file_path = f.path)"
+  } else {
+    "0"
+  };
+  let mut stmt = conn.prepare(&format!(
+    "SELECT f.path, f.kind, f.size, f.mtime, f.indexed_at, {chunk_count} FROM files f ORDER BY f.path"
+  ))?;
+  let rows = stmt.query_map([], |r| {
+    Ok(IndexedFile {
+      path: r.get(0)?,
+      kind: r.get(1)?,
+      size: r.get(2)?,
+      mtime: r.get(3)?,
+      indexed_at: r.get(4)?,
+      chunks: r.get(5)?,
+    })
+  })?;
+  Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+fn context_order_indices(sources: &[Source], order: ContextOrder) -> Vec<usize> {
+  let mut indices: Vec<usize> = (0..sources.len()).collect();
+  if order == ContextOrder::Document {
+    indices.sort_by(|&a, &b| {
+      let (sa, sb) = (&sources[a], &sources[b]);
+      sa.file_path
+        .cmp(&sb.file_path)
+        .then(sa.page.cmp(&sb.page))
+        .then(sa.chunk_index.cmp(&sb.chunk_index))
+    });
+  }
+  indices
+}
+
+pub fn get_file_chunks(app: &AppHandle, path: String) -> Result<Vec<ChunkInfo>> {
+  let conn = open_db(app)?;
+  if !has_table(&conn, "chunks")? {
+    return Ok(vec![]);
+  }
+
+  let mut stmt = conn.prepare(
+    "SELECT id, page, chunk_index, lang, text FROM chunks WHERE file_path=?1 ORDER BY page, chunk_index",
+  )?;
+  let rows = stmt.query_map(params![path], |r| {
+    Ok(ChunkInfo {
+      id: r.get(0)?,
+      page: r.get(1)?,
+      chunk_index: r.get(2)?,
+      lang: r.get(3)?,
+      text: r.get(4)?,
+    })
+  })?;
+
+  let mut out = vec![];
+  for row in rows {
+    out.push(row?);
+  }
+  Ok(out)
+}
+
+fn answer_language_instruction(answer_language: Option<&str>) -> String {
+  match answer_language.map(str::trim).filter(|l| !l.is_empty()) {
+    Some(lang) => format!("Always respond in {lang}, regardless of the language of the question or the sources."),
+    None => "Respond in the same language as the user's question.".to_string(),
+  }
+}
+
+/// Full text of one chunk plus the ids of the chunks before and after it in the same file.
+pub fn get_chunk(app: &AppHandle, id: i64) -> Result<ChunkFull> {
+  let conn = open_db(app)?;
+  let (file_path, page, chunk_index, text): (String, i32, i32, String) = conn
+    .query_row(
+      "SELECT file_path, page, chunk_index, text FROM chunks WHERE id=?1",
+      params![id],
+      |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+    )
+    .with_context(|| format!("chunk {id} not found"))?;
+
+  let prev_id: Option<i64> = conn
+    .query_row(
+      "SELECT id FROM chunks
+       WHERE file_path=?1 AND (page < ?2 OR (page = ?2 AND chunk_index < ?3))
+       ORDER BY page DESC, chunk_index DESC LIMIT 1",
+      params![file_path, page, chunk_index],
+      |r| r.get(0),
+    )
+    .ok();
+  let next_id: Option<i64> = conn
+    .query_row(
+      "SELECT id FROM chunks
+       WHERE file_path=?1 AND (page > ?2 OR (page = ?2 AND chunk_index > ?3))
+       ORDER BY page, chunk_index LIMIT 1",
+      params![file_path, page, chunk_index],
+      |r| r.get(0),
+    )
+    .ok();
+
+  Ok(ChunkFull { id, file_path, page, chunk_index, text, prev_id, next_id })
+}
+
+/// Upper bound on the characters of history sent with a question, on top of the message cap.
+const HISTORY_MAX_CHARS: usize = 8000;
+
+/// The most recent user/assistant turns that fit both `max_messages` and `HISTORY_MAX_CHARS`,
+/// in their original order. Other roles and empty turns are dropped.
+fn trim_history(history: &[ChatMessage], max_messages: usize) -> Vec<ChatMessage> {
+  let mut kept = Vec::new();
+  let mut chars = 0;
+  for m in history.iter().rev() {
+    if kept.len() >= max_messages {
+      break;
+    }
+    if !matches!(m.role.as_str(), "user" | "assistant") || m.content.trim().is_empty() {
+      continue;
+    }
+    chars += m.content.chars().count();
+    if chars > HISTORY_MAX_CHARS {
+      break;
+    }
+    kept.push(m.clone());
+  }
+  kept.reverse();
+  kept
+}
+
+fn build_chat_messages(
+  question: &str,
+  history: &[ChatMessage],
+  sources: &[Source],
+  settings: &RetrievalSettings,
+) -> Vec<ChatMessage> {
+  let mut context_block = String::new();
+  // Citation numbers follow the relevance ranking so [n] still maps to sources[n - 1].
+  for i in context_order_indices(sources, settings.context_order) {
+    let s = &sources[i];
+    let page = s.page + 1;
+    context_block.push_str(&format!(
+      "\n[{}] {} (page {})\n{}\n",
+      i + 1,
+      s.file_path,
+      page,
+      truncate_snippet(&s.snippet, settings.context_max_chars)
+    ));
+  }
+
+  let system = chat_system_prompt(settings);
+  let user = chat_user_prompt(question, &context_block, settings);
+
+  let mut messages = vec![ChatMessage { role: "system".into(), content: system }];
+  messages.extend(trim_history(history, settings.history_max_messages));
+  messages.push(ChatMessage { role: "user".into(), content: user });
+  messages
+}
+
+const DEFAULT_SYSTEM_PROMPT: &str = "You are a RAG assistant. Answer only using the provided sources. If the sources do not contain the answer, say you don't know.";
+
+fn chat_system_prompt(settings: &RetrievalSettings) -> String {
+  let base = settings
+    .system_prompt
+    .as_deref()
+    .map(str::trim)
+    .filter(|p| !p.is_empty())
+    .unwrap_or(DEFAULT_SYSTEM_PROMPT);
+  let mut system = base.to_string();
+  if settings.cite_sources {
+    system.push_str(" Cite sources in brackets [1], [2], etc.");
+  }
+  system.push(' ');
+  system.push_str(&answer_language_instruction(settings.answer_language.as_deref()));
+  system
+}
+
+fn chat_user_prompt(question: &str, context_block: &str, settings: &RetrievalSettings) -> String {
+  if let Some(template) = settings.user_prompt_template.as_deref().filter(|t| !t.trim().is_empty()) {
+    // Fill {context} before {question} so braces typed in the question are left alone.
+    let mut user = template.replace("{context}", context_block).replace("{question}", question);
+    if !template.contains("{context}") {
+      user.push_str(&format!("\n\nSources:\n{}", context_block));
+    }
+    return user;
+  }
+  let closing = if settings.cite_sources { "Answer with citations [1], [2]:" } else { "Answer:" };
+  format!("Question:\n{}\n\nSources:\n{}\n\n{}", question, context_block, closing)
+}
+
+/// Widens the KNN `k` in proportion to how small the scoped share of the index is,
+/// so filtering to the scope still leaves roughly `candidate_k` hits.
+fn scale_candidate_k(candidate_k: i64, total_chunks: i64, scoped_chunks: i64) -> i64 {
+  if scoped_chunks <= 0 || total_chunks <= scoped_chunks {
+    return candidate_k;
+  }
+  let scaled = (candidate_k as f64 * total_chunks as f64 / scoped_chunks as f64).ceil() as i64;
+  scaled.min(total_chunks).min(MAX_KNN_K).max(candidate_k)
+}
+
+/// Cuts `text` to at most `max_chars` characters, preferably at a word boundary, and marks
+/// the cut with an ellipsis. `None` (or text already short enough) returns it unchanged.
+fn truncate_snippet(text: &str, max_chars: Option<usize>) -> String {
+  let Some(max_chars) = max_chars else { return text.to_string() };
+  let Some((cut, _)) = text.char_indices().nth(max_chars) else { return text.to_string() };
+  let head = &text[..cut];
+  // Fall back to the hard cut when the only space is near the start (one long token).
+  let head = match head.rfind(char::is_whitespace) {
+    Some(space) if head[..space].chars().count() >= max_chars / 2 => &head[..space],
+    _ => head,
+  };
+  format!("{}…", head.trim_end())
+}
+
+/// Shorter matches are more likely coincidence than real chunk overlap.
+const MIN_MERGE_OVERLAP: usize = 8;
+
+/// Joins two consecutive chunks, dropping the prefix of `next` that repeats the tail of `prev`.
+fn join_overlapping(prev: &str, next: &str) -> String {
+  let mut shared = 0;
+  for (idx, c) in next.char_indices() {
+    let end = idx + c.len_utf8();
+    if end > prev.len() {
+      break;
+    }
+    if prev.ends_with(&next[..end]) {
+      shared = end;
+    }
+  }
+  if shared >= MIN_MERGE_OVERLAP {
+    // The overlap lines up with the original text, so the remainder continues it directly.
+    return format!("{prev}{}", &next[shared..]);
+  }
+  format!("{prev} {next}")
+}
+
+/// Collapses runs of consecutive chunks from the same page into one source, so chunk overlap does
+/// not repeat sentences in the context. Runs keep the rank of their best member; each snippet
+/// is cut from the merged text around its first match of `terms`.
+fn merge_adjacent_sources(sources: Vec<Source>, terms: &[(String, bool)], snippet_max_chars: Option<usize>) -> Vec<Source> {
+  let mut order: Vec<usize> = (0..sources.len()).collect();
+  order.sort_by(|&a, &b| {
+    let (sa, sb) = (&sources[a], &sources[b]);
+    (sa.file_path.as_str(), sa.page, sa.chunk_index).cmp(&(sb.file_path.as_str(), sb.page, sb.chunk_index))
+  });
+
+  // (best rank, last chunk index, chunks merged, source)
+  let mut runs: Vec<(usize, i32, usize, Source)> = Vec::new();
+  let mut slots: Vec<Option<Source>> = sources.into_iter().map(Some).collect();
+  for idx in order {
+    let Some(src) = slots[idx].take() else { continue };
+    if let Some((rank, last, count, run)) = runs.last_mut() {
+      if run.file_path == src.file_path && run.page == src.page && src.chunk_index == *last + 1 {
+        run.snippet = join_overlapping(&run.snippet, &src.snippet);
+        run.distance = run.distance.min(src.distance);
+        *rank = (*rank).min(idx);
+        *last = src.chunk_index;
+        *count += 1;
+        continue;
+      }
+    }
+    runs.push((idx, src.chunk_index, 1, src));
+  }
+
+  runs.sort_by_key(|(rank, _, _, _)| *rank);
+  runs
+    .into_iter()
+    .map(|(_, _, count, mut src)| {
+      (src.snippet, src.highlight) = snippet_with_highlight(&src.snippet, terms, snippet_max_chars.map(|max| max * count));
+      src
+    })
+    .collect()
+}
+
+/// KNN `k` before scope scaling: padded past `top_k` so distance/language filters still leave enough hits.
+fn base_candidate_k(settings: &RetrievalSettings) -> i64 {
+  let top_k = settings.top_k.max(1);
+  if let Some(pool) = settings.candidate_pool {
+    return pool.max(top_k).min(MAX_KNN_K);
+  }
+  let mut candidate_k = top_k
+    .max(top_k.saturating_mul(settings.knn_multiplier.max(1)))
+    .max(settings.knn_min_k);
+  if settings.use_mmr {
+    // Cap candidate set size to keep MMR latency bounded.
+    let max_mmr = top_k.saturating_mul(4).min(64);
+    let mmr_candidates = settings.mmr_candidates.max(1).min(max_mmr);
+    candidate_k = candidate_k.max(mmr_candidates);
+  }
+  candidate_k.min(MAX_KNN_K)
+}
+
+fn retrieve_sources(
+  conn: &Connection,
+  ollama: &Ollama,
+  question: &str,
+  embed_model: &str,
+  settings: &RetrievalSettings,
+) -> Result<Vec<Source>> {
+  ensure_query_embed_model(conn, embed_model)?;
+  let q = ollama.embed(embed_model, with_embed_prefix(settings.embed_query_prefix.as_deref(), question))?;
+  let q0 = q.first().context("No embedding returned")?;
+  let q_json = serde_json::to_string(q0)?;
+
+  let q_lang = detect_lang_code(question);
+
+  let top_k = settings.top_k.max(1);
+  let mut candidate_k = base_candidate_k(settings);
+
+  let scope: Option<HashSet<&str>> = settings
+    .file_scope
+    .as_ref()
+    .filter(|files| !files.is_empty())
+    .map(|files| files.iter().map(|f| f.as_str()).collect());
+  if let Some(ref scope) = scope {
+    let total: i64 = conn.query_row("SELECT COUNT(*) FROM chunks", [], |r| r.get(0))?;
+    let mut scoped = 0i64;
+    for path in scope {
+      let n: i64 = conn.query_row("SELECT COUNT(*) FROM chunks WHERE file_path=?1", params![path], |r| r.get(0))?;
+      scoped += n;
+    }
+    if scoped == 0 {
+      return Ok(vec![]);
+    }
+    candidate_k = scale_candidate_k(candidate_k, total, scoped);
+  }
+
+  let mut stmt = conn.prepare(
+    "WITH matches AS (
+       SELECT rowid AS id, distance
+       FROM vec_chunks
+       WHERE embedding MATCH vec_f32(?1) AND k = ?2
+       ORDER BY distance
+     )
+     SELECT c.id, c.file_path, c.page, c.chunk_index, c.text, c.lang, m.distance
+     FROM matches m
+     JOIN chunks c ON c.id = m.id
+     ORDER BY m.distance;"
+  )?;
+
+  #[derive(Clone)]
+  struct Candidate {
+    id: i64,
+    file_path: String,
+    page: i32,
+    chunk_index: i32,
+    text: String,
+    lang: Option<String>,
+    distance: f64,
+  }
+
+  let other_model_files = files_embedded_with_other_models(conn, embed_model);
+  let mut rows = stmt.query(params![q_json, candidate_k])?;
+  let mut candidates: Vec<Candidate> = vec![];
+
+  while let Some(r) = rows.next()? {
+    let id: i64 = r.get(0)?;
+    let file_path: String = r.get(1)?;
+    let page: i32 = r.get(2)?;
+    let chunk_index: i32 = r.get(3)?;
+    let text: String = r.get(4)?;
+    let lang: Option<String> = r.get(5)?;
+    let distance: f64 = r.get(6)?;
+
+    if let Some(ref scope) = scope {
+      if !scope.contains(file_path.as_str()) {
+        continue;
       }
     }
 
-    let embeds = if chunk_texts.is_empty() {
-      Vec::new()
-    } else {
-      embed_with_batches(&ollama, embed_model, &chunk_texts)?
-    };
+    if other_model_files.contains(&file_path) {
+      continue;
+    }
 
-    let mut filtered_texts: Vec<String> = Vec::new();
-    let mut filtered_meta: Vec<(i32, i32, Option<String>)> = Vec::new();
-    let mut filtered_embeds: Vec<Vec<f32>> = Vec::new();
-    for (idx, emb) in embeds.into_iter().enumerate() {
-      if let Some(emb) = emb {
-        filtered_texts.push(chunk_texts[idx].clone());
-        filtered_meta.push(chunk_meta[idx].clone());
-        filtered_embeds.push(emb);
-      } else {
-        eprintln!("embed skip: {} (chunk {})", file_str, idx);
+    if let Some(max_dist) = settings.max_distance {
+      if distance > max_dist {
+        continue;
       }
     }
 
-    if !chunk_texts.is_empty() && filtered_texts.is_empty() {
-      if emit_progress {
-        app.emit("index_progress", IndexProgress { current: i + 1, total, file: file_str.clone(), status: "error".into() })?;
+    candidates.push(Candidate { id, file_path, page, chunk_index, text, lang, distance });
+  }
+
+  let mut filtered = if let Some(ql) = filter_language(question, settings.language_filter) {
+    let lang_hits: Vec<Candidate> = candidates
+      .iter()
+      .filter(|c| c.lang.as_deref() == Some(ql.as_str()) || c.lang.as_deref().is_some_and(|l| l.starts_with("code:")))
+      .cloned()
+      .collect();
+    if !lang_hits.is_empty() || settings.language_filter == LanguageFilter::Strict { lang_hits } else { candidates }
+  } else {
+    candidates
+  };
+
+  let fts_opts = FtsQueryOptions {
+    lang: q_lang.as_deref(),
+    stopwords: settings.fts_stopwords,
+    stemming: settings.fts_stemming,
+  };
+  let fts_query = fts_match_query(conn, question, fts_opts);
+  if let Some(fts_query) = &fts_query {
+    if has_table(conn, "chunks_fts")? {
+      let fts_ranks = fetch_fts_ranks(conn, fts_query, candidate_k as usize);
+      if !fts_ranks.is_empty() {
+        let rrf_k = 60.0f64;
+        let mut scored: Vec<(Candidate, f64)> = filtered
+          .iter()
+          .cloned()
+          .enumerate()
+          .map(|(idx, c)| {
+            let v_rank = idx + 1;
+            let mut score = 1.0 / (rrf_k + v_rank as f64);
+            if let Some(f_rank) = fts_ranks.get(&c.id) {
+              score += 1.0 / (rrf_k + *f_rank as f64);
+            }
+            (c, score)
+          })
+          .collect();
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        filtered = scored.into_iter().map(|(c, _)| c).collect();
       }
-      eprintln!("index skip {}: no embeddings produced", file_str);
-      continue;
     }
+  }
 
-    let tx = conn.transaction()?;
-    tx.execute("DELETE FROM vec_chunks WHERE rowid IN (SELECT id FROM chunks WHERE file_path=?1)", params![file_str])?;
-    tx.execute("DELETE FROM chunks_fts WHERE rowid IN (SELECT id FROM chunks WHERE file_path=?1)", params![file_str])?;
-    tx.execute("DELETE FROM chunks WHERE file_path=?1", params![file_str])?;
-    tx.execute(
-      "INSERT OR REPLACE INTO files(path, kind, hash, size, mtime, indexed_at) VALUES(?1, ?2, ?3, ?4, ?5, ?6)",
-      params![file_str, doc.kind.as_str(), hash, size, mtime, now_ts()]
-    )?;
+  let single_file = scope.as_ref().is_some_and(|s| s.len() == 1);
+  if single_file && settings.max_chunks_per_page > 0 {
+    filtered = cap_per_page(filtered, |c| c.page, settings.max_chunks_per_page);
+  }
 
-    for (idx, text) in filtered_texts.iter().enumerate() {
-      let (page, chunk_index, lang) = &filtered_meta[idx];
-      let emb = &filtered_embeds[idx];
-      tx.execute(
-        "INSERT INTO chunks(file_path, page, chunk_index, lang, text) VALUES(?1, ?2, ?3, ?4, ?5)",
-        params![&file_str, page, chunk_index, lang, text]
-      )?;
-      let id = tx.last_insert_rowid();
-      tx.execute(
-        "INSERT INTO chunks_fts(rowid, text) VALUES(?1, ?2)",
-        params![id, text]
-      )?;
+  let top_k = top_k as usize;
+  if filtered.len() > top_k && settings.use_mmr {
+    // Candidates already have their index-time vectors; only embed chunks whose row is missing.
+    let ids: Vec<i64> = filtered.iter().map(|c| c.id).collect();
+    let mut stored = load_vectors(conn, &ids)?;
+    let missing: Vec<usize> = (0..filtered.len()).filter(|&i| !stored.contains_key(&filtered[i].id)).collect();
+    if !missing.is_empty() {
+      let doc_prefix = meta_value(conn, "embed_doc_prefix");
+      let texts: Vec<String> = missing
+        .iter()
+        .map(|&i| with_embed_prefix(doc_prefix.as_deref(), &filtered[i].text))
+        .collect();
+      let embeds = embed_with_batches(ollama, embed_model, &texts, Some(conn), |_, _| {})?;
+      for (i, emb) in missing.into_iter().zip(embeds) {
+        if let Some(emb) = emb {
+          stored.insert(filtered[i].id, emb);
+        }
+      }
+    }
+    let embeds: Vec<Option<&[f32]>> = filtered.iter().map(|c| stored.get(&c.id).map(Vec::as_slice)).collect();
+    let lambda = settings.mmr_lambda.clamp(0.0, 1.0);
 
-      let emb_json = serde_json::to_string(emb)?;
-      tx.execute(
-        "INSERT INTO vec_chunks(rowid, embedding) VALUES(?1, vec_f32(?2))",
-        params![id, emb_json]
-      )?;
+    let mut selected_indices: Vec<usize> = Vec::new();
+    let mut used = vec![false; filtered.len()];
+
+    while selected_indices.len() < top_k {
+      let mut best_idx: Option<usize> = None;
+      let mut best_score = f64::NEG_INFINITY;
+
+      for (i, is_used) in used.iter().enumerate() {
+        if *is_used { continue; }
+        let sim_to_query = embeds[i].map(|e| cosine_similarity(q0, e)).unwrap_or(0.0);
+        let mut max_sim_to_selected = 0.0f64;
+        for sel_idx in &selected_indices {
+          if let (Some(a), Some(b)) = (embeds[i], embeds[*sel_idx]) {
+            let sim = cosine_similarity(a, b);
+            if sim > max_sim_to_selected {
+              max_sim_to_selected = sim;
+            }
+          }
+        }
+
+        let score = (lambda * sim_to_query) - ((1.0 - lambda) * max_sim_to_selected);
+        if score > best_score {
+          best_score = score;
+          best_idx = Some(i);
+        }
+      }
+
+      if let Some(idx) = best_idx {
+        used[idx] = true;
+        selected_indices.push(idx);
+      } else {
+        break;
+      }
     }
-    tx.commit()?;
 
-    if emit_progress {
-      app.emit("index_progress", IndexProgress { current: i + 1, total, file: file_str, status: "done".into() })?;
+    let mut selected: Vec<Candidate> = Vec::new();
+    for idx in selected_indices {
+      if let Some(c) = filtered.get(idx) {
+        selected.push(c.clone());
+      }
     }
+    filtered = selected;
   }
 
-  if emit_progress {
-    app.emit("index_done", true)?;
+  let mut sources: Vec<Source> = vec![];
+  for c in filtered.into_iter().take(top_k) {
+    sources.push(Source {
+      id: c.id,
+      file_path: c.file_path,
+      page: c.page,
+      chunk_index: c.chunk_index,
+      snippet: c.text,
+      distance: c.distance,
+      origin: None,
+      highlight: None,
+      title: None,
+      author: None,
+    });
   }
-  Ok(())
+  let origins = chunk_origins(conn, sources.iter().map(|s| s.id));
+  for source in &mut sources {
+    source.origin = origins.get(&source.id).cloned();
+  }
+  let terms = fts_query.as_deref().map(highlight_terms).unwrap_or_default();
+  let mut sources = merge_adjacent_sources(sources, &terms, settings.snippet_max_chars);
+  fill_doc_meta(conn, &mut sources);
+  Ok(sources)
 }
 
-pub fn index_library(app: AppHandle, targets: Vec<IndexTarget>, embed_model: String, settings: IndexSettings) -> Result<()> {
-  let docs = list_documents(&targets);
-  index_documents(&app, docs, &embed_model, &settings, true)
+/// File name of the document, or of the entry for archive members.
+fn source_file_name(file_path: &str) -> String {
+  let (path, entry) = split_archive_path(file_path);
+  let name = entry.unwrap_or(path);
+  Path::new(name)
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_else(|| name.to_string())
 }
 
-pub fn index_files(app: &AppHandle, files: Vec<String>, embed_model: String, settings: IndexSettings) -> Result<()> {
-  let mut docs = vec![];
-  for file in files {
-    let path = PathBuf::from(&file);
-    if let Some(kind) = kind_from_path(&path) {
-      if path.is_file() {
-        docs.push(DocumentCandidate { path, kind });
-      }
+/// Sets `title`/`author` from `doc_meta`, falling back to the file name for the title.
+/// Databases indexed before the table existed only get the fallback.
+fn fill_doc_meta(conn: &Connection, sources: &mut [Source]) {
+  let mut stmt = conn.prepare("SELECT title, author FROM doc_meta WHERE path=?1").ok();
+  let mut known: HashMap<String, DocMeta> = HashMap::new();
+  for source in sources {
+    let meta = known.entry(source.file_path.clone()).or_insert_with(|| {
+      stmt
+        .as_mut()
+        .and_then(|stmt| {
+          stmt
+            .query_row(params![source.file_path], |r| Ok(DocMeta { title: r.get(0)?, author: r.get(1)? }))
+            .ok()
+        })
+        .unwrap_or_default()
+    });
+    source.title = Some(meta.title.clone().unwrap_or_else(|| source_file_name(&source.file_path)));
+    source.author = meta.author.clone();
+  }
+}
+
+/// `chunks.origin` by id; empty for databases indexed before the column existed.
+fn chunk_origins(conn: &Connection, ids: impl Iterator<Item = i64>) -> HashMap<i64, String> {
+  let Ok(mut stmt) = conn.prepare("SELECT origin FROM chunks WHERE id=?1") else {
+    return HashMap::new();
+  };
+  ids
+    .filter_map(|id| {
+      let origin: Option<String> = stmt.query_row(params![id], |r| r.get(0)).ok()?;
+      origin.map(|o| (id, o))
+    })
+    .collect()
+}
+
+/// Keeps ranking order but drops items once their page already has `cap` entries.
+fn cap_per_page<T>(items: Vec<T>, page: impl Fn(&T) -> i32, cap: usize) -> Vec<T> {
+  let mut per_page: HashMap<i32, usize> = HashMap::new();
+  items
+    .into_iter()
+    .filter(|item| {
+      let n = per_page.entry(page(item)).or_insert(0);
+      *n += 1;
+      *n <= cap
+    })
+    .collect()
+}
+
+const CONFIDENCE_GAP_SCALE: f64 = 0.15;
+
+/// 0..1 estimate of how well retrieval went: the best similarity, discounted when the
+/// remaining sources score about the same (a flat distribution means nothing stood out).
+fn retrieval_confidence(sources: &[Source]) -> f32 {
+  let mut sims: Vec<f64> = sources.iter().map(|s| (1.0 - s.distance).clamp(0.0, 1.0)).collect();
+  if sims.is_empty() {
+    return 0.0;
+  }
+  sims.sort_by(|a, b| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+  let top = sims[0];
+  let gap_factor = if sims.len() == 1 {
+    0.5
+  } else {
+    let rest = sims[1..].iter().sum::<f64>() / (sims.len() - 1) as f64;
+    ((top - rest) / CONFIDENCE_GAP_SCALE).clamp(0.0, 1.0)
+  };
+  (top * (0.6 + 0.4 * gap_factor)) as f32
+}
+
+fn best_distance(sources: &[Source]) -> f64 {
+  sources.iter().map(|s| s.distance).fold(f64::INFINITY, f64::min)
+}
+
+/// First non-empty line of the model's reply, without surrounding quotes or a "Query:" label.
+fn clean_reformulated_query(raw: &str) -> Option<String> {
+  let line = raw.lines().map(str::trim).find(|l| !l.is_empty())?;
+  let line = line
+    .split_once(':')
+    .filter(|(label, _)| label.eq_ignore_ascii_case("query") || label.eq_ignore_ascii_case("zapytanie"))
+    .map(|(_, rest)| rest.trim())
+    .unwrap_or(line);
+  let line = line.trim_matches(|c| matches!(c, '"' | '\'' | '`' | '„' | '”' | '“')).trim();
+  if line.is_empty() { None } else { Some(line.to_string()) }
+}
+
+/// Retrieval with at most one LLM rewrite of the question when the first attempt scores poorly.
+fn retrieve_with_reformulation(
+  conn: &Connection,
+  ollama: &Ollama,
+  question: &str,
+  llm_model: &str,
+  embed_model: &str,
+  settings: &RetrievalSettings,
+) -> Result<Vec<Source>> {
+  let sources = retrieve_sources(conn, ollama, question, embed_model, settings)?;
+  if !settings.auto_reformulate || best_distance(&sources) <= settings.reformulate_distance {
+    return Ok(sources);
+  }
+
+  let messages = vec![
+    ChatMessage {
+      role: "system".into(),
+      content: "Rewrite the user's question as a concise search query for finding relevant passages in their documents. Keep the original language and key terms. Reply with the query only.".into(),
+    },
+    ChatMessage { role: "user".into(), content: question.to_string() },
+  ];
+  let rewritten = match chat_with_retry(|| ollama.chat(llm_model, messages.clone())) {
+    Ok(raw) => clean_reformulated_query(&raw),
+    Err(e) => {
+      eprintln!("query reformulation failed: {e:#}");
+      None
     }
+  };
+  let Some(rewritten) = rewritten.filter(|q| q != question) else {
+    return Ok(sources);
+  };
+
+  let retry = retrieve_sources(conn, ollama, &rewritten, embed_model, settings)?;
+  if best_distance(&retry) < best_distance(&sources) {
+    Ok(retry)
+  } else {
+    Ok(sources)
   }
-  index_documents(app, docs, &embed_model, &settings, true)
 }
 
-pub fn preview_index(app: &AppHandle, targets: Vec<IndexTarget>) -> Result<Vec<IndexFilePreview>> {
-  let conn = open_db(app)?;
-  let indexed = load_indexed_hashes(&conn)?;
-  let mut out = vec![];
+/// Rewrites a follow-up into a question that stands on its own, so retrieval does not have to
+/// guess what "the second one" refers to. Falls back to the question as asked.
+fn contextualize_question(
+  ollama: &Ollama,
+  question: &str,
+  history: &[ChatMessage],
+  llm_model: &str,
+  settings: &RetrievalSettings,
+) -> String {
+  let history = trim_history(history, settings.history_max_messages);
+  if !settings.history_rewrite || history.is_empty() {
+    return question.to_string();
+  }
+  let transcript: String = history
+    .iter()
+    .map(|m| format!("{}: {}\n", m.role, m.content.trim()))
+    .collect();
+  let messages = vec![
+    ChatMessage {
+      role: "system".into(),
+      content: "Given the conversation and a follow-up question, rewrite the follow-up as a standalone search query. Keep the original language and key terms. Reply with the query only.".into(),
+    },
+    ChatMessage {
+      role: "user".into(),
+      content: format!("Conversation:\n{}\nFollow-up: {}", transcript, question),
+    },
+  ];
+  match chat_with_retry(|| ollama.chat(llm_model, messages.clone())) {
+    Ok(raw) => clean_reformulated_query(&raw).unwrap_or_else(|| question.to_string()),
+    Err(e) => {
+      eprintln!("history rewrite failed: {e:#}");
+      question.to_string()
+    }
+  }
+}
 
-  for item in list_preview_items(&targets) {
-    let path_str = item.path.to_string_lossy().to_string();
-    let (status, size, mtime) = if !item.exists {
-      ("missing".to_string(), 0, 0)
-    } else {
-      let (hash, size, mtime) = file_fingerprint(&item.path)?;
-      let status = match indexed.get(&path_str) {
-        None => "new",
-        Some(old) if old == &hash => "indexed",
-        Some(_) => "changed",
-      };
-      (status.to_string(), size, mtime)
-    };
+/// First number in a model's reply, clamped to the 0–10 scale the scoring prompt asks for.
+fn parse_relevance_score(raw: &str) -> Option<f64> {
+  let start = raw.find(|c: char| c.is_ascii_digit())?;
+  let num: String = raw[start..]
+    .chars()
+    .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == ',')
+    .map(|c| if c == ',' { '.' } else { c })
+    .collect();
+  num.trim_end_matches('.').parse::<f64>().ok().map(|v| v.clamp(0.0, 10.0))
+}
 
-    out.push(IndexFilePreview {
-      path: path_str,
-      kind: item.kind.as_str().to_string(),
-      status,
-      size,
-      mtime,
-    });
+fn llm_relevance_score(ollama: &Ollama, model: &str, question: &str, passage: &str) -> Result<f64> {
+  let messages = vec![
+    ChatMessage {
+      role: "system".into(),
+      content: "Rate how well the passage answers the question on a scale from 0 (unrelated) to 10 (answers it directly). Reply with the number only.".into(),
+    },
+    ChatMessage { role: "user".into(), content: format!("Question:\n{question}\n\nPassage:\n{passage}") },
+  ];
+  let raw = ollama.chat(model, messages)?;
+  parse_relevance_score(&raw).with_context(|| format!("no relevance score in reply: {raw}"))
+}
+
+/// Reorders `sources` by `score` (highest first) and keeps `top_k`. Ties and passages that
+/// could not be scored keep their retrieval order; if nothing could be scored the input order
+/// is kept as is.
+fn rerank_sources<F>(sources: Vec<Source>, top_k: usize, mut score: F) -> Vec<Source>
+where
+  F: FnMut(&Source) -> Result<f64>,
+{
+  let scores: Vec<Option<f64>> = sources
+    .iter()
+    .map(|s| match score(s) {
+      Ok(v) => Some(v),
+      Err(e) => {
+        eprintln!("rerank failed for {}: {e:#}", s.file_path);
+        None
+      }
+    })
+    .collect();
+  let mut ranked: Vec<(Source, f64)> = if scores.iter().all(Option::is_none) {
+    sources.into_iter().map(|s| (s, 0.0)).collect()
+  } else {
+    sources.into_iter().zip(scores).map(|(s, v)| (s, v.unwrap_or(-1.0))).collect()
+  };
+  ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+  ranked.into_iter().take(top_k).map(|(s, _)| s).collect()
+}
+
+/// Retrieval for chat: reformulation, then the optional LLM rerank over a wider candidate set.
+fn retrieve_for_chat(
+  conn: &Connection,
+  ollama: &Ollama,
+  question: &str,
+  llm_model: &str,
+  embed_model: &str,
+  settings: &RetrievalSettings,
+) -> Result<Vec<Source>> {
+  if !settings.rerank {
+    return retrieve_with_reformulation(conn, ollama, question, llm_model, embed_model, settings);
   }
-
-  out.sort_by(|a, b| a.path.cmp(&b.path));
-  Ok(out)
+  let mut wide = settings.clone();
+  wide.top_k = settings.top_k.max(settings.rerank_candidates as i64);
+  let sources = retrieve_with_reformulation(conn, ollama, question, llm_model, embed_model, &wide)?;
+  let model = settings.rerank_model.as_deref().unwrap_or(llm_model);
+  Ok(rerank_sources(sources, settings.top_k.max(1) as usize, |s| {
+    llm_relevance_score(ollama, model, question, &s.snippet)
+  }))
 }
 
-fn build_chat_messages(question: &str, sources: &[Source]) -> Vec<ChatMessage> {
-  let mut context_block = String::new();
-  for (i, s) in sources.iter().enumerate() {
-    let page = s.page + 1;
-    context_block.push_str(&format!(
-      "\n[{}] {} (page {})\n{}\n",
-      i + 1,
-      s.file_path,
-      page,
-      s.snippet
-    ));
+fn insufficient_evidence_answer(question: &str, answer_language: Option<&str>) -> String {
+  let lang = match answer_language.map(|l| l.trim().to_lowercase()).filter(|l| !l.is_empty()) {
+    Some(l) if l.starts_with("pl") || l.starts_with("pol") => Some("pol".to_string()),
+    Some(_) => None,
+    None => detect_lang_code(question),
+  };
+  match lang.as_deref() {
+    Some("pol") => "Nie znalazłem w dokumentach wystarczających źródeł, aby odpowiedzieć na to pytanie.".to_string(),
+    _ => "I couldn't find enough evidence in your documents to answer this question.".to_string(),
   }
+}
 
-  let system = "You are a RAG assistant. Answer only using the provided sources. If the sources do not contain the answer, say you don't know. Cite sources in brackets [1], [2], etc. Respond in the same language as the user's question.";
+/// Runs retrieval and assembles the exact messages `chat` would send, without calling the LLM.
+pub fn build_chat_prompt(app: &AppHandle, question: String, embed_model: String, settings: RetrievalSettings) -> Result<PromptPreview> {
+  let ollama = ollama_client(settings.ollama_url.as_deref());
+  let conn = open_db_read_only(app)?;
 
-  let user = format!(
-    "Question:\n{}\n\nSources:\n{}\n\nAnswer with citations [1], [2]:",
-    question, context_block
-  );
+  let sources = retrieve_sources(&conn, &ollama, &question, &embed_model, &settings)?;
+  let messages = build_chat_messages(&question, &[], &sources, &settings);
+  let content = |role: &str| {
+    messages
+      .iter()
+      .find(|m| m.role == role)
+      .map(|m| m.content.clone())
+      .unwrap_or_default()
+  };
 
-  vec![
-    ChatMessage { role: "system".into(), content: system.into() },
-    ChatMessage { role: "user".into(), content: user },
-  ]
+  Ok(PromptPreview { system: content("system"), user: content("user"), sources })
 }
 
-fn retrieve_sources(
-  conn: &Connection,
-  ollama: &Ollama,
+/// Per-request Ollama `options`; anything but a JSON object (or null) is rejected up front.
+fn validate_model_options(options: Option<serde_json::Value>) -> Result<Option<serde_json::Value>> {
+  match options {
+    None | Some(serde_json::Value::Null) => Ok(None),
+    Some(serde_json::Value::Object(map)) if map.is_empty() => Ok(None),
+    Some(v @ serde_json::Value::Object(_)) => Ok(Some(v)),
+    Some(_) => anyhow::bail!("model_options must be a JSON object"),
+  }
+}
+
+fn chat_cache_key(
   question: &str,
+  history: &[ChatMessage],
+  llm_model: &str,
   embed_model: &str,
   settings: &RetrievalSettings,
-) -> Result<Vec<Source>> {
-  let q = ollama.embed(embed_model, question)?;
-  let q0 = q.get(0).context("No embedding returned")?;
-  let q_json = serde_json::to_string(q0)?;
+  model_options: Option<&serde_json::Value>,
+  index_version: &str,
+) -> Result<String> {
+  let history = trim_history(history, settings.history_max_messages);
+  // `ollama_url` is not serialized with the settings; a different host may serve different weights.
+  let key = serde_json::json!([question.trim(), history, llm_model, embed_model, settings, settings.ollama_url, model_options, index_version]);
+  let mut h = Sha256::new();
+  h.update(serde_json::to_vec(&key)?);
+  Ok(format!("{:x}", h.finalize()))
+}
 
-  let q_lang = detect_lang_code(question);
+/// Answers cached per question, models, settings and index version; a new index version
+/// makes older rows unreachable, so they are dropped on the next write. Best-effort: the
+/// lookup reads like the rest of chat, and a failed open or write only costs a cache miss.
+struct ChatCache {
+  conn: Db,
+  key: String,
+  version: String,
+}
 
-  let top_k = settings.top_k.max(1);
-  let mut candidate_k = top_k;
-  if settings.use_mmr {
-    // Cap candidate set size to keep MMR latency bounded.
-    let max_mmr = top_k.saturating_mul(4).min(64);
-    let mmr_candidates = settings.mmr_candidates.max(1).min(max_mmr);
-    candidate_k = candidate_k.max(mmr_candidates);
+impl ChatCache {
+  fn open(
+    app: &AppHandle,
+    question: &str,
+    history: &[ChatMessage],
+    llm_model: &str,
+    embed_model: &str,
+    settings: &RetrievalSettings,
+    model_options: Option<&serde_json::Value>,
+  ) -> Option<Self> {
+    let opened = open_db_read_only(app).and_then(|conn| {
+      let version = meta_value(&conn, "index_version").unwrap_or_else(|| "0".into());
+      let key = chat_cache_key(question, history, llm_model, embed_model, settings, model_options, &version)?;
+      Ok(Self { conn, key, version })
+    });
+    opened.map_err(|e| eprintln!("chat cache unavailable: {e:#}")).ok()
   }
 
-  let mut stmt = conn.prepare(
-    "WITH matches AS (
-       SELECT rowid AS id, distance
-       FROM vec_chunks
-       WHERE embedding MATCH vec_f32(?1) AND k = ?2
-       ORDER BY distance
-     )
-     SELECT c.id, c.file_path, c.page, c.text, c.lang, m.distance
-     FROM matches m
-     JOIN chunks c ON c.id = m.id
-     ORDER BY m.distance;"
-  )?;
+  fn get(&self) -> Option<ChatResult> {
+    let raw: String = self
+      .conn
+      .query_row("SELECT result FROM chat_cache WHERE key=?1", params![self.key], |r| r.get(0))
+      .ok()?;
+    serde_json::from_str(&raw).ok()
+  }
 
-  #[derive(Clone)]
-  struct Candidate {
-    id: i64,
-    file_path: String,
-    page: i32,
-    text: String,
-    lang: Option<String>,
-    distance: f64,
+  fn put(&self, app: &AppHandle, result: &ChatResult) -> Result<()> {
+    let conn = open_db(app)?;
+    conn.busy_timeout(CHAT_CACHE_BUSY_TIMEOUT)?;
+    conn.execute("DELETE FROM chat_cache WHERE index_version != ?1", params![self.version])?;
+    conn.execute(
+      "INSERT OR REPLACE INTO chat_cache(key, index_version, result, created_at) VALUES(?1, ?2, ?3, ?4)",
+      params![self.key, self.version, serde_json::to_string(result)?, now_ts()],
+    )?;
+    Ok(())
   }
+}
 
-  let mut rows = stmt.query(params![q_json, candidate_k])?;
-  let mut candidates: Vec<Candidate> = vec![];
+/// Ranked sources for `question` without generating an answer: the same vector search,
+/// language filter, keyword fusion and MMR that `chat` runs before calling the LLM.
+/// Reformulation and reranking are skipped since they need a chat model.
+pub fn retrieve(app: &AppHandle, question: &str, embed_model: &str, settings: &RetrievalSettings) -> Result<Vec<Source>> {
+  let conn = open_db_read_only(app)?;
+  warn_if_embed_prefix_mismatch(app, &conn, settings);
+  retrieve_sources(&conn, &ollama_client(settings.ollama_url.as_deref()), question, embed_model, settings)
+}
 
-  while let Some(r) = rows.next()? {
-    let id: i64 = r.get(0)?;
-    let file_path: String = r.get(1)?;
-    let page: i32 = r.get(2)?;
-    let text: String = r.get(3)?;
-    let lang: Option<String> = r.get(4)?;
-    let distance: f64 = r.get(5)?;
+#[allow(clippy::too_many_arguments)]
+pub fn chat(
+  app: &AppHandle,
+  question: String,
+  history: Vec<ChatMessage>,
+  llm_model: String,
+  embed_model: String,
+  settings: RetrievalSettings,
+  model_options: Option<serde_json::Value>,
+  bypass_cache: bool,
+) -> Result<ChatResult> {
+  let cache = if bypass_cache {
+    None
+  } else {
+    ChatCache::open(app, &question, &history, &llm_model, &embed_model, &settings, model_options.as_ref())
+  };
+  if let Some(hit) = cache.as_ref().and_then(ChatCache::get) {
+    return Ok(hit);
+  }
+  let result = chat_uncached(app, question, history, llm_model, embed_model, settings, model_options)?;
+  if let Some(cache) = cache {
+    if let Err(e) = cache.put(app, &result) {
+      eprintln!("chat cache write failed: {e:#}");
+    }
+  }
+  Ok(result)
+}
 
-    if let Some(max_dist) = settings.max_distance {
-      if distance > max_dist {
-        continue;
-      }
+#[allow(clippy::too_many_arguments)]
+pub fn chat_stream(
+  app: &AppHandle,
+  question: String,
+  history: Vec<ChatMessage>,
+  llm_model: String,
+  embed_model: String,
+  settings: RetrievalSettings,
+  model_options: Option<serde_json::Value>,
+  bypass_cache: bool,
+) -> Result<ChatResult> {
+  let cache = if bypass_cache {
+    None
+  } else {
+    ChatCache::open(app, &question, &history, &llm_model, &embed_model, &settings, model_options.as_ref())
+  };
+  if let Some(hit) = cache.as_ref().and_then(ChatCache::get) {
+    // Replay through the streaming events so the UI path is the same as a live answer.
+    let _ = app.emit("chat_sources", &hit.sources);
+    let _ = app.emit("chat_delta", &hit.answer);
+    let _ = app.emit("chat_done", &hit);
+    return Ok(hit);
+  }
+  let result = chat_stream_uncached(app, question, history, llm_model, embed_model, settings, model_options)?;
+  if let Some(cache) = cache {
+    if let Err(e) = cache.put(app, &result) {
+      eprintln!("chat cache write failed: {e:#}");
     }
+  }
+  // Lets listeners finalize the message without awaiting the command's return value.
+  let _ = app.emit("chat_done", &result);
+  Ok(result)
+}
 
-    candidates.push(Candidate { id, file_path, page, text, lang, distance });
+fn chat_uncached(
+  app: &AppHandle,
+  question: String,
+  history: Vec<ChatMessage>,
+  llm_model: String,
+  embed_model: String,
+  settings: RetrievalSettings,
+  model_options: Option<serde_json::Value>,
+) -> Result<ChatResult> {
+  let ollama = ollama_client(settings.ollama_url.as_deref()).with_chat_options(validate_model_options(model_options)?);
+  let conn = open_db_read_only(app)?;
+
+  warn_if_embed_prefix_mismatch(app, &conn, &settings);
+  let search_question = contextualize_question(&ollama, &question, &history, &llm_model, &settings);
+  let sources = retrieve_for_chat(&conn, &ollama, &search_question, &llm_model, &embed_model, &settings)?;
+  let language = filter_language(&search_question, settings.language_filter);
+  let confidence = retrieval_confidence(&sources);
+  if sources.len() < settings.min_sources {
+    return Ok(ChatResult { answer: insufficient_evidence_answer(&question, settings.answer_language.as_deref()), sources, confidence, language });
   }
+  let messages = build_chat_messages(&question, &history, &sources, &settings);
+  let answer = chat_with_retry(|| ollama.chat(&llm_model, messages.clone())).map_err(|e| explain_chat_error(e, &llm_model))?;
 
-  let mut filtered = if let Some(ref ql) = q_lang {
-    let lang_hits: Vec<Candidate> = candidates
-      .iter()
-      .cloned()
-      .filter(|c| c.lang.as_deref() == Some(ql.as_str()))
-      .collect();
-    if !lang_hits.is_empty() { lang_hits } else { candidates }
-  } else {
-    candidates
+  Ok(ChatResult { answer, sources, confidence, language })
+}
+
+fn chat_stream_uncached(
+  app: &AppHandle,
+  question: String,
+  history: Vec<ChatMessage>,
+  llm_model: String,
+  embed_model: String,
+  settings: RetrievalSettings,
+  model_options: Option<serde_json::Value>,
+) -> Result<ChatResult> {
+  let ollama = ollama_client(settings.ollama_url.as_deref()).with_chat_options(validate_model_options(model_options)?);
+  let conn = open_db_read_only(app)?;
+
+  warn_if_embed_prefix_mismatch(app, &conn, &settings);
+  let search_question = contextualize_question(&ollama, &question, &history, &llm_model, &settings);
+  let sources = retrieve_for_chat(&conn, &ollama, &search_question, &llm_model, &embed_model, &settings)?;
+  let language = filter_language(&search_question, settings.language_filter);
+  // Sources are final before generation starts, so the UI can show citations while tokens stream.
+  let _ = app.emit("chat_sources", &sources);
+  let confidence = retrieval_confidence(&sources);
+  if sources.len() < settings.min_sources {
+    return Ok(ChatResult { answer: insufficient_evidence_answer(&question, settings.answer_language.as_deref()), sources, confidence, language });
+  }
+  let messages = build_chat_messages(&question, &history, &sources, &settings);
+
+  let mut answer = String::new();
+  let mut saw_delta = false;
+  let attempts = ollama_chat_attempts();
+  let mut attempt = 1;
+  // Reconnecting is only safe before any token reached the UI.
+  let stream_res = loop {
+    let res = ollama.chat_stream(&llm_model, messages.clone(), |delta| {
+      saw_delta = true;
+      answer.push_str(delta);
+      let _ = app.emit("chat_delta", delta);
+    });
+    match res {
+      Err(err) if !saw_delta && attempt < attempts && is_chat_retryable(&err) => {
+        eprintln!("chat stream attempt {attempt} failed, retrying: {err}");
+        std::thread::sleep(ollama_chat_backoff(attempt));
+        attempt += 1;
+      }
+      other => break other,
+    }
   };
 
-  if let Some(fts_query) = build_fts_query(question) {
-    if has_table(conn, "chunks_fts")? {
-      let fts_ranks = fetch_fts_ranks(conn, &fts_query, candidate_k as usize);
-      if !fts_ranks.is_empty() {
-        let rrf_k = 60.0f64;
-        let mut scored: Vec<(Candidate, f64)> = filtered
-          .iter()
-          .cloned()
-          .enumerate()
-          .map(|(idx, c)| {
-            let v_rank = idx + 1;
-            let mut score = 1.0 / (rrf_k + v_rank as f64);
-            if let Some(f_rank) = fts_ranks.get(&c.id) {
-              score += 1.0 / (rrf_k + *f_rank as f64);
-            }
-            (c, score)
-          })
-          .collect();
-        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
-        filtered = scored.into_iter().map(|(c, _)| c).collect();
+  match stream_res {
+    Ok(full) => {
+      if !full.is_empty() {
+        answer = full;
+      }
+    }
+    Err(err) => {
+      if !saw_delta {
+        let fallback =
+          chat_with_retry(|| ollama.chat(&llm_model, messages.clone())).map_err(|e| explain_chat_error(e, &llm_model))?;
+        return Ok(ChatResult { answer: fallback, sources, confidence, language });
       }
+      eprintln!("chat stream error: {}", err);
     }
   }
 
-  let top_k = top_k as usize;
-  if filtered.len() > top_k && settings.use_mmr {
-    let texts: Vec<String> = filtered.iter().map(|c| c.text.clone()).collect();
-    let mut embeds = ollama.embed(embed_model, texts)?;
-    let lambda = settings.mmr_lambda.clamp(0.0, 1.0);
+  Ok(ChatResult { answer, sources, confidence, language })
+}
 
-    if embeds.len() < filtered.len() {
-      filtered.truncate(embeds.len());
-    } else if embeds.len() > filtered.len() {
-      embeds.truncate(filtered.len());
-    }
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::{Path, PathBuf};
 
-    let mut selected_indices: Vec<usize> = Vec::new();
-    let mut used = vec![false; filtered.len()];
+  #[test]
+  fn kind_from_path_detects_supported_extensions() {
+    assert_eq!(kind_from_path(Path::new("doc.pdf"), &[]), Some(DocumentKind::Pdf));
+    assert_eq!(kind_from_path(Path::new("doc.txt"), &[]), Some(DocumentKind::Txt));
+    assert_eq!(kind_from_path(Path::new("doc.md"), &[]), Some(DocumentKind::Md));
+    assert_eq!(kind_from_path(Path::new("doc.markdown"), &[]), Some(DocumentKind::Md));
+    assert_eq!(kind_from_path(Path::new("doc.docx"), &[]), Some(DocumentKind::Docx));
+    assert_eq!(kind_from_path(Path::new("page.HTM"), &[]), Some(DocumentKind::Html));
+    assert_eq!(kind_from_path(Path::new("book.epub"), &[]), Some(DocumentKind::Epub));
+    assert_eq!(kind_from_path(Path::new("letter.RTF"), &[]), Some(DocumentKind::Rtf));
+    assert_eq!(kind_from_path(Path::new("deck.pptx"), &[]), Some(DocumentKind::Pptx));
+    assert_eq!(kind_from_path(Path::new("budget.csv"), &[]), Some(DocumentKind::Csv));
+    assert_eq!(kind_from_path(Path::new("budget.xlsx"), &[]), Some(DocumentKind::Xlsx));
+    assert_eq!(kind_from_path(Path::new("doc.bin"), &[]), None);
+    let code = default_code_extensions();
+    assert_eq!(kind_from_path(Path::new("src/main.RS"), &code), Some(DocumentKind::Code));
+    assert_eq!(kind_from_path(Path::new("notes.md"), &code), Some(DocumentKind::Md));
+    assert_eq!(kind_from_path(Path::new("main.rs"), &[]), None);
+
+    let filter = KindFilter { code_extensions: code.clone(), enabled_kinds: Some(vec!["docx".into(), " MD ".into()]) };
+    assert_eq!(filter.kind_of(Path::new("scan.pdf")), None);
+    assert_eq!(filter.kind_of(Path::new("notes.md")), Some(DocumentKind::Md));
+    assert_eq!(filter.kind_of(Path::new("main.rs")), None);
+    assert_eq!(filter.all_kinds().kind_of(Path::new("scan.pdf")), Some(DocumentKind::Pdf));
+    let empty = KindFilter { code_extensions: vec![], enabled_kinds: Some(vec![]) };
+    assert_eq!(empty.kind_of(Path::new("scan.pdf")), Some(DocumentKind::Pdf));
+    assert_eq!(code_language(Path::new("app/view.tsx")), "code:typescript");
+  }
 
-    while selected_indices.len() < top_k {
-      let mut best_idx: Option<usize> = None;
-      let mut best_score = f64::NEG_INFINITY;
+  #[test]
+  fn supported_document_checks_extension() {
+    assert!(is_supported_document(Path::new("report.pdf"), &KindFilter::default()));
+    assert!(!is_supported_document(Path::new("report.exe"), &KindFilter::default()));
+  }
 
-      for i in 0..filtered.len() {
-        if used[i] { continue; }
-        let sim_to_query = embeds.get(i).map(|e| cosine_similarity(q0, e)).unwrap_or(0.0);
-        let mut max_sim_to_selected = 0.0f64;
-        for sel_idx in &selected_indices {
-          if let (Some(a), Some(b)) = (embeds.get(i), embeds.get(*sel_idx)) {
-            let sim = cosine_similarity(a, b);
-            if sim > max_sim_to_selected {
-              max_sim_to_selected = sim;
-            }
-          }
-        }
+  #[test]
+  fn chunk_text_splits_with_overlap() {
+    let chunks = chunk_text("abcdefgh", 3, 1);
+    assert_eq!(chunks, vec!["abc", "cde", "efg", "gh"]);
+  }
 
-        let score = (lambda * sim_to_query) - ((1.0 - lambda) * max_sim_to_selected);
-        if score > best_score {
-          best_score = score;
-          best_idx = Some(i);
-        }
-      }
+  #[test]
+  fn chunk_text_prefers_boundaries() {
+    let chunks = chunk_text("alpha beta gamma", 10, 0);
+    assert_eq!(chunks, vec!["alpha", "beta", "gamma"]);
+  }
 
-      if let Some(idx) = best_idx {
-        used[idx] = true;
-        selected_indices.push(idx);
-      } else {
-        break;
-      }
+  #[test]
+  fn chunk_text_handles_utf8_without_replacement() {
+    let chunks = chunk_text("zażółć gęślą jaźń", 6, 0);
+    assert!(chunks.iter().all(|c| !c.contains('\u{FFFD}')));
+  }
+
+  #[test]
+  fn chunk_text_keeps_emoji_and_cjk_intact() {
+    let text = "猫が好きです 🐱🐶 東京タワー 👩‍👩‍👧 こんにちは世界 🎉".repeat(8);
+    for (size, overlap) in [(5, 2), (7, 3), (13, 4)] {
+      let chunks = chunk_text(&text, size, overlap);
+      assert!(!chunks.is_empty());
+      assert!(chunks.iter().all(|c| !c.contains('\u{FFFD}')));
+      assert!(chunks.iter().all(|c| c.chars().count() <= size));
     }
+  }
 
-    let mut selected: Vec<Candidate> = Vec::new();
-    for idx in selected_indices {
-      if let Some(c) = filtered.get(idx) {
-        selected.push(c.clone());
+  #[test]
+  fn chunk_text_overlap_starts_on_word() {
+    let chunks = chunk_text("jeden dwa trzy cztery pięć sześć", 16, 4);
+    assert_eq!(chunks, vec!["jeden dwa trzy", "trzy cztery", "cztery pięć", "pięć sześć"]);
+
+    let text = "Umowa najmu lokalu zawarta w Krakowie pomiędzy stronami, które ustaliły czynsz, kaucję \
+      oraz terminy płatności; najemca zobowiązuje się do zwrotu lokalu w stanie niepogorszonym.";
+    for (max_chars, overlap) in [(30, 10), (40, 12), (60, 20)] {
+      let chunks = chunk_text(text, max_chars, overlap);
+      for pair in chunks.windows(2) {
+        let (prev, next) = (&pair[0], &pair[1]);
+        assert!(!prev.ends_with(next.as_str()), "{next:?} is a suffix of {prev:?}");
+        // `next` starts at a word inside `prev`: some prefix of it is a tail of `prev` that follows a space.
+        let overlaps = (1..next.len())
+          .filter(|&k| next.is_char_boundary(k))
+          .any(|k| prev.ends_with(&next[..k]) && prev[..prev.len() - k].ends_with(' '));
+        assert!(overlaps, "{next:?} does not overlap {prev:?} on a word ({max_chars}, {overlap})");
       }
     }
-    filtered = selected;
   }
 
-  let mut sources: Vec<Source> = vec![];
-  for c in filtered.into_iter().take(top_k) {
-    let snippet = c.text.chars().take(600).collect::<String>();
-    sources.push(Source { file_path: c.file_path, page: c.page, snippet, distance: c.distance });
+  #[test]
+  fn split_sentences_guards_abbreviations() {
+    let text = "Umowę podpisał dr Kowalski, tj. najemca. Czynsz wynosi 2000 zł! Czy J. Smith wie? 3 strony.";
+    assert_eq!(
+      split_sentences(text),
+      vec!["Umowę podpisał dr Kowalski, tj. najemca.", "Czynsz wynosi 2000 zł!", "Czy J. Smith wie?", "3 strony."]
+    );
+    assert_eq!(split_sentences("e.g. this stays. one sentence"), vec!["e.g. this stays. one sentence"]);
   }
-  Ok(sources)
-}
 
-pub fn chat(app: &AppHandle, question: String, llm_model: String, embed_model: String, settings: RetrievalSettings) -> Result<ChatResult> {
-  let ollama = Ollama::new();
-  let conn = open_db(app)?;
+  #[test]
+  fn chunk_sentences_packs_and_overlaps_whole_sentences() {
+    let text = "One two. Three four. Five six. Seven eight nine ten eleven twelve.";
+    let chunks = chunk_sentences(text, 21, 12);
+    assert_eq!(chunks[..2], ["One two. Three four.", "Three four. Five six."]);
+    // Only the over-long last sentence is hard-split.
+    assert!(chunks.len() > 3 && chunks[2..].iter().all(|c| c.chars().count() <= 21));
+    assert_eq!(chunk_sentences(text, 21, 0)[1], "Five six.");
+  }
+
+  #[test]
+  fn reflow_ocr_text_joins_lines_and_hyphens() {
+    let raw = "Umowa naj-\nmu lokalu   zawarta\nw Warszawie.\n\n\nStrona  druga-\nTeil\x0CNext page\nline";
+    assert_eq!(
+      reflow_ocr_text(raw),
+      "Umowa najmu lokalu zawarta w Warszawie.\n\nStrona druga- Teil\x0CNext page line"
+    );
+  }
+
+  #[test]
+  fn read_zip_entry_enforces_the_limit() {
+    assert_eq!(read_zip_entry(&b"hello"[..], 5, 5).unwrap(), b"hello");
+    // A lying header cannot make us allocate, and a short declared size cannot smuggle more in.
+    assert!(read_zip_entry(&b"hello"[..], u64::MAX, 5).is_err());
+    assert!(read_zip_entry(&b"hello world"[..], 3, 5).is_err());
+  }
+
+  #[test]
+  fn exceeds_max_file_bytes_only_with_a_limit() {
+    assert!(!exceeds_max_file_bytes(5_000_000_000, None));
+    assert!(!exceeds_max_file_bytes(1024, Some(1024)));
+    assert!(exceeds_max_file_bytes(1025, Some(1024)));
+  }
+
+  #[test]
+  fn index_concurrency_defaults_to_cpus() {
+    assert_eq!(index_concurrency(0, 8), 8);
+    assert_eq!(index_concurrency(0, 0), 1);
+    assert_eq!(index_concurrency(2, 8), 2);
+  }
+
+  #[test]
+  fn embedded_chunks_skip_wrong_dimension_vectors() {
+    let meta = (0..3).map(|i| (1, i, None, PageOrigin::Text)).collect();
+    let texts = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+    let embeds = vec![
+      (Some(vec![0.1, 0.2]), "in a".to_string()),
+      (Some(vec![0.1, 0.2, 0.3]), "in b".to_string()),
+      (None, "in c".to_string()),
+    ];
+    let (chunks, too_long) = embedded_chunks("doc.txt", 2, meta, texts, embeds);
+    assert_eq!(chunks.len(), 1);
+    assert_eq!((chunks[0].chunk_index, chunks[0].text.as_str(), chunks[0].input.as_str()), (0, "a", "in a"));
+    assert_eq!(chunks[0].embedding, vec![0.1, 0.2]);
+    // Only the chunk the model refused counts as too long; the wrong-dim one is just dropped.
+    assert_eq!(too_long, vec![2]);
+  }
+
+  #[test]
+  fn run_pipeline_writes_every_outcome_on_one_thread() {
+    let writer = std::thread::current().id();
+    let mut written = Vec::new();
+    run_pipeline(
+      (0..50).collect(),
+      4,
+      |next, events| {
+        while let Some(i) = next() {
+          // A progress message and the outcome, like prepare_document sends.
+          let _ = events.send(Ok(-1));
+          if events.send(Ok(i)).is_err() {
+            break;
+          }
+        }
+      },
+      |msg: i32| {
+        assert_eq!(std::thread::current().id(), writer);
+        if msg >= 0 {
+          written.push(msg);
+        }
+        Ok(true)
+      },
+    )
+    .unwrap();
+    written.sort();
+    assert_eq!(written, (0..50).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn run_pipeline_stops_on_errors() {
+    // A worker error ends the run even with workers blocked on the full channel.
+    let mut written = 0;
+    let err = run_pipeline(
+      (0..1000).collect(),
+      2,
+      |next, events| {
+        while let Some(i) = next() {
+          let msg = if i == 3 { Err(anyhow::anyhow!("extract failed")) } else { Ok(i) };
+          if events.send(msg).is_err() {
+            break;
+          }
+        }
+      },
+      |_: i32| {
+        written += 1;
+        Ok(true)
+      },
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "extract failed");
+    assert!(written < 1000);
+
+    // So does a writer error.
+    let err = run_pipeline(
+      (0..1000).collect(),
+      2,
+      |next, events| {
+        while let Some(i) = next() {
+          if events.send(Ok(i)).is_err() {
+            break;
+          }
+        }
+      },
+      |_: i32| -> Result<bool> { anyhow::bail!("disk full") },
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "disk full");
+  }
 
-  let sources = retrieve_sources(&conn, &ollama, &question, &embed_model, &settings)?;
-  let messages = build_chat_messages(&question, &sources);
-  let answer = ollama.chat(&llm_model, messages)?;
+  #[test]
+  fn cancel_during_model_probe_stops_the_run() {
+    let state = IndexCancel::default();
+    assert_eq!(probe_dim_unless_cancelled(Some(&state.0), || Ok(768)).unwrap(), Some(768));
+
+    // Pressed while Ollama loads the model: the run must not clear it and carry on.
+    let dim = probe_dim_unless_cancelled(Some(&state.0), || {
+      state.request();
+      Ok(768)
+    });
+    assert_eq!(dim.unwrap(), None);
 
-  Ok(ChatResult { answer, sources })
-}
+    // Pressed before the probe: Ollama is not even asked.
+    let dim = probe_dim_unless_cancelled(Some(&state.0), || panic!("probed after cancel"));
+    assert_eq!(dim.unwrap(), None);
 
-pub fn chat_stream(
-  app: &AppHandle,
-  question: String,
-  llm_model: String,
-  embed_model: String,
-  settings: RetrievalSettings,
-) -> Result<ChatResult> {
-  let ollama = Ollama::new();
-  let conn = open_db(app)?;
+    state.reset();
+    assert_eq!(probe_dim_unless_cancelled(Some(&state.0), || Ok(768)).unwrap(), Some(768));
+  }
 
-  let sources = retrieve_sources(&conn, &ollama, &question, &embed_model, &settings)?;
-  let messages = build_chat_messages(&question, &sources);
+  #[test]
+  fn ocr_concurrency_limit_defaults_to_cpus_minus_one() {
+    assert_eq!(ocr_concurrency_limit(0, 8), 7);
+    assert_eq!(ocr_concurrency_limit(0, 1), 1);
+    assert_eq!(ocr_concurrency_limit(3, 8), 3);
+  }
 
-  let mut answer = String::new();
-  let mut saw_delta = false;
-  let stream_res = ollama.chat_stream(&llm_model, messages, |delta| {
-    saw_delta = true;
-    answer.push_str(delta);
-    let _ = app.emit("chat_delta", delta);
-  });
+  #[test]
+  fn extract_csv_text_labels_cells_with_headers() {
+    let csv = "\u{feff}Kategoria;Kwota;Uwagi\r\nCzynsz;2500;\r\n\"Media; prąd\";310,50;\"rachunek \"\"X\"\"\nza maj\"\r\n";
+    assert_eq!(
+      extract_csv_text(csv),
+      "Kategoria: Czynsz | Kwota: 2500\nKategoria: Media; prąd | Kwota: 310,50 | Uwagi: rachunek \"X\" za maj"
+    );
+    assert_eq!(extract_csv_text("a,b\n"), "a | b");
+  }
 
-  match stream_res {
-    Ok(full) => {
-      if !full.is_empty() {
-        answer = full;
-      }
-    }
-    Err(err) => {
-      if !saw_delta {
-        let fallback = ollama.chat(&llm_model, build_chat_messages(&question, &sources))?;
-        return Ok(ChatResult { answer: fallback, sources });
-      }
-      eprintln!("chat stream error: {}", err);
-    }
+  #[test]
+  fn xlsx_sheet_text_resolves_shared_strings() {
+    let shared = xlsx_shared_strings(
+      r#"<sst><si><t>Item</t></si><si><r><t>Co</t></r><r><t>st</t></r><rPh><t>x</t></rPh></si><si><t>Rent</t></si></sst>"#.as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(shared, vec!["Item", "Cost", "Rent"]);
+    let sheet = r#"<worksheet><sheetData>
+      <row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>
+      <row r="2"><c r="A2" t="s"><v>2</v></c><c r="B2"><v>1200.5</v></c><c r="D2" t="inlineStr"><is><t>note</t></is></c></row>
+      <row r="3"/>
+    </sheetData></worksheet>"#;
+    assert_eq!(xlsx_sheet_text(sheet.as_bytes(), &shared).unwrap(), "Item: Rent | Cost: 1200.5 | col4: note");
+    assert_eq!(xlsx_column("AB12"), Some(27));
   }
 
-  Ok(ChatResult { answer, sources })
-}
+  #[test]
+  fn chunk_code_splits_between_blocks() {
+    let src = "fn a() {\n    one();\n}\n\nfn b() {\n    two();\n}\n\n\nfn c() {\n    three();\n}\n";
+    assert_eq!(
+      chunk_code(src, 48, 0),
+      vec!["fn a() {\n    one();\n}\n\nfn b() {\n    two();\n}", "fn c() {\n    three();\n}"]
+    );
+    let long = "fn long() {\n    first_call();\n    second_call();\n}";
+    assert_eq!(chunk_code(long, 30, 0), vec!["fn long() {\n    first_call();", "    second_call();\n}"]);
+  }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
-  use std::path::{Path, PathBuf};
+  #[test]
+  fn chunk_rows_keeps_rows_whole() {
+    let rows = "a: 1 | b: 2\na: 3 | b: 4\na: 5 | b: 6";
+    assert_eq!(chunk_rows(rows, 24, 0), vec!["a: 1 | b: 2\na: 3 | b: 4", "a: 5 | b: 6"]);
+    assert_eq!(chunk_rows("abcdefghij", 4, 0).len(), chunk_text("abcdefghij", 4, 0).len());
+  }
 
   #[test]
-  fn kind_from_path_detects_supported_extensions() {
-    assert_eq!(kind_from_path(Path::new("doc.pdf")), Some(DocumentKind::Pdf));
-    assert_eq!(kind_from_path(Path::new("doc.txt")), Some(DocumentKind::Txt));
-    assert_eq!(kind_from_path(Path::new("doc.md")), Some(DocumentKind::Md));
-    assert_eq!(kind_from_path(Path::new("doc.markdown")), Some(DocumentKind::Md));
-    assert_eq!(kind_from_path(Path::new("doc.docx")), Some(DocumentKind::Docx));
-    assert_eq!(kind_from_path(Path::new("doc.bin")), None);
+  fn pptx_slides_and_notes() {
+    let slide = r#"<p:sld xmlns:a="a" xmlns:p="p"><p:cSld><p:spTree>
+      <p:sp><p:txBody><a:p><a:r><a:t>Plan na Q3</a:t></a:r></a:p></p:txBody></p:sp>
+      <p:sp><p:txBody><a:p><a:r><a:t>Koszty &amp; </a:t></a:r><a:r><a:t>zyski</a:t></a:r><a:br/><a:r><a:t>druga linia</a:t></a:r></a:p>
+      <a:p><a:fld type="slidenum"><a:t>7</a:t></a:fld></a:p></p:txBody></p:sp>
+    </p:spTree></p:cSld></p:sld>"#;
+    assert_eq!(pptx_xml_text(slide).unwrap(), "Plan na Q3\nKoszty & zyski\ndruga linia\n\n");
+    assert_eq!(pptx_slide_number("ppt/slides/slide12.xml"), Some(12));
+    assert_eq!(pptx_slide_number("ppt/slides/_rels/slide12.xml.rels"), None);
+    let rels = r#"<Relationships><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/slideLayout" Target="../slideLayouts/slideLayout2.xml"/>
+      <Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/notesSlide" Target="../notesSlides/notesSlide3.xml"/></Relationships>"#;
+    assert_eq!(pptx_notes_target(rels).as_deref(), Some("ppt/notesSlides/notesSlide3.xml"));
   }
 
   #[test]
-  fn supported_document_checks_extension() {
-    assert!(is_supported_document(Path::new("report.pdf")));
-    assert!(!is_supported_document(Path::new("report.exe")));
+  fn extract_rtf_text_strips_control_words() {
+    let rtf = br"{\rtf1\ansi\deff0{\fonttbl{\f0 Times New Roman;}}{\*\generator Writer;}
+{\pict\pngblip 89504e47}\pard Szanowny Panie,\par Umowa zosta\u322?a podpisana w caf\'e9 \{A\}.\par
+{\b Koniec}\tab ok}";
+    assert_eq!(
+      extract_rtf_text(rtf).unwrap(),
+      "Szanowny Panie,\nUmowa została podpisana w café {A}.\nKoniec\tok"
+    );
+    assert!(extract_rtf_text(b"PK\x03\x04 not rtf").is_err());
   }
 
   #[test]
-  fn chunk_text_splits_with_overlap() {
-    let chunks = chunk_text("abcdefgh", 3, 1);
-    assert_eq!(chunks, vec!["abc", "cde", "efg", "gh"]);
+  fn extract_rtf_text_decodes_the_ansi_code_page() {
+    // Windows-1250 as Polish editors write it: escaped ę ź ą ł ś plus raw 8-bit bytes for "Żółw".
+    let rtf = b"{\\rtf1\\ansi\\ansicpg1250 Prosz\\'ea o odpowied\\'9f \\'96 wi\\'b9za\\'b3 si\\'ea \\'9cwietnie.\\par \xaf\xf3\xb3w}";
+    assert_eq!(extract_rtf_text(rtf).unwrap(), "Proszę o odpowiedź – wiązał się świetnie.\nŻółw");
+    assert_eq!(extract_rtf_text(br"{\rtf1\ansi en\'96dash}").unwrap(), "en–dash");
   }
 
   #[test]
-  fn chunk_text_prefers_boundaries() {
-    let chunks = chunk_text("alpha beta gamma", 10, 0);
-    assert_eq!(chunks, vec!["alpha", "beta", "gamma"]);
+  fn extract_html_text_drops_scripts_and_tags() {
+    let html = "<html><head><style>p { color: red }</style><script>var x = '<p>';</script></head>\
+      <body><!-- nav --><h1>Umowa&nbsp;najmu</h1><p>Czynsz: 2&#160;000 z&#x142; &amp; op\u{142}aty</p>\
+      <p>Koniec <b>umowy</b></p><p>broken <a href=\"x\"";
+    assert_eq!(
+      extract_html_text(html),
+      "Umowa najmu\n\nCzynsz: 2 000 zł & opłaty\n\nKoniec umowy\n\nbroken <a href=\"x\""
+    );
   }
 
   #[test]
-  fn chunk_text_handles_utf8_without_replacement() {
-    let chunks = chunk_text("zażółć gęślą jaźń", 6, 0);
-    assert!(chunks.iter().all(|c| !c.contains('\u{FFFD}')));
+  fn epub_spine_follows_itemrefs_and_resolves_paths() {
+    let container = r#"<container><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#;
+    assert_eq!(epub_rootfile(container).as_deref(), Some("OEBPS/content.opf"));
+    let opf = r#"<package><manifest>
+      <item id="c2" href="text/ch%202.xhtml" media-type="application/xhtml+xml"/>
+      <item id="c1" href="text/ch1.xhtml#start" media-type="application/xhtml+xml"/>
+      <item id="css" href="style.css" media-type="text/css"/>
+    </manifest><spine><itemref idref="c1"/><itemref idref="c2"/><itemref idref="missing"/></spine></package>"#;
+    assert_eq!(epub_spine(opf, "OEBPS/content.opf"), vec!["OEBPS/text/ch1.xhtml", "OEBPS/text/ch 2.xhtml"]);
+    assert_eq!(html_heading("<title>Book</title><h2 class=\"t\">Rozdział <i>1</i></h2>").as_deref(), Some("Rozdział 1"));
   }
 
   #[test]
@@ -1657,8 +7115,8 @@ mod tests {
   #[test]
   fn sanitize_and_build_fts_query() {
     assert_eq!(sanitize_fts_token("hi!"), "hi".to_string());
-    assert_eq!(build_fts_query("a b cd"), Some("cd*".to_string()));
-    assert_eq!(build_fts_query("a"), None);
+    assert_eq!(build_fts_query("a b cd", FtsQueryOptions::default()), Some("cd*".to_string()));
+    assert_eq!(build_fts_query("a", FtsQueryOptions::default()), None);
   }
 
   #[test]
@@ -1694,8 +7152,942 @@ mod tests {
     assert!(base.ends_with(&expected));
   }
 
+  #[test]
+  fn scale_candidate_k_grows_with_narrow_scope() {
+    assert_eq!(scale_candidate_k(8, 100, 100), 8);
+    assert_eq!(scale_candidate_k(8, 100, 25), 32);
+    assert_eq!(scale_candidate_k(8, 1_000_000, 1), MAX_KNN_K);
+    assert_eq!(scale_candidate_k(8, 10, 1), 10);
+  }
+
+  #[test]
+  fn pick_ocr_lang_matches_configured_packs() {
+    let polish = "To jest dokument napisany po polsku, który zawiera wystarczająco dużo tekstu, aby rozpoznać język.";
+    assert_eq!(pick_ocr_lang(polish, &["pol", "eng"]), Some("pol".to_string()));
+    assert_eq!(pick_ocr_lang(polish, &["eng", "deu"]), None);
+    assert_eq!(pick_ocr_lang("", &["pol", "eng"]), None);
+  }
+
+  #[test]
+  fn context_order_indices_sorts_by_document_position() {
+    let source = |file_path: &str, page: i32, chunk_index: i32| Source {
+      id: chunk_index as i64,
+      file_path: file_path.into(),
+      page,
+      chunk_index,
+      snippet: String::new(),
+      distance: 0.0,
+      origin: None,
+      highlight: None,
+      title: None,
+      author: None,
+    };
+    let sources = vec![source("b.pdf", 0, 0), source("a.pdf", 2, 1), source("a.pdf", 2, 0), source("a.pdf", 0, 3)];
+    assert_eq!(context_order_indices(&sources, ContextOrder::Relevance), vec![0, 1, 2, 3]);
+    assert_eq!(context_order_indices(&sources, ContextOrder::Document), vec![3, 2, 1, 0]);
+  }
+
+  #[test]
+  fn build_fts_query_drops_stopwords_and_stems() {
+    let opts = FtsQueryOptions { lang: Some("eng"), stopwords: true, stemming: false };
+    assert_eq!(build_fts_query("what is the invoice total", opts), Some("invoice* total*".to_string()));
+    // Short queries keep their stop words.
+    assert_eq!(build_fts_query("the invoice", opts), Some("the* invoice*".to_string()));
+    // A query made only of stop words falls back to the raw tokens.
+    assert_eq!(build_fts_query("what is the", opts), Some("what* is* the*".to_string()));
+
+    let opts = FtsQueryOptions { lang: Some("pol"), stopwords: true, stemming: true };
+    assert_eq!(build_fts_query("umowy w sprawie faktury", opts), Some("umow* spraw* faktur*".to_string()));
+  }
+
+  #[test]
+  fn chunk_overlap_ratio_overrides_absolute_overlap() {
+    let settings = |ratio: serde_json::Value| -> IndexSettings {
+      serde_json::from_value(serde_json::json!({
+        "chunkSize": 1000,
+        "chunkOverlap": 250,
+        "chunkOverlapRatio": ratio,
+        "ocrEnabled": false,
+        "ocrLang": "eng",
+        "ocrMinChars": 120,
+        "ocrDpi": 300
+      }))
+      .unwrap()
+    };
+    assert_eq!(settings(serde_json::Value::Null).effective_chunk_overlap(), 250);
+    assert_eq!(settings(serde_json::json!(0.15)).effective_chunk_overlap(), 150);
+    assert_eq!(settings(serde_json::json!(2.0)).effective_chunk_overlap(), 900);
+    assert_eq!(settings(serde_json::json!(-0.5)).effective_chunk_overlap(), 0);
+  }
+
+  #[test]
+  fn index_error_classifies_anyhow_chain() {
+    let ocr = anyhow::Error::new(OcrFailure("tesseract failed: boom".into())).context(ExtractFailure("a.pdf".into()));
+    assert_eq!(
+      IndexError::from_anyhow(&ocr, Some("a.pdf")),
+      IndexError::OcrFailed { message: "extract failed: a.pdf: tesseract failed: boom".into(), file: Some("a.pdf".into()) }
+    );
+    let extract = anyhow::anyhow!("bad zip").context(ExtractFailure("b.docx".into()));
+    assert!(matches!(IndexError::from_anyhow(&extract, None), IndexError::ExtractFailed { file: Some(f), .. } if f == "b.docx"));
+    let vec = anyhow::Error::new(VecExtensionNotFound("sqlite-vec extension not found".into())).context("open db");
+    assert!(matches!(IndexError::from(vec), IndexError::VecExtensionMissing { .. }));
+
+    let http = anyhow::Error::new(OllamaHttpError { status: reqwest::StatusCode::NOT_FOUND, body: "model not found".into() });
+    let json = serde_json::to_value(IndexError::from(http)).unwrap();
+    assert_eq!(json["code"], "model_missing");
+    assert_eq!(serde_json::to_value(IndexError::from("x".to_string())).unwrap(), serde_json::json!({ "code": "other", "message": "x" }));
+  }
+
+  #[test]
+  fn clean_paged_text_keeps_page_alignment() {
+    let pages = clean_paged_text(Some(vec!["Intro  text\n".into(), "".into(), "Third".into()])).unwrap();
+    assert_eq!(pages.len(), 3);
+    assert!(pages[1].is_empty());
+    assert_eq!(pages[2], "Third");
+    assert_eq!(clean_paged_text(Some(vec!["".into(), " ".into()])), None);
+    assert_eq!(clean_paged_text(None), None);
+  }
+
+  #[test]
+  fn traineddata_langs_lists_models_only() {
+    let dir = std::env::temp_dir().join(format!("lfc-tessdata-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in ["pol.traineddata", "eng.traineddata", "eng.user-words", "README"] {
+      fs::write(dir.join(name), b"").unwrap();
+    }
+    assert_eq!(traineddata_langs(&dir), vec!["eng", "pol"]);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(traineddata_langs(&dir).is_empty());
+  }
+
+  #[test]
+  fn matches_target_respects_exclude_paths() {
+    let root = std::env::temp_dir().join("lfc-docs");
+    let target = IndexTarget {
+      path: root.to_string_lossy().to_string(),
+      kind: IndexTargetKind::Folder,
+      include_subfolders: true,
+      exclude_paths: vec![root.join("Archive").to_string_lossy().to_string()],
+      exclude_globs: vec![],
+      default_ignores: true,
+    };
+    assert!(matches_target(&root.join("notes").join("a.md"), &target));
+    assert!(!matches_target(&root.join("Archive").join("old.pdf"), &target));
+    assert!(matches_target(&root.join("Archive2").join("new.pdf"), &target));
+  }
+
+  #[test]
+  fn exclude_globs_match_relative_to_target_root() {
+    let root = std::env::temp_dir().join("lfc-globs");
+    let mut target = IndexTarget {
+      path: root.to_string_lossy().to_string(),
+      kind: IndexTargetKind::Folder,
+      include_subfolders: true,
+      exclude_paths: vec![],
+      exclude_globs: vec!["generated".into(), "build/*.md".into(), "*.tmp".into()],
+      default_ignores: true,
+    };
+    let excl = target.exclusions();
+    assert!(excl.is_excluded(&root.join("a").join("generated").join("x.md")));
+    assert!(excl.is_excluded(&root.join("build").join("out.md")));
+    assert!(!excl.is_excluded(&root.join("a").join("build").join("out.md")));
+    assert!(excl.is_excluded(&root.join("notes.tmp")));
+    assert!(excl.is_excluded(&root.join(".git").join("HEAD")));
+    assert!(!excl.is_excluded(&root.join("notes").join("a.md")));
+    target.default_ignores = false;
+    assert!(!target.is_excluded(&root.join(".git").join("HEAD")));
+  }
+
+  #[test]
+  fn ignore_file_patterns_extend_exclusions() {
+    assert_eq!(ignore_file_patterns("# drafts\n\n  drafts/ \n*.bak\n!keep.bak\n"), vec!["drafts/", "*.bak"]);
+    let root = std::env::temp_dir().join(format!("lfc-ignore-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join(IGNORE_FILE_NAME), "drafts\nprivate/*.pdf\n").unwrap();
+    let target = IndexTarget {
+      path: root.to_string_lossy().to_string(),
+      kind: IndexTargetKind::Folder,
+      include_subfolders: true,
+      exclude_paths: vec![],
+      exclude_globs: vec![],
+      default_ignores: true,
+    };
+    let excl = target.exclusions();
+    fs::remove_dir_all(&root).unwrap();
+    assert!(excl.is_excluded(&root.join("a").join("drafts").join("x.md")));
+    assert!(excl.is_excluded(&root.join("private").join("salary.pdf")));
+    assert!(!excl.is_excluded(&root.join("private").join("notes.md")));
+  }
+
+  #[test]
+  fn chat_retry_only_for_server_errors() {
+    let err = |status| anyhow::anyhow!(OllamaHttpError { status, body: String::new() });
+    assert!(is_chat_retryable(&err(StatusCode::SERVICE_UNAVAILABLE)));
+    assert!(is_chat_retryable(&err(StatusCode::INTERNAL_SERVER_ERROR)));
+    assert!(!is_chat_retryable(&err(StatusCode::NOT_FOUND)));
+    assert!(!is_chat_retryable(&err(StatusCode::BAD_REQUEST)));
+    assert!(!is_chat_retryable(&anyhow::anyhow!("bad json")));
+    assert!(is_chat_retryable(&err(StatusCode::TOO_MANY_REQUESTS)));
+    let missing = anyhow::anyhow!(OllamaHttpError {
+      status: StatusCode::INTERNAL_SERVER_ERROR,
+      body: "model \"qwen3\" not found, try pulling it first".into()
+    });
+    assert!(!is_chat_retryable(&missing));
+    assert!(format!("{:#}", explain_chat_error(missing, "qwen3")).starts_with("Chat model 'qwen3' is not installed"));
+  }
+
+  #[test]
+  fn retrieval_confidence_rewards_a_clear_winner() {
+    let source = |distance: f64| Source {
+      id: 0,
+      file_path: "a.txt".into(),
+      page: 0,
+      chunk_index: 0,
+      snippet: String::new(),
+      distance,
+      origin: None,
+      highlight: None,
+      title: None,
+      author: None,
+    };
+    assert_eq!(retrieval_confidence(&[]), 0.0);
+    let clear = retrieval_confidence(&[source(0.1), source(0.5), source(0.6)]);
+    let flat = retrieval_confidence(&[source(0.1), source(0.11), source(0.12)]);
+    let weak = retrieval_confidence(&[source(0.8), source(0.9)]);
+    assert!((clear - 0.9).abs() < 1e-6);
+    assert!(flat < clear);
+    assert!(weak < 0.25);
+  }
+
+  #[test]
+  fn filter_language_skips_short_questions() {
+    assert_eq!(filter_language("Wie hoch ist die Miete im Vertrag?", LanguageFilter::Soft).as_deref(), Some("deu"));
+    assert_eq!(filter_language("Wie hoch ist die Miete im Vertrag?", LanguageFilter::Off), None);
+    assert_eq!(filter_language("Miete 2024", LanguageFilter::Strict), None);
+  }
+
+  #[test]
+  fn rerank_sources_orders_by_score() {
+    let source = |id: i64| Source {
+      id,
+      file_path: format!("{id}.pdf"),
+      page: 0,
+      chunk_index: 0,
+      snippet: String::new(),
+      distance: 0.1 * id as f64,
+      origin: None,
+      highlight: None,
+      title: None,
+      author: None,
+    };
+    let scores = [3.0, 9.0, 9.0, 1.0];
+    let ids = |v: Vec<Source>| v.into_iter().map(|s| s.id).collect::<Vec<_>>();
+    let ranked = rerank_sources((0..4).map(source).collect(), 3, |s| Ok(scores[s.id as usize]));
+    assert_eq!(ids(ranked), vec![1, 2, 0]);
+    let failed = rerank_sources((0..4).map(source).collect(), 2, |_| anyhow::bail!("offline"));
+    assert_eq!(ids(failed), vec![0, 1]);
+    assert_eq!(parse_relevance_score("Score: 7,5/10"), Some(7.5));
+    assert_eq!(parse_relevance_score("42"), Some(10.0));
+    assert_eq!(parse_relevance_score("none"), None);
+  }
+
+  #[test]
+  fn truncate_snippet_cuts_at_words() {
+    assert_eq!(truncate_snippet("krótki tekst", Some(50)), "krótki tekst");
+    assert_eq!(truncate_snippet("zażółć gęślą jaźń", Some(14)), "zażółć gęślą…");
+    assert_eq!(truncate_snippet("abcdefghij", Some(4)), "abcd…");
+    assert_eq!(truncate_snippet("abcdefghij", None), "abcdefghij");
+  }
+
+  #[test]
+  fn trim_history_keeps_latest_turns_in_order() {
+    let msg = |role: &str, content: &str| ChatMessage { role: role.into(), content: content.into() };
+    let history = vec![
+      msg("user", "first"),
+      msg("assistant", "one"),
+      msg("system", "ignored"),
+      msg("user", "second"),
+      msg("assistant", " "),
+      msg("assistant", "two"),
+    ];
+    let kept: Vec<String> = trim_history(&history, 3).into_iter().map(|m| m.content).collect();
+    assert_eq!(kept, vec!["one", "second", "two"]);
+
+    let long = vec![msg("user", &"x".repeat(HISTORY_MAX_CHARS)), msg("user", "recent")];
+    assert_eq!(trim_history(&long, 8).len(), 1);
+    assert!(trim_history(&history, 0).is_empty());
+  }
+
+  #[test]
+  fn merge_adjacent_sources_dedupes_overlap() {
+    let text = "Alpha beta gamma. Delta epsilon zeta. Eta theta iota. Kappa lambda mu.";
+    let chunks = chunk_text(text, 30, 12);
+    assert!(chunks.len() >= 3);
+    assert_eq!(chunks.iter().skip(1).fold(chunks[0].clone(), |acc, c| join_overlapping(&acc, c)), text);
+
+    let source = |page: i32, chunk_index: i32, snippet: &str, distance: f64| Source {
+      id: chunk_index as i64,
+      file_path: "a.txt".into(),
+      page,
+      chunk_index,
+      snippet: snippet.into(),
+      distance,
+      origin: None,
+      highlight: None,
+      title: None,
+      author: None,
+    };
+    let merged = merge_adjacent_sources(vec![
+      source(0, 1, &chunks[1], 0.2),
+      source(3, 0, "other page", 0.3),
+      source(0, 0, &chunks[0], 0.4),
+    ], &[], Some(600));
+    assert_eq!(merged.len(), 2);
+    assert_eq!(merged[0].chunk_index, 0);
+    assert_eq!(merged[0].snippet, join_overlapping(&chunks[0], &chunks[1]));
+    assert!((merged[0].distance - 0.2).abs() < 1e-9);
+    assert_eq!(merged[1].page, 3);
+  }
+
+  #[test]
+  fn chunk_exceeds_context_uses_token_estimate() {
+    assert!(!chunk_exceeds_context(1400, 512));
+    assert!(chunk_exceeds_context(1400, 256));
+    assert!(chunk_exceeds_context(1537, 512));
+  }
+
+  #[test]
+  fn text_hash_tracks_content_and_page_breaks() {
+    let a = vec!["one".to_string(), "two".to_string()];
+    assert_eq!(text_hash(&a), text_hash(&a.clone()));
+    assert_ne!(text_hash(&a), text_hash(&["onetwo".to_string()]));
+    assert_ne!(text_hash(&a), text_hash(&["one".to_string(), "twó".to_string()]));
+  }
+
+  #[test]
+  fn archive_paths_round_trip() {
+    let key = document_key(Path::new("/docs/Bundle.ZIP"), Some("inner/dir/file.pdf"));
+    assert_eq!(key, "/docs/Bundle.ZIP!inner/dir/file.pdf");
+    assert_eq!(split_archive_path(&key), ("/docs/Bundle.ZIP", Some("inner/dir/file.pdf")));
+    assert_eq!(split_archive_path("/docs/wow!.pdf"), ("/docs/wow!.pdf", None));
+    assert!(is_supported_document(Path::new("a/b.zip"), &KindFilter::default()));
+  }
+
+  #[test]
+  fn validate_model_options_accepts_objects_only() {
+    assert!(validate_model_options(None).unwrap().is_none());
+    assert!(validate_model_options(Some(serde_json::json!({}))).unwrap().is_none());
+    let opts = validate_model_options(Some(serde_json::json!({ "temperature": 0.1 }))).unwrap();
+    assert_eq!(opts, Some(serde_json::json!({ "temperature": 0.1 })));
+    assert!(validate_model_options(Some(serde_json::json!([1, 2]))).is_err());
+    assert!(validate_model_options(Some(serde_json::json!("strict"))).is_err());
+  }
+
+  #[test]
+  fn append_page_extras_keeps_page_alignment() {
+    let mut pages = vec!["first".to_string(), "second".to_string()];
+    append_page_extras(
+      &mut pages,
+      vec![vec![], vec!["[Form field] name: Jan".into()], vec!["[Annotation] late".into()]],
+    );
+    assert_eq!(pages[0], "first");
+    assert_eq!(pages[1], "second\n\n[Form field] name: Jan\n\n[Annotation] late");
+
+    let mut empty = vec![];
+    append_page_extras(&mut empty, vec![vec!["[Annotation] a".into(), "[Annotation] b".into()]]);
+    assert_eq!(empty, vec!["[Annotation] a\n[Annotation] b".to_string()]);
+  }
+
+  #[test]
+  fn answer_language_overrides_question_language() {
+    assert!(answer_language_instruction(None).contains("same language"));
+    assert!(answer_language_instruction(Some("  ")).contains("same language"));
+    assert!(answer_language_instruction(Some("English")).contains("Always respond in English"));
+
+    let pl_question = "Jakie są warunki umowy najmu mieszkania w tym dokumencie?";
+    assert!(insufficient_evidence_answer(pl_question, Some("English")).starts_with("I couldn't"));
+    assert!(insufficient_evidence_answer("What are the terms?", Some("Polski")).starts_with("Nie znalazłem"));
+  }
+
+  #[test]
+  fn estimate_chunk_count_accounts_for_overlap() {
+    assert_eq!(estimate_chunk_count(0, 1000, 200), 0);
+    assert_eq!(estimate_chunk_count(500, 1000, 200), 1);
+    assert_eq!(estimate_chunk_count(1000, 1000, 200), 1);
+    assert_eq!(estimate_chunk_count(1801, 1000, 200), 3);
+    assert_eq!(estimate_chunk_count(5000, 1000, 5000), 4001);
+  }
+
+  #[test]
+  fn drop_short_chunks_keeps_lone_chunk() {
+    assert_eq!(drop_short_chunks(vec!["12".into()], 30), vec!["12".to_string()]);
+    let long = "a".repeat(40);
+    assert_eq!(drop_short_chunks(vec![long.clone(), "p. 3".into(), long.clone()], 30), vec![long.clone(), long]);
+  }
+
+  #[test]
+  fn contextualize_chunk_prepends_summary() {
+    assert_eq!(contextualize_chunk(Some("Lease agreement for flat 4"), "Rent is due monthly."), "Lease agreement for flat 4\n\nRent is due monthly.");
+    assert_eq!(contextualize_chunk(None, "Rent is due monthly."), "Rent is due monthly.");
+  }
+
+  #[test]
+  fn same_embed_model_ignores_latest_tag() {
+    assert!(same_embed_model("nomic-embed-text", "nomic-embed-text:latest"));
+    assert!(same_embed_model(" bge-m3 ", "bge-m3"));
+    assert!(!same_embed_model("nomic-embed-text", "mxbai-embed-large"));
+    assert!(!same_embed_model("bge-m3:567m", "bge-m3"));
+  }
+
+  #[test]
+  fn embed_prefix_mismatch_flags_one_sided_prefixes() {
+    assert!(!embed_prefix_mismatch(None, None));
+    assert!(!embed_prefix_mismatch(Some("search_document: "), Some("search_query: ")));
+    assert!(embed_prefix_mismatch(None, Some("search_query: ")));
+    assert!(embed_prefix_mismatch(Some("search_document: "), Some("  ")));
+    assert_eq!(with_embed_prefix(Some("search_query: "), "rent"), "search_query: rent");
+  }
+
+  #[test]
+  fn clean_reformulated_query_strips_labels_and_quotes() {
+    assert_eq!(clean_reformulated_query("\n  \"termin wypowiedzenia umowy\"\nextra"), Some("termin wypowiedzenia umowy".into()));
+    assert_eq!(clean_reformulated_query("Query: lease termination notice"), Some("lease termination notice".into()));
+    assert_eq!(clean_reformulated_query("Note: keep this"), Some("Note: keep this".into()));
+    assert_eq!(clean_reformulated_query("  \"\" "), None);
+    assert_eq!(best_distance(&[]), f64::INFINITY);
+  }
+
+  #[test]
+  fn blank_pdf_pages_keep_later_page_numbers() {
+    // Three pages, the middle one blank.
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let content = |text: &str| {
+      if text.is_empty() { String::new() } else { format!("BT /F1 12 Tf 72 720 Td ({text}) Tj ET") }
+    };
+    let streams = [content("First page text"), content(""), content("Third page text")];
+    let mut objects = vec![
+      "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+      "<< /Type /Pages /Kids [4 0 R 5 0 R 6 0 R] /Count 3 >>".to_string(),
+      "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+    ];
+    for i in 0..3 {
+      objects.push(format!(
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+        7 + i
+      ));
+    }
+    for s in &streams {
+      objects.push(format!("<< /Length {} >>\nstream\n{s}\nendstream", s.len()));
+    }
+    let mut offsets = Vec::new();
+    for (i, obj) in objects.iter().enumerate() {
+      offsets.push(pdf.len());
+      pdf.extend_from_slice(format!("{} 0 obj\n{obj}\nendobj\n", i + 1).as_bytes());
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for off in offsets {
+      pdf.extend_from_slice(format!("{off:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend_from_slice(
+      format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n", objects.len() + 1).as_bytes(),
+    );
+    let path = std::env::temp_dir().join(format!("lfc-blank-page-{}.pdf", std::process::id()));
+    fs::write(&path, &pdf).unwrap();
+    let pages = extract_pdf_text_layer(&path);
+    let _ = fs::remove_file(&path);
+    let pages = pages.unwrap();
+    assert_eq!(pages.len(), 3);
+    assert!(pages[1].is_empty());
+
+    let settings: IndexSettings = serde_json::from_value(serde_json::json!({
+      "chunkSize": 1000,
+      "chunkOverlap": 0,
+      "ocrEnabled": false,
+      "ocrLang": "eng",
+      "ocrMinChars": 0,
+      "ocrDpi": 300,
+      "minChunkChars": 0
+    }))
+    .unwrap();
+    let (meta, texts) = chunk_pages(&pages, &[], DocumentKind::Pdf, None, &settings);
+    let mut page_numbers: Vec<i32> = meta.iter().map(|m| m.0).collect();
+    page_numbers.dedup();
+    assert_eq!(page_numbers, vec![0, 2]);
+    assert!(texts[0].starts_with("First") && texts.iter().any(|t| t.starts_with("Third")));
+  }
+
+  #[test]
+  fn merge_ocr_pages_prefers_richer_source_per_page() {
+    let text_layer = vec!["Native page one with text".to_string(), String::new()];
+    let ocr = vec!["Native pagc one".to_string(), "Scanned page two".to_string(), "Extra".to_string()];
+    let (pages, report) = merge_ocr_pages(&text_layer, ocr);
+    assert_eq!(pages, vec!["Native page one with text", "Scanned page two", "Extra"]);
+    let origins: Vec<PageOrigin> = report.iter().map(|p| p.origin).collect();
+    assert_eq!(origins, vec![PageOrigin::Text, PageOrigin::Ocr, PageOrigin::Ocr]);
+    assert_eq!(report[0].chars, 25);
+    assert_eq!(report[1].chars, 0);
+  }
+
+  #[test]
+  fn base_candidate_k_pads_past_top_k() {
+    let settings = |extra: serde_json::Value| -> RetrievalSettings {
+      let mut v = serde_json::json!({
+        "topK": 8,
+        "maxDistance": null,
+        "useMmr": false,
+        "mmrLambda": 0.5,
+        "mmrCandidates": 40
+      });
+      v.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+      serde_json::from_value(v).unwrap()
+    };
+    assert_eq!(base_candidate_k(&settings(serde_json::json!({}))), 24);
+    assert_eq!(base_candidate_k(&settings(serde_json::json!({ "topK": 2 }))), 20);
+    assert_eq!(base_candidate_k(&settings(serde_json::json!({ "useMmr": true }))), 32);
+    assert_eq!(base_candidate_k(&settings(serde_json::json!({ "knnMultiplier": 1, "knnMinK": 0 }))), 8);
+    assert_eq!(base_candidate_k(&settings(serde_json::json!({ "candidatePool": 100, "useMmr": true }))), 100);
+    assert_eq!(base_candidate_k(&settings(serde_json::json!({ "candidatePool": 3 }))), 8);
+  }
+
+  #[test]
+  fn chat_prompts_honor_overrides() {
+    let settings = |extra: serde_json::Value| -> RetrievalSettings {
+      let mut v = serde_json::json!({
+        "topK": 8,
+        "maxDistance": null,
+        "useMmr": false,
+        "mmrLambda": 0.5,
+        "mmrCandidates": 40
+      });
+      v.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+      serde_json::from_value(v).unwrap()
+    };
+    let default = settings(serde_json::json!({}));
+    assert!(chat_system_prompt(&default).starts_with(DEFAULT_SYSTEM_PROMPT));
+    assert!(chat_system_prompt(&default).contains("[1], [2]"));
+    assert!(chat_user_prompt("Q?", "ctx", &default).ends_with("Answer with citations [1], [2]:"));
+
+    let custom = settings(serde_json::json!({ "systemPrompt": "Be terse.", "citeSources": false }));
+    assert!(chat_system_prompt(&custom).starts_with("Be terse. "));
+    assert!(!chat_system_prompt(&custom).contains("[1]"));
+
+    let templated = settings(serde_json::json!({ "userPromptTemplate": "{context}\n---\n{question}" }));
+    assert_eq!(chat_user_prompt("what {context}?", "ctx", &templated), "ctx\n---\nwhat {context}?");
+    let no_context = settings(serde_json::json!({ "userPromptTemplate": "Q: {question}" }));
+    assert_eq!(chat_user_prompt("why", "ctx", &no_context), "Q: why\n\nSources:\nctx");
+  }
+
+  #[test]
+  fn db_files_include_wal_sidecars() {
+    let files = db_files(Path::new("/data/library.sqlite3"));
+    assert_eq!(
+      files,
+      vec![
+        PathBuf::from("/data/library.sqlite3"),
+        PathBuf::from("/data/library.sqlite3-wal"),
+        PathBuf::from("/data/library.sqlite3-shm"),
+      ]
+    );
+  }
+
+  #[test]
+  fn cap_per_page_limits_chunks_per_page_in_rank_order() {
+    let ranked = vec![(3, "a"), (3, "b"), (1, "c"), (3, "d"), (1, "e"), (7, "f")];
+    let kept: Vec<&str> = cap_per_page(ranked, |r| r.0, 2).into_iter().map(|r| r.1).collect();
+    assert_eq!(kept, vec!["a", "b", "c", "e", "f"]);
+  }
+
+  #[test]
+  fn delete_orphans_removes_rows_without_parents() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE files(path TEXT PRIMARY KEY);
+         CREATE TABLE chunks(id INTEGER PRIMARY KEY, file_path TEXT);
+         CREATE TABLE vec_chunks(embedding BLOB);
+         INSERT INTO files(path) VALUES('a.pdf');
+         INSERT INTO chunks(id, file_path) VALUES(1, 'a.pdf'), (2, 'gone.pdf');
+         INSERT INTO vec_chunks(rowid, embedding) VALUES(1, x''), (2, x''), (3, x'');",
+      )
+      .unwrap();
+    assert_eq!(delete_orphans(&conn).unwrap(), (1, 2));
+    let left: i64 = conn.query_row("SELECT COUNT(*) FROM vec_chunks", [], |r| r.get(0)).unwrap();
+    assert_eq!(left, 1);
+  }
+
+  #[test]
+  fn document_exists_checks_files_and_archives() {
+    assert!(document_exists("Cargo.toml"));
+    assert!(!document_exists("no-such-file.pdf"));
+    assert!(!document_exists("no-such-archive.zip!inner/a.txt"));
+  }
+
+  #[test]
+  fn chunking_changed_compares_with_indexed_meta() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch("CREATE TABLE meta(key TEXT PRIMARY KEY, value TEXT);").unwrap();
+    let settings: IndexSettings = serde_json::from_value(serde_json::json!({
+      "chunkSize": 1000,
+      "chunkOverlap": 200,
+      "ocrEnabled": false,
+      "ocrLang": "eng",
+      "ocrMinChars": 120,
+      "ocrDpi": 300
+    }))
+    .unwrap();
+    conn.execute_batch("INSERT INTO meta(key, value) VALUES('chunk_size', '1000'), ('chunk_overlap', '200');").unwrap();
+    assert!(!chunking_changed(&conn, &settings));
+    conn.execute("UPDATE meta SET value='800' WHERE key='chunk_size'", []).unwrap();
+    assert!(chunking_changed(&conn, &settings));
+  }
+
+  #[test]
+  fn mark_files_stale_keeps_chunks() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE files(path TEXT PRIMARY KEY, hash TEXT NOT NULL, text_hash TEXT);
+         CREATE TABLE chunks(id INTEGER PRIMARY KEY, file_path TEXT);
+         INSERT INTO files VALUES('a.pdf', 'h1', 't1');
+         INSERT INTO chunks(id, file_path) VALUES(1, 'a.pdf');",
+      )
+      .unwrap();
+    assert_eq!(mark_files_stale(&conn).unwrap(), 1);
+    let (hash, text_hash): (String, Option<String>) =
+      conn.query_row("SELECT hash, text_hash FROM files", [], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+    assert_eq!((hash.as_str(), text_hash), ("", None));
+    let chunks: i64 = conn.query_row("SELECT COUNT(*) FROM chunks", [], |r| r.get(0)).unwrap();
+    assert_eq!(chunks, 1);
+  }
+
+  #[test]
+  fn other_model_files_are_marked_stale_and_excluded() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE files(path TEXT PRIMARY KEY, hash TEXT NOT NULL, text_hash TEXT, embed_model TEXT);
+         INSERT INTO files VALUES('old.pdf', 'h0', 't0', NULL);
+         INSERT INTO files VALUES('a.pdf', 'h1', 't1', 'nomic-embed-text');
+         INSERT INTO files VALUES('b.pdf', 'h2', 't2', 'bge-m3');
+         INSERT INTO files VALUES('c.pdf', 'h3', 't3', 'nomic-embed-text:latest');",
+      )
+      .unwrap();
+    let other = files_embedded_with_other_models(&conn, "nomic-embed-text");
+    assert_eq!(other, HashSet::from(["b.pdf".to_string()]));
+    assert_eq!(mark_other_model_files_stale(&conn, "nomic-embed-text").unwrap(), 1);
+    let hash: String = conn.query_row("SELECT hash FROM files WHERE path='c.pdf'", [], |r| r.get(0)).unwrap();
+    assert_eq!(hash, "h3");
+    let hash: String = conn.query_row("SELECT hash FROM files WHERE path='b.pdf'", [], |r| r.get(0)).unwrap();
+    assert_eq!(hash, "");
+  }
+
+  #[test]
+  fn keys_only_under_keeps_files_other_targets_cover() {
+    let folder = |path: &str, include_subfolders: bool| IndexTarget {
+      path: path.to_string(),
+      kind: IndexTargetKind::Folder,
+      include_subfolders,
+      exclude_paths: vec![],
+      exclude_globs: vec![],
+      default_ignores: true,
+    };
+    let removed = folder("/docs", true);
+    let remaining = vec![folder("/docs/keep", false)];
+    let keys = vec![
+      "/docs/a.pdf".to_string(),
+      "/docs/sub/b.zip!inner/c.txt".to_string(),
+      "/docs/keep/d.pdf".to_string(),
+      "/other/e.pdf".to_string(),
+    ];
+    assert_eq!(
+      keys_only_under(keys, &removed, &remaining),
+      vec!["/docs/a.pdf".to_string(), "/docs/sub/b.zip!inner/c.txt".to_string()]
+    );
+  }
+
+  #[test]
+  fn delete_file_rows_removes_only_that_file() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE files(path TEXT PRIMARY KEY);
+         CREATE TABLE chunks(id INTEGER PRIMARY KEY, file_path TEXT);
+         CREATE TABLE vec_chunks(embedding BLOB);
+         INSERT INTO files(path) VALUES('a.pdf'), ('b.pdf');
+         INSERT INTO chunks(id, file_path) VALUES(1, 'a.pdf'), (2, 'b.pdf');
+         INSERT INTO vec_chunks(rowid, embedding) VALUES(1, x''), (2, x'');",
+      )
+      .unwrap();
+    assert_eq!(delete_file_rows(&conn, "a.pdf").unwrap(), 1);
+    assert_eq!(delete_file_rows(&conn, "missing.pdf").unwrap(), 0);
+    let chunks: i64 = conn.query_row("SELECT COUNT(*) FROM chunks", [], |r| r.get(0)).unwrap();
+    let vecs: i64 = conn.query_row("SELECT COUNT(*) FROM vec_chunks", [], |r| r.get(0)).unwrap();
+    assert_eq!((chunks, vecs), (1, 1));
+  }
+
+  #[test]
+  fn wipe_index_drops_document_metadata() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE meta(key TEXT PRIMARY KEY, value TEXT);
+         CREATE TABLE files(path TEXT PRIMARY KEY);
+         CREATE TABLE chunks(id INTEGER PRIMARY KEY, file_path TEXT);
+         CREATE TABLE doc_meta(path TEXT PRIMARY KEY, title TEXT, author TEXT);
+         INSERT INTO files(path) VALUES('a.pdf'), ('b.pdf');
+         INSERT INTO chunks(id, file_path) VALUES(1, 'a.pdf');
+         INSERT INTO doc_meta(path, title) VALUES('a.pdf', 'Stale title');",
+      )
+      .unwrap();
+    assert_eq!(wipe_index(&conn).unwrap(), 2);
+    let left: i64 = conn
+      .query_row("SELECT (SELECT COUNT(*) FROM files) + (SELECT COUNT(*) FROM chunks) + (SELECT COUNT(*) FROM doc_meta)", [], |r| r.get(0))
+      .unwrap();
+    assert_eq!(left, 0);
+  }
+
+  #[test]
+  fn document_titles_come_from_markdown_and_docx_metadata() {
+    assert_eq!(markdown_title("```\n# not this\n```\nintro\n#  Umowa najmu ##\n# Second"), Some("Umowa najmu".into()));
+    assert_eq!(markdown_title("## Only a subheading"), None);
+    let core = r#"<cp:coreProperties xmlns:dc="http://purl.org/dc/elements/1.1/">
+      <dc:title>Raport &amp; plan</dc:title><dc:creator>Jan Kowalski</dc:creator></cp:coreProperties>"#;
+    let meta = docx_core_props(core).unwrap();
+    assert_eq!(meta, DocMeta { title: Some("Raport & plan".into()), author: Some("Jan Kowalski".into()) });
+  }
+
+  #[test]
+  fn fill_doc_meta_falls_back_to_file_name() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE doc_meta(path TEXT PRIMARY KEY, title TEXT, author TEXT);
+         INSERT INTO doc_meta(path, title, author) VALUES('/docs/a.pdf', 'Annual report', 'Ada');",
+      )
+      .unwrap();
+    let source = |file_path: &str| Source {
+      id: 1,
+      file_path: file_path.into(),
+      page: 0,
+      chunk_index: 0,
+      snippet: String::new(),
+      distance: 0.0,
+      origin: None,
+      highlight: None,
+      title: None,
+      author: None,
+    };
+    let mut sources = vec![source("/docs/a.pdf"), source("/docs/b.docx"), source("/docs/c.zip!inner/d.txt")];
+    fill_doc_meta(&conn, &mut sources);
+    let got: Vec<_> = sources.iter().map(|s| (s.title.as_deref(), s.author.as_deref())).collect();
+    assert_eq!(got, vec![(Some("Annual report"), Some("Ada")), (Some("b.docx"), None), (Some("d.txt"), None)]);
+  }
+
+  #[test]
+  fn changed_preview_paths_keeps_new_and_changed_files() {
+    let item = |path: &str, status: &str| IndexFilePreview {
+      path: path.into(),
+      kind: "txt".into(),
+      status: status.into(),
+      size: 1,
+      mtime: 0,
+      indexed_at: None,
+    };
+    let preview = vec![
+      item("/d/a.txt", "indexed"),
+      item("/d/b.txt", "changed"),
+      item("/d/c.zip!x.txt", "new"),
+      item("/d/c.zip!y.txt", "changed"),
+      item("/d/d.txt", "missing"),
+      item("/d/e.txt", "too_large"),
+      item("/d/f.txt", "new"),
+    ];
+    assert_eq!(changed_preview_paths(&preview), vec!["/d/b.txt", "/d/c.zip", "/d/f.txt"]);
+  }
+
+  #[test]
+  fn indexed_files_counts_chunks_per_file() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE files(path TEXT PRIMARY KEY, kind TEXT, hash TEXT, size INTEGER, mtime INTEGER, indexed_at INTEGER);
+         CREATE TABLE chunks(id INTEGER PRIMARY KEY, file_path TEXT);
+         INSERT INTO files VALUES('b.pdf', 'pdf', 'h', 10, 1, 100), ('a.txt', 'txt', 'h', 5, 2, NULL);
+         INSERT INTO chunks(file_path) VALUES('b.pdf'), ('b.pdf');",
+      )
+      .unwrap();
+    let files = indexed_files(&conn).unwrap();
+    let got: Vec<_> = files.iter().map(|f| (f.path.as_str(), f.indexed_at, f.chunks)).collect();
+    assert_eq!(got, vec![("a.txt", None, 0), ("b.pdf", Some(100), 2)]);
+  }
+
+  #[test]
+  fn embedding_probe_flags_degenerate_vectors() {
+    let ok = embedding_probe(&[3.0, 4.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+    assert_eq!((ok.dim, ok.norm, ok.sample.len(), ok.degenerate), (9, 26f64.sqrt(), 8, false));
+    assert!(embedding_probe(&[0.0; 4]).degenerate);
+    assert!(embedding_probe(&[1.0, f32::NAN]).degenerate);
+    assert!(embedding_probe(&[f32::INFINITY]).degenerate);
+    assert!(embedding_probe(&[]).degenerate);
+  }
+
+  #[test]
+  fn move_db_files_moves_existing_sidecars() {
+    let dir = std::env::temp_dir().join(format!("lfc-dbmove-{}", std::process::id()));
+    let (from, to) = (dir.join("a"), dir.join("b"));
+    fs::create_dir_all(&from).unwrap();
+    fs::create_dir_all(&to).unwrap();
+    fs::write(from.join(DB_NAME), b"db").unwrap();
+    fs::write(from.join(format!("{DB_NAME}-wal")), b"wal").unwrap();
+    move_db_files(&from.join(DB_NAME), &to.join(DB_NAME)).unwrap();
+    let moved = (to.join(DB_NAME).is_file(), to.join(format!("{DB_NAME}-wal")).is_file(), from.join(DB_NAME).exists());
+    let missing = move_db_files(&dir.join("gone").join(DB_NAME), &to.join("x")).is_ok();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(moved, (true, true, false));
+    assert!(missing);
+  }
+
+  #[test]
+  fn load_vectors_reads_stored_embeddings() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch("CREATE TABLE vec_chunks(embedding BLOB);").unwrap();
+    conn
+      .execute("INSERT INTO vec_chunks(rowid, embedding) VALUES(7, ?1)", params![f32_blob(&[0.25, -1.0])])
+      .unwrap();
+    let got = load_vectors(&conn, &[7, 8]).unwrap();
+    assert_eq!(got.len(), 1);
+    assert_eq!(got[&7], vec![0.25, -1.0]);
+  }
+
+  #[test]
+  fn embed_cache_round_trips_vectors() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_embed_cache(&conn).unwrap();
+    let v = vec![0.5f32, -1.25, 3.0e-7];
+    assert_eq!(blob_f32(&f32_blob(&v)), v);
+    embed_cache_put(&conn, "bge-m3", "tekst", &v).unwrap();
+    assert_eq!(embed_cache_get(&conn, "bge-m3", "tekst"), Some(v));
+    assert_eq!(embed_cache_get(&conn, "nomic-embed-text", "tekst"), None);
+    assert_eq!(embed_cache_get(&conn, "bge-m3", "inny"), None);
+  }
+
+  #[test]
+  fn embed_batch_end_respects_count_and_chars() {
+    let texts: Vec<String> = ["aaaa", "bbbb", "cccccccccc", "dd", "ee"].iter().map(|s| s.to_string()).collect();
+    assert_eq!(embed_batch_end(&texts, 0, 4, 9), 2);
+    assert_eq!(embed_batch_end(&texts, 2, 4, 9), 3);
+    assert_eq!(embed_batch_end(&texts, 3, 4, 9), 5);
+    assert_eq!(embed_batch_end(&texts, 0, 1, 100), 1);
+  }
+
+  #[test]
+  fn fts_search_ranks_keyword_hits() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE meta(key TEXT PRIMARY KEY, value TEXT);
+         CREATE TABLE chunks(id INTEGER PRIMARY KEY, file_path TEXT, page INTEGER, chunk_index INTEGER, lang TEXT, text TEXT, origin TEXT);",
+      )
+      .unwrap();
+    assert!(fts_search(&conn, "umowa", 10).unwrap().is_empty());
+    create_fts_table(&conn, FtsTokenizer::Unicode61).unwrap();
+    let texts = ["Umowa najmu lokalu, umowa zawarta w Łodzi", "Faktura za prąd", "Aneks do umowy"];
+    for (i, text) in texts.iter().enumerate() {
+      let id = i as i64 + 1;
+      conn
+        .execute(
+          "INSERT INTO chunks(id, file_path, page, chunk_index, text, origin) VALUES(?1, 'a.pdf', ?2, 0, ?3, 'native')",
+          params![id, i as i64, text],
+        )
+        .unwrap();
+      conn.execute("INSERT INTO chunks_fts(rowid, text) VALUES(?1, ?2)", params![id, fold_for_fts(text)]).unwrap();
+    }
+    let hits = fts_search(&conn, "umow", 10).unwrap();
+    let ids: Vec<i64> = hits.iter().map(|s| s.id).collect();
+    assert_eq!(ids, vec![1, 3]);
+    assert_eq!(hits[0].origin.as_deref(), Some("native"));
+    assert_eq!(fts_search(&conn, "lodzi", 1).unwrap()[0].page, 0);
+    assert!(fts_search(&conn, "?!", 10).unwrap().is_empty());
+  }
+
+  #[test]
+  fn keyword_highlight_centers_on_matches() {
+    let terms = highlight_terms("umow* lodz*");
+    let h = keyword_highlight("Aneks do umowy najmu lokalu w Łodzi.", &terms, None).unwrap();
+    let marked: Vec<String> = h.ranges.iter().map(|[s, e]| h.text.chars().skip(*s).take(e - s).collect()).collect();
+    assert_eq!(marked, vec!["umowy", "Łodzi"]);
+
+    let long = format!("{} Faktura numer 7 {}", "wstęp ".repeat(40), "koniec ".repeat(40));
+    let h = keyword_highlight(&long, &highlight_terms("faktur*"), Some(60)).unwrap();
+    assert!(h.text.starts_with('…') && h.text.ends_with('…'));
+    assert!(h.text.chars().count() <= 62);
+    let [s, e] = h.ranges[0];
+    assert_eq!(h.text.chars().skip(s).take(e - s).collect::<String>(), "Faktura");
+
+    // The window comes from the full text, so a match past the snippet limit still shows.
+    let (snippet, h) = snippet_with_highlight(&long, &highlight_terms("faktur*"), Some(60));
+    assert_eq!(snippet, h.unwrap().text);
+    assert!(snippet.contains("Faktura"));
+    assert_eq!(snippet_with_highlight(&long, &[], Some(60)).0, truncate_snippet(&long, Some(60)));
+
+    assert_eq!(keyword_highlight("nothing here", &terms, None), None);
+    let trigram = keyword_highlight("Przedumowa", &highlight_terms("umowa"), None).unwrap();
+    assert_eq!(trigram.ranges, vec![[0, 10]]);
+  }
+
+  #[test]
+  fn fts_matches_without_diacritics() {
+    let conn = Connection::open_in_memory().unwrap();
+    create_fts_table(&conn, FtsTokenizer::Unicode61).unwrap();
+    conn
+      .execute("INSERT INTO chunks_fts(rowid, text) VALUES(1, ?1)", params![fold_for_fts("Zażółć gęślą jaźń, łódź")])
+      .unwrap();
+    let hits = |q: &str| -> i64 {
+      conn
+        .query_row("SELECT COUNT(*) FROM chunks_fts WHERE chunks_fts MATCH ?1", params![fold_for_fts(q)], |r| r.get(0))
+        .unwrap()
+    };
+    assert_eq!(hits("zazolc*"), 1);
+    assert_eq!(hits("łódź"), 1);
+    assert_eq!(hits("lodz"), 1);
+    conn.execute("DELETE FROM chunks_fts WHERE rowid=1", []).unwrap();
+    assert_eq!(hits("lodz"), 0);
+  }
+
+  #[test]
+  fn trigram_fts_query_drops_prefix_markers_and_short_terms() {
+    assert_eq!(trigram_fts_query("ok* umowa* najmu*"), Some("umowa najmu".to_string()));
+    assert_eq!(trigram_fts_query("ok* hi*"), None);
+    let conn = Connection::open_in_memory().unwrap();
+    create_fts_table(&conn, FtsTokenizer::Trigram).unwrap();
+    conn.execute("INSERT INTO chunks_fts(rowid, text) VALUES(1, 'wypowiedzenie umowy')", []).unwrap();
+    let hits: i64 = conn
+      .query_row("SELECT COUNT(*) FROM chunks_fts WHERE chunks_fts MATCH 'powiedz'", [], |r| r.get(0))
+      .unwrap();
+    assert_eq!(hits, 1);
+  }
+
+  #[test]
+  fn bump_index_version_counts_up_from_one() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch("CREATE TABLE meta(key TEXT PRIMARY KEY, value TEXT);").unwrap();
+    bump_index_version(&conn).unwrap();
+    bump_index_version(&conn).unwrap();
+    assert_eq!(meta_value(&conn, "index_version").as_deref(), Some("2"));
+  }
+
+  #[test]
+  fn remap_path_matches_whole_components() {
+    assert_eq!(remap_path("/old/docs/a.pdf", "/old/docs", "/new/docs"), Some("/new/docs/a.pdf".into()));
+    assert_eq!(remap_path("/old/docs", "/old/docs", "D:\\docs"), Some("D:\\docs".into()));
+    assert_eq!(remap_path("/old/docs.zip!x/a.txt", "/old/docs.zip", "/n.zip"), Some("/n.zip!x/a.txt".into()));
+    assert_eq!(remap_path("/old/docs2/a.pdf", "/old/docs", "/new"), None);
+    assert_eq!(remap_path("C:\\Docs\\a.pdf", "C:\\Docs", "E:\\Docs"), Some("E:\\Docs\\a.pdf".into()));
+  }
+
   #[test]
   fn build_fts_query_filters_short_tokens() {
-    assert_eq!(build_fts_query("ok hi abc"), Some("ok* hi* abc*".to_string()));
+    assert_eq!(build_fts_query("ok hi abc", FtsQueryOptions::default()), Some("ok* hi* abc*".to_string()));
   }
 }
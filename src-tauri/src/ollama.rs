@@ -3,13 +3,16 @@ use reqwest::blocking::Client;
 use reqwest::{Client as AsyncClient, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fmt;
 use std::io::BufRead;
 use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
 use std::time::Duration;
 
 const DEFAULT_OLLAMA_BASE: &str = "http://127.0.0.1:11434/api";
 const DEFAULT_OLLAMA_TIMEOUT_SECS: u64 = 300;
+const OLLAMA_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 const MAX_ERROR_BODY_BYTES: usize = 4096;
 const DEFAULT_OLLAMA_CLOUD_BASE: &str = "https://ollama.com";
 const DEFAULT_OLLAMA_CLOUD_TIMEOUT_SECS: u64 = 10;
@@ -36,6 +39,35 @@ impl fmt::Display for OllamaHttpError {
 
 impl std::error::Error for OllamaHttpError {}
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum OllamaErrorKind {
+  /// The requested model has not been pulled.
+  ModelNotFound,
+  /// Nothing is listening at the configured host.
+  Unreachable,
+  Other,
+}
+
+pub fn classify_ollama_error(err: &anyhow::Error) -> OllamaErrorKind {
+  if let Some(http_err) = err.downcast_ref::<OllamaHttpError>() {
+    return classify_http_error(http_err.status, &http_err.body);
+  }
+  match err.downcast_ref::<reqwest::Error>() {
+    Some(e) if e.is_connect() => OllamaErrorKind::Unreachable,
+    _ => OllamaErrorKind::Other,
+  }
+}
+
+fn classify_http_error(status: StatusCode, body: &str) -> OllamaErrorKind {
+  let body = body.to_lowercase();
+  // Ollama answers 404 `model "x" not found, try pulling it first`; older builds used 400/500.
+  if status == StatusCode::NOT_FOUND || (body.contains("model") && body.contains("not found")) {
+    OllamaErrorKind::ModelNotFound
+  } else {
+    OllamaErrorKind::Other
+  }
+}
+
 fn truncate_body(body: &str) -> String {
   if body.len() <= MAX_ERROR_BODY_BYTES {
     return body.to_string();
@@ -55,26 +87,64 @@ fn truncate_body(body: &str) -> String {
 pub struct Ollama {
   http: Client,
   base: String,
+  truncate: bool,
+  chat_options: Option<Value>,
+  embed_options: Option<Value>,
 }
 
 impl Ollama {
   pub fn new() -> Self {
+    Self::with_config(ollama_base_url(), ollama_timeout())
+  }
+
+  /// Client for `base` whose requests give up after `timeout`; connecting gives up sooner, so an
+  /// Ollama that is down fails fast while a slow embed of long chunks still gets the full budget.
+  pub fn with_config(base: String, timeout: Duration) -> Self {
     let http = Client::builder()
-      .timeout(ollama_timeout())
+      .connect_timeout(OLLAMA_CONNECT_TIMEOUT.min(timeout))
+      .timeout(timeout)
       .no_proxy()
       .build()
       .unwrap_or_else(|_| Client::new());
     Self {
       http,
-      base: ollama_base_url(),
+      base,
+      truncate: true,
+      chat_options: None,
+      embed_options: None,
     }
   }
 
+  /// Client for an explicit endpoint (e.g. `192.168.1.20:11434`); scheme and `/api` are added as needed.
+  pub fn with_base_url(base: String) -> Self {
+    Self::with_config(normalize_ollama_base(&base), ollama_timeout())
+  }
+
+  /// With truncation off, Ollama rejects inputs longer than the model context instead of cutting them.
+  pub fn with_truncate(mut self, truncate: bool) -> Self {
+    self.truncate = truncate;
+    self
+  }
+
+  /// Extra `options` sent with every chat request from this client, e.g. `temperature` (0 for
+  /// repeatable answers), `seed`, `num_ctx` (context window in tokens), `top_p`, `num_predict`.
+  pub fn with_chat_options(mut self, options: Option<Value>) -> Self {
+    self.chat_options = options;
+    self
+  }
+
+  /// `options` for embed requests; mostly `num_ctx` to embed longer chunks than the model default.
+  pub fn with_embed_options(mut self, options: Option<Value>) -> Self {
+    self.embed_options = options;
+    self
+  }
+
   pub fn embed(&self, model: &str, input: impl Into<EmbedInput>) -> Result<Vec<Vec<f32>>> {
     let req = EmbedRequest {
       model: model.to_string(),
       input: input.into(),
-      truncate: Some(true),
+      truncate: Some(self.truncate),
+      options: self.embed_options.clone(),
     };
 
     // /api/embed: input może być string albo array stringów 
@@ -98,6 +168,7 @@ impl Ollama {
       model: model.to_string(),
       messages,
       stream: Some(false), // streaming off = prościej do obsługi 
+      options: self.chat_options.clone(),
     };
 
     // /api/chat 
@@ -127,6 +198,7 @@ impl Ollama {
       model: model.to_string(),
       messages,
       stream: Some(true),
+      options: self.chat_options.clone(),
     };
 
     let resp = self
@@ -177,6 +249,78 @@ impl Ollama {
     Ok(answer)
   }
 
+  fn show(&self, model: &str) -> Result<Value> {
+    let resp = self
+      .http
+      .post(format!("{}/show", self.base))
+      .json(&serde_json::json!({ "model": model }))
+      .send()?;
+    let status = resp.status();
+    if !status.is_success() {
+      let body = truncate_body(&resp.text().unwrap_or_default());
+      return Err(anyhow!(OllamaHttpError { status, body }));
+    }
+    Ok(resp.json()?)
+  }
+
+  /// Context window in tokens, preferring an explicit `num_ctx` over the architecture maximum.
+  pub fn context_length(&self, model: &str) -> Result<Option<u64>> {
+    Ok(extract_context_length(&self.show(model)?))
+  }
+
+  /// Installed models with `/api/show` metadata, limited to `only` when given. Show results are
+  /// cached per model digest, so repeated calls only hit Ollama for newly pulled models.
+  pub fn list_model_details(&self, only: Option<&[String]>) -> Result<Vec<ModelDetails>> {
+    // (base, name@digest) -> (context_length, embedding_length)
+    type ShowCache = HashMap<(String, String), (Option<u64>, Option<u64>)>;
+    static SHOW_CACHE: OnceLock<Mutex<ShowCache>> = OnceLock::new();
+    let cache = SHOW_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+
+    let resp = self
+      .http
+      .get(format!("{}/tags", self.base))
+      .send()?;
+    let status = resp.status();
+    if !status.is_success() {
+      let body = truncate_body(&resp.text().unwrap_or_default());
+      return Err(anyhow!(OllamaHttpError { status, body }));
+    }
+    let resp: TagsResponse = resp.json()?;
+
+    let mut out = Vec::new();
+    for m in resp.models {
+      if only.is_some_and(|names| !names.iter().any(|n| n == &m.name)) {
+        continue;
+      }
+      let key = (self.base.clone(), format!("{}@{}", m.name, m.digest.as_deref().unwrap_or("")));
+      let cached = cache.lock().ok().and_then(|c| c.get(&key).copied());
+      let (context_length, embedding_length) = match cached {
+        Some(lengths) => lengths,
+        None => match self.show(&m.name) {
+          Ok(raw) => {
+            let lengths = (extract_context_length(&raw), extract_embedding_length(&raw));
+            if let Ok(mut c) = cache.lock() {
+              c.insert(key, lengths);
+            }
+            lengths
+          }
+          // Metadata is best-effort; a model that fails to load still gets listed.
+          Err(_) => (None, None),
+        },
+      };
+      let details = m.details.unwrap_or_default();
+      out.push(ModelDetails {
+        name: m.name,
+        size: m.size,
+        family: details.family,
+        parameter_size: details.parameter_size,
+        context_length,
+        embedding_length,
+      });
+    }
+    Ok(out)
+  }
+
   pub fn list_models(&self) -> Result<Vec<String>> {
     let resp = self
       .http
@@ -237,6 +381,47 @@ impl Ollama {
   }
 }
 
+fn extract_context_length(raw: &Value) -> Option<u64> {
+  let num_ctx = raw
+    .get("parameters")
+    .and_then(|v| v.as_str())
+    .and_then(|params| {
+      params.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+          (Some("num_ctx"), Some(value)) => value.parse::<u64>().ok(),
+          _ => None,
+        }
+      })
+    });
+  num_ctx.or_else(|| {
+    raw
+      .get("model_info")
+      .and_then(|v| v.as_object())
+      .and_then(|info| info.iter().find(|(k, _)| k.ends_with(".context_length")))
+      .and_then(|(_, v)| v.as_u64())
+  })
+}
+
+/// Output width of an embedding model; `None` for models that do not report one.
+fn extract_embedding_length(raw: &Value) -> Option<u64> {
+  raw
+    .get("model_info")
+    .and_then(|v| v.as_object())
+    .and_then(|info| info.iter().find(|(k, _)| k.ends_with(".embedding_length")))
+    .and_then(|(_, v)| v.as_u64())
+}
+
+#[derive(Serialize)]
+pub struct ModelDetails {
+  pub name: String,
+  pub size: Option<u64>,
+  pub family: Option<String>,
+  pub parameter_size: Option<String>,
+  pub context_length: Option<u64>,
+  pub embedding_length: Option<u64>,
+}
+
 #[derive(Serialize)]
 pub struct OllamaRuntimeModel {
   pub name: String,
@@ -259,6 +444,20 @@ struct TagsResponse {
 #[derive(Deserialize)]
 struct ModelInfo {
   name: String,
+  #[serde(default)]
+  size: Option<u64>,
+  #[serde(default)]
+  digest: Option<String>,
+  #[serde(default)]
+  details: Option<TagDetails>,
+}
+
+#[derive(Deserialize, Default)]
+struct TagDetails {
+  #[serde(default)]
+  family: Option<String>,
+  #[serde(default)]
+  parameter_size: Option<String>,
 }
 
 pub async fn list_models_with_timeout(timeout: Duration) -> Result<Vec<String>> {
@@ -277,6 +476,22 @@ pub async fn list_models_with_timeout(timeout: Duration) -> Result<Vec<String>>
   Ok(data.models.into_iter().map(|m| m.name).collect())
 }
 
+pub async fn version_with_timeout(timeout: Duration) -> Result<String> {
+  let client = AsyncClient::builder().timeout(timeout).no_proxy().build()?;
+  let base = ollama_base_url();
+  let resp = client
+    .get(format!("{base}/version"))
+    .send()
+    .await?;
+  let status = resp.status();
+  if !status.is_success() {
+    let body = truncate_body(&resp.text().await.unwrap_or_default());
+    return Err(anyhow!(OllamaHttpError { status, body }));
+  }
+  let raw: Value = resp.json().await?;
+  Ok(raw.get("version").and_then(|v| v.as_str()).unwrap_or("unknown").to_string())
+}
+
 pub fn list_cloud_models() -> Result<Vec<String>> {
   let Some(token) = load_cloud_token() else {
     return Ok(Vec::new());
@@ -330,6 +545,8 @@ fn normalize_ollama_base(raw: &str) -> String {
   }
 }
 
+/// Per-request limit from `OLLAMA_TIMEOUT_SECS`. Hitting it makes embedding retry with smaller
+/// batches; raise it if very long chunks on a slow machine still time out on their own.
 fn ollama_timeout() -> Duration {
   let seconds = std::env::var("OLLAMA_TIMEOUT_SECS")
     .ok()
@@ -608,6 +825,8 @@ struct EmbedRequest {
   input: EmbedInput,
   #[serde(skip_serializing_if = "Option::is_none")]
   truncate: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  options: Option<Value>,
 }
 
 #[derive(Deserialize)]
@@ -627,6 +846,8 @@ struct ChatRequest {
   messages: Vec<ChatMessage>,
   #[serde(skip_serializing_if = "Option::is_none")]
   stream: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  options: Option<Value>,
 }
 
 #[derive(Deserialize)]
@@ -661,6 +882,46 @@ mod tests {
     );
   }
 
+  #[test]
+  fn classify_http_error_detects_missing_models() {
+    let body = r#"{"error":"model \"bge-m3\" not found, try pulling it first"}"#;
+    assert_eq!(classify_http_error(StatusCode::NOT_FOUND, body), OllamaErrorKind::ModelNotFound);
+    assert_eq!(classify_http_error(StatusCode::BAD_REQUEST, body), OllamaErrorKind::ModelNotFound);
+    assert_eq!(classify_http_error(StatusCode::INTERNAL_SERVER_ERROR, "boom"), OllamaErrorKind::Other);
+  }
+
+  #[test]
+  fn with_base_url_normalizes_trailing_slashes() {
+    assert_eq!(Ollama::with_base_url("http://10.0.0.5:11434//".into()).base, "http://10.0.0.5:11434/api");
+  }
+
+  #[test]
+  fn extract_context_length_prefers_num_ctx() {
+    let raw = serde_json::json!({
+      "parameters": "num_ctx                        8192\nstop \"<eos>\"",
+      "model_info": { "bert.context_length": 32768 }
+    });
+    assert_eq!(extract_context_length(&raw), Some(8192));
+    let raw = serde_json::json!({ "model_info": { "qwen3.context_length": 32768 } });
+    assert_eq!(extract_context_length(&raw), Some(32768));
+    assert_eq!(extract_context_length(&serde_json::json!({})), None);
+  }
+
+  #[test]
+  fn embed_request_sends_options_only_when_set() {
+    let req = |options| EmbedRequest { model: "m".into(), input: "x".into(), truncate: Some(true), options };
+    assert!(serde_json::to_value(req(None)).unwrap().get("options").is_none());
+    let json = serde_json::to_value(req(Some(serde_json::json!({ "num_ctx": 8192 })))).unwrap();
+    assert_eq!(json["options"]["num_ctx"], 8192);
+  }
+
+  #[test]
+  fn extract_embedding_length_reads_model_info() {
+    let raw = serde_json::json!({ "model_info": { "nomic-bert.embedding_length": 768, "nomic-bert.context_length": 2048 } });
+    assert_eq!(extract_embedding_length(&raw), Some(768));
+    assert_eq!(extract_embedding_length(&serde_json::json!({ "model_info": {} })), None);
+  }
+
   #[test]
   fn normalize_ollama_base_handles_trailing_slash_and_empty() {
     assert_eq!(
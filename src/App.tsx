@@ -2,13 +2,35 @@
 import { deriveTitle, formatSize, getMissingModels, isEmbeddingModel, loadJson, newId } from "./App.helpers";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
-import { open as openDialog } from "@tauri-apps/plugin-dialog";
+import { ask, open as openDialog } from "@tauri-apps/plugin-dialog";
 import { openPath, revealItemInDir } from "@tauri-apps/plugin-opener";
 import "./App.css";
 
-type SourceHit = { file_path: string; page: number; snippet: string; distance: number };
-type ChatResponse = { answer: string; sources: SourceHit[] };
-type IndexProgress = { current: number; total: number; file: string; status: string };
+type SnippetHighlight = { text: string; ranges: [number, number][] };
+type SourceHit = {
+  file_path: string;
+  page: number;
+  snippet: string;
+  distance: number;
+  origin?: "native" | "ocr" | null;
+  highlight?: SnippetHighlight | null;
+  title?: string | null;
+  author?: string | null;
+};
+type ChatResponse = { answer: string; sources: SourceHit[]; language?: string | null };
+type IndexProgress = {
+  current: number;
+  total: number;
+  file: string;
+  status: string;
+  chunk_current?: number;
+  chunk_total?: number;
+};
+type IndexErrorEvent = {
+  code: "ollama_unreachable" | "model_missing" | "vec_extension_missing" | "ocr_failed" | "extract_failed" | "other";
+  message: string;
+  file?: string | null;
+};
 type SetupStatus = { running: boolean; managed: boolean; models: string[]; defaultChat: string; defaultFast: string; defaultEmbed: string };
 type SetupProgress = { stage: string; message: string };
 type ModelPullProgress = { model: string; line: string };
@@ -24,14 +46,20 @@ type IndexTarget = {
   path: string;
   kind: "file" | "folder";
   includeSubfolders: boolean;
+  excludePaths?: string[];
+  excludeGlobs?: string[];
+  defaultIgnores?: boolean;
 };
 
+type DestructivePreview = { files: number; chunks: number };
+
 type IndexFilePreview = {
   path: string;
   kind: string;
   status: "new" | "indexed" | "changed" | "missing" | string;
   size: number;
   mtime: number;
+  indexed_at?: number | null;
 };
 
 type IndexSettings = {
@@ -76,6 +104,17 @@ const DEFAULT_RETRIEVAL_SETTINGS: RetrievalSettings = {
   mmrCandidates: 24,
 };
 
+function toTargetPayload({ path, kind, includeSubfolders, excludePaths, excludeGlobs, defaultIgnores }: IndexTarget) {
+  return {
+    path,
+    kind,
+    includeSubfolders,
+    excludePaths: excludePaths ?? [],
+    excludeGlobs: excludeGlobs ?? [],
+    defaultIgnores: defaultIgnores ?? true,
+  };
+}
+
 const STORAGE_KEYS = {
   lang: "ui.lang",
   theme: "ui.theme",
@@ -140,6 +179,7 @@ const copy = {
     fileLabel: "Plik",
     targetsEmpty: "Brak dodanych źródeł. Dodaj foldery lub pliki.",
     indexNow: "Indeksuj teraz",
+    scanChanges: "Skanuj zmiany",
     indexing: "Indeksowanie...",
     indexIdle: "Bezczynny",
     indexDone: "Indeks zakończony.",
@@ -230,13 +270,22 @@ const copy = {
     prev: "Poprzednia",
     next: "Następna",
     confirmDeleteChat: "Czy na pewno usunąć rozmowę?",
+    removeTargetTitle: "Usuń źródło",
+    confirmRemoveTarget: "Usunąć z indeksu {files} plików ({chunks} fragmentów)?",
     emptyChat: "Zacznij rozmowę, aby zobaczyć odpowiedzi z dokumentów.",
     indexStatus: {
       start: "Start",
       skip: "Pominięto",
       extract: "Ekstrakcja",
+      prune: "Porządkowanie indeksu",
+      embed: "Osadzanie",
       done: "Gotowe",
       missing: "Brak pliku",
+      loading_model: "Ładowanie modelu",
+      rebuild: "Przebudowa indeksu dla nowego modelu",
+      changed_during_index: "Zmieniony w trakcie indeksowania",
+      too_large: "Pominięto (za duży)",
+      ready: "Model gotowy",
     },
     fileStatus: {
       new: "Nowe",
@@ -244,6 +293,7 @@ const copy = {
       changed: "Do aktualizacji",
       missing: "Brak pliku",
       error: "Błąd",
+      too_large: "Za duży",
     },
   },
   en: {
@@ -298,6 +348,7 @@ const copy = {
     fileLabel: "File",
     targetsEmpty: "No sources yet. Add folders or files.",
     indexNow: "Index now",
+    scanChanges: "Scan for changes",
     indexing: "Indexing...",
     indexIdle: "Idle",
     indexDone: "Index complete.",
@@ -388,13 +439,22 @@ const copy = {
     prev: "Previous",
     next: "Next",
     confirmDeleteChat: "Delete this chat?",
+    removeTargetTitle: "Remove source",
+    confirmRemoveTarget: "Remove {files} indexed files ({chunks} chunks) from the index?",
     emptyChat: "Start chatting to see answers from your documents.",
     indexStatus: {
       start: "Start",
       skip: "Skipped",
       extract: "Extracting",
+      prune: "Pruning index",
+      embed: "Embedding",
       done: "Done",
       missing: "Missing",
+      loading_model: "Loading model",
+      rebuild: "Rebuilding index for new model",
+      changed_during_index: "Changed during indexing",
+      too_large: "Skipped (too large)",
+      ready: "Model ready",
     },
     fileStatus: {
       new: "New",
@@ -402,11 +462,12 @@ const copy = {
       changed: "Needs update",
       missing: "Missing",
       error: "Error",
+      too_large: "Too large",
     },
   },
 } as const;
 
-const SUPPORTED_EXTS = ["pdf", "txt", "md", "markdown", "docx"];
+const SUPPORTED_EXTS = ["pdf", "txt", "md", "markdown", "docx", "html", "htm", "epub", "rtf", "pptx", "csv", "xlsx", "rs", "py", "js", "ts", "go", "java", "c", "cpp"];
 const DEFAULT_CHAT_MODEL = "llama3.1:8b";
 const DEFAULT_FAST_CHAT_MODEL = "llama3.2:3b";
 const DEFAULT_EMBED_MODEL = "qwen3-embedding";
@@ -423,6 +484,20 @@ function extractOllamaError(err: unknown) {
   return raw.slice(idx);
 }
 
+function renderHighlight({ text, ranges }: SnippetHighlight) {
+  // Ranges are code point offsets, so slice the code point array rather than the UTF-16 string.
+  const chars = Array.from(text);
+  const parts: ReactNode[] = [];
+  let pos = 0;
+  ranges.forEach(([start, end], i) => {
+    if (start > pos) parts.push(chars.slice(pos, start).join(""));
+    parts.push(<mark key={i}>{chars.slice(start, end).join("")}</mark>);
+    pos = end;
+  });
+  if (pos < chars.length) parts.push(chars.slice(pos).join(""));
+  return parts;
+}
+
 type IconProps = {
   children: ReactNode;
   className?: string;
@@ -1114,11 +1189,7 @@ export default function App() {
   useEffect(() => {
     if (!targetsLoaded) return;
     const timer = setTimeout(() => {
-      const payload = targets.map(({ path, kind, includeSubfolders }) => ({
-        path,
-        kind,
-        includeSubfolders,
-      }));
+      const payload = targets.map(toTargetPayload);
       invoke("save_targets", { targets: payload }).catch(() => {});
     }, 250);
     return () => clearTimeout(timer);
@@ -1128,6 +1199,8 @@ export default function App() {
     let unlistenProgress: (() => void) | null = null;
     let unlistenDone: (() => void) | null = null;
     let unlistenError: (() => void) | null = null;
+    let unlistenRemoved: (() => void) | null = null;
+    let unlistenCancelled: (() => void) | null = null;
 
     listen<IndexProgress>("index_progress", (event) => {
       setIndexProgress(event.payload);
@@ -1147,23 +1220,41 @@ export default function App() {
       unlistenDone = unlisten;
     });
 
-    listen<string>("index_error", (event) => {
-      setIndexError(event.payload);
+    listen<IndexErrorEvent>("index_error", (event) => {
+      setIndexError(event.payload.message);
       setIndexProgress(null);
       setIndexing(false);
     }).then((unlisten) => {
       unlistenError = unlisten;
     });
 
+    listen<string>("index_removed", () => {
+      setPreviewVersion((v) => v + 1);
+    }).then((unlisten) => {
+      unlistenRemoved = unlisten;
+    });
+
+    listen<{ processed: number; total: number }>("index_cancelled", () => {
+      setIndexProgress(null);
+      setIndexing(false);
+      setPreviewVersion((v) => v + 1);
+    }).then((unlisten) => {
+      unlistenCancelled = unlisten;
+    });
+
     return () => {
       unlistenProgress?.();
       unlistenDone?.();
       unlistenError?.();
+      unlistenRemoved?.();
+      unlistenCancelled?.();
     };
   }, []);
 
   useEffect(() => {
     let unlistenDelta: (() => void) | null = null;
+    let unlistenSources: (() => void) | null = null;
+    let unlistenWarning: (() => void) | null = null;
     listen<string>("chat_delta", (event) => {
       const sessionId = streamSessionRef.current;
       if (!sessionId) return;
@@ -1173,8 +1264,22 @@ export default function App() {
     }).then((unlisten) => {
       unlistenDelta = unlisten;
     });
+    listen<SourceHit[]>("chat_sources", (event) => {
+      const sessionId = streamSessionRef.current;
+      if (!sessionId) return;
+      updateLastAssistant(sessionId, (m) => ({ ...m, sources: event.payload }));
+    }).then((unlisten) => {
+      unlistenSources = unlisten;
+    });
+    listen<string>("chat_warning", (event) => {
+      setChatError(event.payload);
+    }).then((unlisten) => {
+      unlistenWarning = unlisten;
+    });
     return () => {
       unlistenDelta?.();
+      unlistenSources?.();
+      unlistenWarning?.();
     };
   }, []);
 
@@ -1253,11 +1358,7 @@ export default function App() {
     const timer = setTimeout(async () => {
       setPreviewBusy(true);
       try {
-        const payload = targets.map(({ path, kind, includeSubfolders }) => ({
-          path,
-          kind,
-          includeSubfolders,
-        }));
+        const payload = targets.map(toTargetPayload);
         const res = (await invoke("preview_index", { targets: payload })) as IndexFilePreview[];
         if (active) {
           setPreviewFiles(res);
@@ -1338,7 +1439,11 @@ export default function App() {
   const progressStatus = indexProgress
     ? t.indexStatus[indexProgress.status as keyof typeof t.indexStatus] ?? indexProgress.status
     : "";
-  const progressCount = indexProgress ? `${indexProgress.current}/${indexProgress.total}` : "";
+  const progressCount = !indexProgress
+    ? ""
+    : indexProgress.chunk_total
+      ? `${indexProgress.current}/${indexProgress.total} · ${indexProgress.chunk_current ?? 0}/${indexProgress.chunk_total}`
+      : `${indexProgress.current}/${indexProgress.total}`;
   const progressClass = !indexProgress
     ? "neutral"
     : indexProgress.status === "done"
@@ -1460,19 +1565,27 @@ export default function App() {
     });
   }
 
-  function removeTarget(id: string) {
-    setTargets((prev) => {
-      const next = prev.filter((t) => t.id !== id);
-      if (targetsLoaded) {
-        const payload = next.map(({ path, kind, includeSubfolders }) => ({
-          path,
-          kind,
-          includeSubfolders,
-        }));
-        invoke("prune_index", { targets: payload }).catch(() => {});
+  async function removeTarget(id: string) {
+    const target = targets.find((t) => t.id === id);
+    if (!target) return;
+    if (targetsLoaded) {
+      const { path, kind } = target;
+      try {
+        const preview = await invoke<DestructivePreview>("preview_destructive", {
+          action: { kind: "removeTarget", path, target_kind: kind },
+        });
+        if (preview.files > 0) {
+          const message = t.confirmRemoveTarget
+            .replace("{files}", String(preview.files))
+            .replace("{chunks}", String(preview.chunks));
+          if (!(await ask(message, { title: t.removeTargetTitle, kind: "warning" }))) return;
+        }
+        await invoke("remove_target", { path, kind, confirm: true });
+      } catch {
+        return;
       }
-      return next;
-    });
+    }
+    setTargets((prev) => prev.filter((t) => t.id !== id));
   }
 
   function toggleSubfolders(id: string, value: boolean) {
@@ -1527,11 +1640,7 @@ export default function App() {
     setIndexing(true);
     try {
       await syncOllamaHost();
-      const payload = targets.map(({ path, kind, includeSubfolders }) => ({
-        path,
-        kind,
-        includeSubfolders,
-      }));
+      const payload = targets.map(toTargetPayload);
       await invoke("start_index", {
         targets: payload,
         embedModel,
@@ -1543,6 +1652,23 @@ export default function App() {
     }
   }
 
+  async function scanTargets() {
+    if (!embedModel || targets.length === 0 || !hasIndexable) return;
+    setIndexError(null);
+    setIndexDone(false);
+    setIndexing(true);
+    try {
+      await syncOllamaHost();
+      await invoke("scan_targets", {
+        embedModel,
+        settings: indexSettings,
+      });
+    } catch (err) {
+      setIndexError(String(err));
+      setIndexing(false);
+    }
+  }
+
   async function reindexFile(path: string) {
     if (!embedModel) return;
     setIndexError(null);
@@ -1568,6 +1694,9 @@ export default function App() {
     if (!hasActive) {
       setActiveSessionId(sessionId);
     }
+    const history = (hasActive ? sessions.find((s) => s.id === sessionId)?.messages ?? [] : [])
+      .filter((m) => m.text.trim())
+      .map((m) => ({ role: m.role, content: m.text }));
     setQ("");
     setChatError(null);
     setChatStreaming(false);
@@ -1580,6 +1709,7 @@ export default function App() {
       await syncOllamaHost();
       const resp = (await invoke("chat_stream", {
         question: query,
+        history,
         llmModel: chatModel,
         embedModel,
         settings: retrievalSettings,
@@ -1932,10 +2062,10 @@ export default function App() {
                       <div className="source-card" key={`${s.file_path}-${idx}`}>
                         <div className="source-meta">
                           <span className="badge neutral">#{idx + 1}</span>
-                          <span className="source-path truncate" title={s.file_path}>{s.file_path}</span>
+                          <span className="source-path truncate" title={s.author ? `${s.file_path} | ${s.author}` : s.file_path}>{s.title || s.file_path}</span>
                           <span className="source-score">p. {s.page + 1} | {s.distance.toFixed(4)}</span>
                         </div>
-                        <div className="source-snippet">{s.snippet}</div>
+                        <div className="source-snippet">{s.highlight ? renderHighlight(s.highlight) : s.snippet}</div>
                         <div className="source-actions">
                           <button className="icon-button ghost icon-only" onClick={() => openPath(s.file_path)} title={t.openFile} aria-label={t.openFile}>
                             {Icons.file}
@@ -2036,6 +2166,16 @@ export default function App() {
                     </button>
                   </>
                 )}
+                <button
+                  className="icon-button ghost icon-only"
+                  onClick={scanTargets}
+                  disabled={!canIndex}
+                  aria-label={t.scanChanges}
+                  title={t.scanChanges}
+                >
+                  {Icons.refresh}
+                  <span className="label">{t.scanChanges}</span>
+                </button>
                 <button
                   className="icon-button primary icon-only"
                   onClick={doIndex}